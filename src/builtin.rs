@@ -1,21 +1,260 @@
 use std::{fs, io, thread};
-use std::process::{Command, exit, Stdio};
-use std::io::{ Read};
+use std::collections::HashMap;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::io::{IsTerminal, Read, Stdin, Write};
 use std::io::ErrorKind::NotFound;
-use std::path::Path;
-use std::sync::{Arc, RwLock};
-use std::thread::Thread;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{JoinHandle, Thread};
+use std::time::{Duration, Instant};
 
 
 use encoding_rs::*;
 use regex::Regex;
+use scanner_rust::{Scanner, ScannerError};
 use crate::context::{ Context, PipelineContextValue};
 use crate::engine::{PipelineEngine};
-use crate::error::PipelineResult;
-use crate::v1::types::{Dynamic, Value};
+use crate::error::{PipelineError, PipelineResult};
+use crate::v1::types::{Dynamic, Struct, Value};
 
 
-pub fn cmd(command:&str, ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>)->PipelineResult<Value>{
+/// cmd函数的可选参数，通过cmd的第二个参数(Map)传入
+#[derive(Debug,Clone,Default)]
+pub struct CmdOptions{
+    /// 为true时捕获stdout和stderr，而不是直接输出到控制台
+    pub capture:bool,
+    /// 为true时命令以非0状态码退出不会返回错误
+    pub allow_failure:bool,
+    /// 为true时命令不继承进程的环境变量，只使用通过env、loadEnv等设置的环境变量
+    pub clear_env:bool,
+    /// 命令的超时时间，超时后终止命令及其子进程
+    pub timeout:Option<Duration>
+}
+
+impl CmdOptions {
+    pub fn from_value(value:Option<&Value>)->PipelineResult<Self>{
+        let mut options=CmdOptions::default();
+        let value=match value {
+            None => return Ok(options),
+            Some(v) => v.as_dynamic()
+        };
+        match value {
+            Dynamic::Map(m)=>{
+                if let Some(capture)=m.get(&Dynamic::from("capture")){
+                    options.capture=capture.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
+                }
+                if let Some(allow_failure)=m.get(&Dynamic::from("allowFailure")){
+                    options.allow_failure=allow_failure.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
+                }
+                if let Some(clear_env)=m.get(&Dynamic::from("clearEnv")){
+                    options.clear_env=clear_env.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
+                }
+                if let Some(timeout)=m.get(&Dynamic::from("timeout")){
+                    options.timeout=Some(parse_duration(timeout)?);
+                }
+            }
+            Dynamic::Boolean(b)=>{
+                options.capture=b;
+            }
+            t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+        }
+        Ok(options)
+    }
+}
+
+/// readInt、readFloat、readString和readLine读取的值的类型
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum PromptKind{
+    Int,
+    Float,
+    String,
+    Line
+}
+/// 提示输入的可选参数，通过提示语之后的具名参数传入，例如readString("项目名:",default="demo",choices=["demo","web"])
+#[derive(Debug,Clone,Default)]
+pub struct PromptOptions{
+    /// 通过--answer key=value回答该提示时使用的键，默认为去掉末尾冒号的提示语
+    pub key:Option<String>,
+    /// 直接回车或stdin不是终端时使用的值
+    pub default:Option<Value>,
+    /// 允许输入的值，为空时不限制
+    pub choices:Vec<String>
+}
+
+impl PromptOptions {
+    pub fn from_value(value:Option<&Value>)->PipelineResult<Self>{
+        let mut options=PromptOptions::default();
+        let m=match value.map(|v|v.as_dynamic()) {
+            None=>return Ok(options),
+            Some(Dynamic::Map(m))=>m,
+            Some(t)=>return Err(PipelineError::UnexpectedType(t.type_name()))
+        };
+        if let Some(key)=m.get(&Dynamic::from("key")){
+            options.key=Some(key.as_string().ok_or(PipelineError::ExpectedType("String".into()))?);
+        }
+        options.default=m.get(&Dynamic::from("default")).cloned();
+        if let Some(choices)=m.get(&Dynamic::from("choices")){
+            let choices=match choices.as_dynamic() {
+                Dynamic::Array(a)=>a,
+                t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+            };
+            options.choices=choices.iter().map(|c|c.as_dynamic().to_string()).collect();
+        }
+        Ok(options)
+    }
+}
+/// 读取一次用户输入。依次使用--answer或--answers-file中的答案、终端输入，
+/// stdin不是终端时使用默认值，没有默认值时立即报错而不是阻塞等待输入
+pub fn prompt(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,kind:PromptKind,hint:&str,options:PromptOptions)->PipelineResult<Value>{
+    let key=options.key.clone().unwrap_or(hint.trim().trim_end_matches([':','：']).trim().to_string());
+    let answer=PipelineEngine::context_with_global_state(ctx).read().unwrap().value(format!("answer.{key}").as_str()).cloned();
+    if let Some(answer)=answer{
+        return parse_answer(kind,&answer,&options)
+            .map_err(|e|PipelineError::InvalidArgument(format!("invalid answer for prompt \"{key}\": {e}")))
+    }
+    if !io::stdin().is_terminal(){
+        return options.default.ok_or(PipelineError::PromptUnanswered(key))
+    }
+    let mut text=hint.to_string();
+    if !options.choices.is_empty(){
+        text.push_str(&format!("[{}]",options.choices.join("/")));
+    }
+    if let Some(default)=&options.default{
+        text.push_str(&format!("({})",default.as_dynamic()));
+    }
+    let sc=PipelineEngine::context_with_native(ctx,"$sc");
+    let mut sc=sc.write().unwrap();
+    let sc=sc.downcast_mut::<Scanner<Stdin>>().unwrap();
+    loop {
+        print!("{text}");
+        io::stdout().flush().map_err(|e|PipelineError::IoFailed("write stdout".into(),e.to_string()))?;
+        let line=read_input(sc,kind,options.default.is_some())
+            .map_err(|e|PipelineError::IoFailed("read stdin".into(),e.to_string()))?
+            .ok_or(PipelineError::PromptUnanswered(key.clone()))?;
+        if line.trim().is_empty(){
+            if let Some(default)=&options.default{
+                return Ok(default.clone())
+            }
+        }
+        match parse_answer(kind,&line,&options) {
+            Ok(v)=>return Ok(v),
+            //终端中输入不合法时重新提示
+            Err(e)=>println!("\x1b[33m[Warn]:input {e}\x1b[0m")
+        }
+    }
+}
+/// 从终端读取一次输入：readLine读取一整行，保留末尾的换行符；其余读取下一个以空白分隔的词，同一行中剩余的词留给之后的读取。
+/// 读取词时只有提示有默认值才把空行作为输入(返回空字符串)，否则跳过空行继续等待。输入结束时返回None，readLine返回空字符串
+fn read_input<R:Read>(sc:&mut Scanner<R>,kind:PromptKind,allow_blank:bool)->Result<Option<String>,ScannerError>{
+    if kind==PromptKind::Line{
+        return Ok(Some(sc.next_line()?.map(|line|line+"\n").unwrap_or_default()))
+    }
+    let mut token=String::new();
+    loop {
+        match sc.next_char()? {
+            None if token.is_empty()=>return Ok(None),
+            None=>break,
+            Some(c) if !c.is_whitespace()=>token.push(c),
+            Some(_) if !token.is_empty()=>break,
+            Some('\n') if allow_blank=>break,
+            Some(_)=>{}
+        }
+    }
+    Ok(Some(token))
+}
+/// 将答案转换为提示要求的类型并检查是否为允许的值，失败时返回原因
+fn parse_answer(kind:PromptKind,answer:&str,options:&PromptOptions)->Result<Value,String>{
+    let trimmed=answer.trim();
+    let value:Value=match kind {
+        PromptKind::Int=>trimmed.parse::<i64>().map_err(|_|format!("\"{trimmed}\" is not an Int"))?.into(),
+        PromptKind::Float=>trimmed.parse::<f64>().map_err(|_|format!("\"{trimmed}\" is not a Float"))?.into(),
+        PromptKind::String=>trimmed.to_string().into(),
+        PromptKind::Line=>answer.to_string().into()
+    };
+    //readLine比较选项时不考虑末尾的换行符
+    let chosen=match kind {
+        PromptKind::Line=>answer.trim_end_matches(['\r','\n']).to_string(),
+        _=>value.as_dynamic().to_string()
+    };
+    if !options.choices.is_empty()&&!options.choices.contains(&chosen){
+        return Err(format!("\"{trimmed}\" should be one of {}",options.choices.join(",")))
+    }
+    Ok(value)
+}
+/// 将脚本中的相对路径解析为相对于当前workspace的路径，绝对路径保持不变
+pub fn workspace_path(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,path:&str)->PathBuf{
+    let workspace=PipelineEngine::context_with_global_value(ctx,"workspace");
+    Path::new(workspace.as_str()).join(path)
+}
+/// dry-run模式下有副作用的操作(执行命令、文件操作等)只记录不执行
+pub fn is_dry_run(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->bool{
+    PipelineEngine::context_with_global_value(ctx,"dry_run")=="true"
+}
+/// 记录dry-run模式下将要执行的操作，位于任务中时记录到该任务的执行计划，否则直接输出
+pub fn record_effect(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,effect:String){
+    match PipelineEngine::context_with_plan(ctx) {
+        Some(plan) => plan.write().unwrap().push(effect),
+        None => println!("\x1b[36m[Plan]:{effect}\x1b[0m")
+    }
+}
+/// 当前环境变量作用域是否不继承进程的环境变量，由pipeline或step的clearEnv选项设置
+pub fn is_clear_env(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->bool{
+    let clear_env=ctx.read().unwrap().value("$clear_env");
+    clear_env.and_then(|c|c.as_local()).is_some_and(|c|c=="true")
+}
+/// 创建新的环境变量作用域，先复制上级作用域中的环境变量，再用env覆盖；
+/// clear_env为true时该作用域及其下级作用域中执行的命令不继承进程的环境变量
+pub fn with_env_scope(ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,env:HashMap<String,String>,clear_env:bool)->Arc<RwLock<dyn Context<PipelineContextValue>>>{
+    let mut scope=PipelineEngine::context_with_env(&ctx).read().unwrap().clone();
+    scope.extend(env);
+    let ctx=PipelineEngine::with_value(ctx,"$env",PipelineContextValue::Env(Arc::new(RwLock::new(scope))));
+    if clear_env{
+        return PipelineEngine::with_value(ctx,"$clear_env",PipelineContextValue::Local("true".into()))
+    }
+    ctx
+}
+/// 读取环境变量，优先使用当前作用域中设置的值，其次是进程的环境变量(clearEnv时除外)
+pub fn get_env(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,key:&str)->Option<String>{
+    let env=PipelineEngine::context_with_env(ctx);
+    if let Some(v)=env.read().unwrap().get(key){
+        return Some(v.clone())
+    }
+    if is_clear_env(ctx){
+        return None
+    }
+    std::env::var(key).ok()
+}
+/// 读取通过pipeline run -p name=value或--params-file传入的参数，在全局状态中以param.为前缀保存
+pub fn get_param(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,name:&str)->Option<String>{
+    let global=PipelineEngine::context_with_global_state(ctx);
+    let global=global.read().unwrap();
+    global.value(format!("param.{name}").as_str()).cloned()
+}
+/// 将命令行传入的参数按默认值的类型转换，默认值为Array时参数按逗号分隔
+pub fn coerce_param(name:&str,raw:&str,default:&Dynamic)->PipelineResult<Value>{
+    let invalid=|expected:&str|PipelineError::InvalidArgument(format!("parameter \"{name}\" expects {expected},but got \"{raw}\""));
+    Ok(match default {
+        Dynamic::Integer(_)=>raw.trim().parse::<i64>().map_err(|_|invalid("Int"))?.into(),
+        Dynamic::Float(_)=>raw.trim().parse::<f64>().map_err(|_|invalid("Float"))?.into(),
+        Dynamic::Boolean(_)=>match raw.trim().to_lowercase().as_str() {
+            "true"|"yes"|"on"|"1"=>true.into(),
+            "false"|"no"|"off"|"0"=>false.into(),
+            _=>return Err(invalid("Bool"))
+        },
+        Dynamic::Array(_)=>{
+            let items=raw.split(',').map(|i|i.trim()).filter(|i|!i.is_empty()).map(|i|i.to_string().into()).collect();
+            Value::with_mutable(Dynamic::Array(items))
+        }
+        _=>raw.to_string().into()
+    })
+}
+pub fn cmd(command:&str, options:CmdOptions, ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>)->PipelineResult<Value>{
+    if is_dry_run(&ctx){
+        let workspace=PipelineEngine::context_with_global_value(&ctx,"workspace");
+        record_effect(&ctx,format!("cmd \"{command}\" in {workspace}"));
+        return Ok(cmd_result(0,String::new(),String::new(),0))
+    }
     let mut cmd="powershell";
     let mut c="/C";
     let os = std::env::consts::OS;
@@ -27,98 +266,205 @@ pub fn cmd(command:&str, ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>)->Pi
     let workspace=global.read().unwrap();
     let workspace=workspace.value("workspace").unwrap();
     let env=PipelineEngine::context_with_env(&ctx);
-    let mut env=env.write().unwrap();
-    let mut child = Command::new(cmd)
+    let env=env.read().unwrap();
+    let mut command_builder=Command::new(cmd);
+    if options.clear_env||is_clear_env(&ctx){
+        command_builder.env_clear();
+    }
+    command_builder
         .current_dir(workspace.as_str())
         .envs(env.iter())
-        .args(&[c, command])
-        // .stdout(Stdio::piped())
-        // .stderr(Stdio::piped())
-        .spawn() // 执行命令，并获取输出结果
-        .expect("执行命令失败");
-    // let flag1=is_system_gbk_output_command(command);
-    // let flag2=is_system_gbk_err_command(command);
-    // let mut stdout =child.stdout.take().expect("Can not get stderr.");
-    // let mut stderr =child.stderr.take().expect("Can not get stderr.");
-    // let join_set=PipelineEngine::context_with_join_set(&ctx,"op_join_set");
-    // let mut join_set =join_set.write().unwrap();
-    // let clone=ctx.clone();
-    // let handle=thread::spawn(move||{
-    //     let ctx=clone.clone();
-    //     let mut binding = PipelineEngine::context_with_logger(&ctx, "logger");
-    //     let  logger=binding.as_logger().unwrap();
-    //     let mut buffer = [0; 1];
-    //     let mut bytes =vec![];
-    //     while let Ok(size)=stdout.read(&mut buffer){
-    //         if size<=0{
-    //             break
-    //         }
-    //         //如果最后一个字节是中文的第一个字节
-    //         if buffer[0]== u8::try_from('\n').unwrap(){
-    //             if flag1{
-    //                 let (cow, _encoding_used, had_errors) = GBK.decode(bytes.as_slice());
-    //                 if had_errors {
-    //                     // 如果出现解码错误，可以按照需要进行处理
-    //                     logger.write().unwrap().task_err(&ctx,String::from_utf8(bytes.clone()).unwrap().as_str());
-    //                 }
-    //                 for line in cow.lines() {
-    //                     logger.write().unwrap().task_out(&ctx,line);
-    //                 }
-    //             }else{
-    //                 logger.write().unwrap().task_out(&ctx,String::from_utf8(bytes.clone()).unwrap().as_str());
-    //             }
-    //
-    //             bytes.clear();
-    //         }else{
-    //             bytes.append(&mut buffer.to_vec());
-    //         }
-    //
-    //     }
-    //     if bytes.len()!=0&&String::from_utf8(bytes.clone()).unwrap()!="\n"{
-    //         logger.write().unwrap().task_out(&ctx,String::from_utf8(bytes.clone()).unwrap().as_str());
-    //     }
-    //     return Ok(())
-    // });
-    // join_set.push(handle);
-    // let handle=thread::spawn(move||{
-    //     let binding = PipelineEngine::context_with_logger(&ctx, "logger");
-    //     let  logger=binding.as_logger().unwrap();
-    //     let mut buffer = [0; 1];
-    //     let mut bytes =vec![];
-    //     while let Ok(size)=stderr.read(&mut buffer){
-    //         if size<=0{
-    //             break
-    //         }
-    //         //如果最后一个字节是中文的第一个字节
-    //         if buffer[0]== u8::try_from('\n').unwrap() {
-    //             if flag2 {
-    //                 let (cow, _encoding_used, had_errors) = GBK.decode(bytes.as_slice());
-    //
-    //                 if had_errors {
-    //                     logger.write().unwrap().task_err(&ctx,String::from_utf8(bytes.clone()).unwrap().as_str());
-    //                 }
-    //
-    //                 for line in cow.lines() {
-    //                     logger.write().unwrap().task_err(&ctx,line);
-    //                 }
-    //             }else {
-    //                 logger.write().unwrap().task_err(&ctx,String::from_utf8(bytes.clone()).unwrap().as_str());
-    //             }
-    //             bytes.clear();
-    //         }else{
-    //             bytes.append(&mut buffer.to_vec());
-    //         }
-    //
-    //     }
-    //     if bytes.len()!=0{
-    //         logger.write().unwrap().task_err(&ctx,String::from_utf8(bytes.clone()).unwrap().as_str());
-    //     }
-    //     return Ok(())
-    // });
-    // join_set.push(handle);
-
-    let _ = child.wait().expect("Failed to wait for command execution");
-    return Ok(().into())
+        .args([c, command]);
+    drop(env);
+    check_deadline(&ctx)?;
+    let start=Instant::now();
+    //命令和所在任务的超时时间中先到达的一个，超时后报告对应的命令或任务
+    let mut deadline=match options.timeout {
+        Some(t)=>Some((deadline_after(start,t)?,format!("command \"{command}\""),t)),
+        None=>None
+    };
+    if let Some((task_deadline,timeout))=PipelineEngine::context_with_deadline(&ctx){
+        if deadline.as_ref().map_or(true,|(d,_,_)|task_deadline<*d){
+            deadline=Some((task_deadline,task_target(&ctx),timeout));
+        }
+    }
+    if options.capture{
+        // 捕获stdout和stderr，而不是直接输出到控制台
+        command_builder.stdin(Stdio::inherit()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    //可能需要被终止的命令在独立的进程组中运行，以便终止整个进程组。
+    //在终端中运行且没有超时的命令仍在前台进程组中，可以读取终端输入，Ctrl-C由终端直接发送给它们
+    let group=deadline.is_some()||!io::stdin().is_terminal();
+    #[cfg(unix)]
+    if group{
+        use std::os::unix::process::CommandExt;
+        command_builder.process_group(0);
+    }
+    let mut child=command_builder.spawn().map_err(|e|command_failed(command,e))?;
+    let pid=child.id();
+    RUNNING_COMMANDS.lock().unwrap().push((pid,group));
+    if INTERRUPTED.load(Ordering::SeqCst){
+        kill_command(&mut child,group);
+        wait_for_exit();
+    }
+    //在单独的线程中读取输出，避免输出填满管道后子进程阻塞
+    let stdout=child.stdout.take().map(read_in_background);
+    let stderr=child.stderr.take().map(read_in_background);
+    let status=wait_until(&mut child,deadline.as_ref().map(|(d,_,_)|*d));
+    RUNNING_COMMANDS.lock().unwrap().retain(|(p,_)|*p!=pid);
+    if INTERRUPTED.load(Ordering::SeqCst){
+        wait_for_exit();
+    }
+    let status=match status.map_err(|e|command_failed(command,e))? {
+        Some(status)=>status,
+        None=>{
+            kill_command(&mut child,group);
+            let _=child.wait();
+            let (_,target,timeout)=deadline.unwrap();
+            return Err(PipelineError::TimedOut(target,timeout))
+        }
+    };
+    let read=|h:Option<JoinHandle<Vec<u8>>>|h.map(|h|decode_output(&h.join().unwrap_or_default())).unwrap_or_default();
+    let (code,stdout,stderr)=(status.code(),read(stdout),read(stderr));
+    let duration=start.elapsed().as_millis() as i64;
+    let code=code.unwrap_or(-1) as i64;
+    if code!=0&&!options.allow_failure{
+        return Err(PipelineError::CommandFailed(command.into(),code))
+    }
+    Ok(cmd_result(code,stdout,stderr,duration))
+}
+/// 正在执行的命令，(进程id,是否在独立的进程组中)，Ctrl-C时终止这些命令及其子进程
+static RUNNING_COMMANDS:Mutex<Vec<(u32,bool)>>=Mutex::new(Vec::new());
+/// 收到Ctrl-C后为true，此后命令被终止不再作为任务失败处理
+static INTERRUPTED:AtomicBool=AtomicBool::new(false);
+/// 终止所有正在执行的命令(包括并发任务中的命令)及其子进程，返回终止的命令数。
+/// 调用后不会再启动新的命令，调用方应在之后退出进程
+pub fn kill_running_commands()->usize{
+    INTERRUPTED.store(true,Ordering::SeqCst);
+    let commands=std::mem::take(&mut *RUNNING_COMMANDS.lock().unwrap());
+    for (pid,group) in &commands{
+        kill_process_tree(*pid,*group);
+    }
+    commands.len()
+}
+/// 命令因Ctrl-C被终止时阻塞当前线程，等待Ctrl-C的处理函数退出进程，避免输出任务失败
+fn wait_for_exit()->!{
+    loop {
+        thread::park();
+    }
+}
+/// 终止命令及其子进程，并总是终止命令本身作为兜底
+fn kill_command(child:&mut Child,group:bool){
+    kill_process_tree(child.id(),group);
+    let _=child.kill();
+}
+/// 终止进程及其子进程。unix下在独立进程组中的命令终止整个进程组(进程组id即命令的进程id)，
+/// 否则只终止命令本身；windows下使用taskkill /T终止进程树
+#[cfg(unix)]
+fn kill_process_tree(pid:u32,group:bool){
+    let pid=pid as libc::pid_t;
+    //进程已经退出时kill会失败，此时没有需要终止的进程
+    unsafe{
+        if group{
+            libc::killpg(pid,libc::SIGKILL);
+        }else{
+            libc::kill(pid,libc::SIGKILL);
+        }
+    }
+}
+#[cfg(not(unix))]
+fn kill_process_tree(pid:u32,_group:bool){
+    let _=Command::new("taskkill").args(["/T","/F","/PID",&pid.to_string()])
+        .stdout(Stdio::null()).stderr(Stdio::null()).status();
+}
+/// 等待子进程退出，超过deadline仍未退出时返回None
+fn wait_until(child:&mut Child,deadline:Option<Instant>)->io::Result<Option<ExitStatus>>{
+    let Some(deadline)=deadline else {
+        return child.wait().map(Some)
+    };
+    loop {
+        if let Some(status)=child.try_wait()?{
+            return Ok(Some(status))
+        }
+        if Instant::now()>=deadline{
+            return Ok(None)
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+fn read_in_background(mut r:impl Read+Send+'static)->JoinHandle<Vec<u8>>{
+    thread::spawn(move||{
+        let mut buf=vec![];
+        let _=r.read_to_end(&mut buf);
+        buf
+    })
+}
+/// 超时的任务报告的目标，例如task "build"
+fn task_target(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->String{
+    format!("task \"{}\"",PipelineEngine::context_with_local(ctx,"$task_name"))
+}
+/// 当前任务已经超过timeout时返回TimedOut错误，不在设置了timeout的任务中时总是返回Ok
+pub fn check_deadline(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->PipelineResult<()>{
+    match PipelineEngine::context_with_deadline(ctx) {
+        Some((deadline,timeout)) if Instant::now()>=deadline=>Err(PipelineError::TimedOut(task_target(ctx),timeout)),
+        _=>Ok(())
+    }
+}
+/// 当前任务距离超时的剩余时间，不在设置了timeout的任务中时为None
+pub fn remaining_time(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->Option<Duration>{
+    PipelineEngine::context_with_deadline(ctx).map(|(deadline,_)|deadline.saturating_duration_since(Instant::now()))
+}
+/// 解析timeout等时长参数，Int和Float的单位为秒，String可以带ms、s、m、h单位，例如"500ms"、"10m"
+pub fn parse_duration(value:&Value)->PipelineResult<Duration>{
+    let invalid=|v:&dyn std::fmt::Display|PipelineError::InvalidArgument(format!("invalid duration \"{v}\""));
+    let seconds=match value.as_dynamic() {
+        Dynamic::Integer(i)=>i as f64,
+        Dynamic::Float(f)=>f,
+        Dynamic::String(s)=>{
+            let t=s.trim();
+            let (number,unit)=t.split_at(t.find(|c:char|c.is_alphabetic()).unwrap_or(t.len()));
+            let n:f64=number.trim().parse().map_err(|_|invalid(&s))?;
+            match unit {
+                "ms"=>n/1000.0,
+                ""|"s"=>n,
+                "m"=>n*60.0,
+                "h"=>n*3600.0,
+                _=>return Err(invalid(&s))
+            }
+        }
+        t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+    };
+    if !seconds.is_finite()||seconds<=0.0{
+        return Err(invalid(&value.as_dynamic()))
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|_|invalid(&value.as_dynamic()))
+}
+/// 从start开始经过timeout后的截止时间，超出Instant能表示的范围时报错
+pub fn deadline_after(start:Instant,timeout:Duration)->PipelineResult<Instant>{
+    start.checked_add(timeout).ok_or(PipelineError::InvalidArgument(format!("timeout {timeout:?} is too large")))
+}
+/// 命令无法启动(如workspace不存在)时的错误
+fn command_failed(command:&str,e:io::Error)->PipelineError{
+    PipelineError::IoFailed(format!("run command \"{command}\""),e.to_string())
+}
+/// 构造cmd函数的返回值CmdResult{code,stdout,stderr,duration}，duration单位为毫秒
+pub fn cmd_result(code:i64,stdout:String,stderr:String,duration:i64)->Value{
+    let mut props=HashMap::new();
+    props.insert("code".into(),code.into());
+    props.insert("stdout".into(),stdout.into());
+    props.insert("stderr".into(),stderr.into());
+    props.insert("duration".into(),duration.into());
+    Value::Immutable(Dynamic::Struct(Box::new(Struct::new("CmdResult".into(),props))))
+}
+/// windows下部分系统命令的输出为GBK编码，utf8解码失败时尝试使用GBK解码
+fn decode_output(bytes:&[u8])->String{
+    match String::from_utf8(bytes.to_vec()) {
+        Ok(s) => s,
+        Err(_) => {
+            let (cow, _encoding_used, _had_errors) = GBK.decode(bytes);
+            cow.into_owned()
+        }
+    }
 }
 fn is_system_gbk_output_command(c: &str) ->bool{
     if c.starts_with("ls"){return true}
@@ -132,31 +478,38 @@ fn is_system_gbk_err_command(c:&str)->bool{
     false
 }
 
-pub  fn replace(ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>, source_path:&str, regex:&str, replace:&str){
+pub  fn replace(ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>, source_path:&str, regex:&str, replace:&str)->PipelineResult<()>{
+    let re=Regex::new(regex).map_err(|e|PipelineError::InvalidArgument(format!("regex /{regex}/: {e}")))?;
+    if is_dry_run(&ctx){
+        record_effect(&ctx,format!("replace /{regex}/ with \"{replace}\" in {source_path}"));
+        return Ok(())
+    }
     let global=PipelineEngine::context_with_global_state(&ctx);
     let global=global.read().unwrap();
     let workspace=global.value("workspace").unwrap();
     let root=Path::new(workspace.as_str());
     let source = root.join(Path::new(source_path));
-    let content=fs::read(source.clone()).expect(format!("replace失败,可能文件路径{}不正确",source_path).as_str());
-    let re=Regex::new(regex).unwrap();
-    let content=String::from_utf8(content).unwrap();
+    let io_failed=|e:io::Error|PipelineError::IoFailed(format!("replace in {source_path}"),e.to_string());
+    let content=fs::read_to_string(source.as_path()).map_err(io_failed)?;
     let replace_content=re.replace_all(content.as_str(),replace);
-    fs::write(source.as_path(),replace_content.as_ref()).unwrap();
+    fs::write(source.as_path(),replace_content.as_ref()).map_err(io_failed)?;
+    Ok(())
 }
 
 
 pub  fn copy_all(source:&Path,target:&Path)->io::Result<()>{
     if !source.exists(){
-        return Err(io::Error::new(NotFound,format!("{}不存在",source.to_str().unwrap())));
+        return Err(io::Error::new(NotFound,format!("{}不存在",source.display())));
     }
     if !target.exists(){
-        fs::create_dir_all(target.parent().unwrap())?;
+        if let Some(parent)=target.parent(){
+            fs::create_dir_all(parent)?;
+        }
     }
     if source.is_dir(){
-        for entry in source.read_dir().expect("目录读取失败") {
+        for entry in source.read_dir()? {
             if let Ok(entry) = entry {
-                copy_all(entry.path().as_path(),target.join(entry.path().as_path().file_name().unwrap()).as_path())?;
+                copy_all(entry.path().as_path(),target.join(entry.file_name()).as_path())?;
             }
         }
     }else{
@@ -168,42 +521,167 @@ pub  fn copy_all(source:&Path,target:&Path)->io::Result<()>{
 // fn test_copy(){
 //     copy_all(Path::new("test/x"),Path::new("test/s")).await.unwrap();
 // }
-pub fn copy(ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>, source_path:&str, target_path:&str){
+pub fn copy(ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>, source_path:&str, target_path:&str)->PipelineResult<()>{
+    if is_dry_run(&ctx){
+        record_effect(&ctx,format!("copy {source_path} -> {target_path}"));
+        return Ok(())
+    }
     let global=PipelineEngine::context_with_global_state(&ctx);
     let global=global.read().unwrap();
     let workspace=global.value("workspace").unwrap();
     let root=Path::new(workspace.as_str());
     let source = root.join(Path::new(source_path));
     let target = root.join(Path::new(target_path));
-    let res=copy_all(source.as_path(),target.as_path());
-    if let Err(e) = res {
-        println!("\x1b[31m[Error]:文件复制失败:{}\x1b[0m",e);
-        exit(0);
-    }
+    copy_all(source.as_path(),target.as_path())
+        .map_err(|e|PipelineError::IoFailed(format!("copy {source_path} -> {target_path}"),e.to_string()))
 }
-pub  fn move_file( ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,source_path:&str, target_path:&str){
+pub  fn move_file( ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,source_path:&str, target_path:&str)->PipelineResult<()>{
+    if is_dry_run(&ctx){
+        record_effect(&ctx,format!("move {source_path} -> {target_path}"));
+        return Ok(())
+    }
     let global=PipelineEngine::context_with_global_state(&ctx);
     let global=global.read().unwrap();
     let workspace=global.value("workspace").unwrap();
     let root=Path::new(workspace.as_str());
     let source = root.join(Path::new(source_path));
     let target = root.join(Path::new(target_path));
-    let res = copy_all(source.as_path(), target.as_path());
-    match res{
-        Ok(_) => {
-            let r=fs::remove_dir_all(source.as_path());
-            match r{
-                Ok(_) => {
-                }
-                Err(e) => {
-                    println!("\x1b[31m[Error]:旧文件移除失败:{}\x1b[0m",e);
-                    exit(0);
-                }
-            }
-        }
-        Err(e) => {
-            println!("\x1b[31m[Error]:文件移动失败:{}\x1b[0m",e);
-            exit(0);
+    let io_failed=|e:io::Error|PipelineError::IoFailed(format!("move {source_path} -> {target_path}"),e.to_string());
+    copy_all(source.as_path(), target.as_path()).map_err(io_failed)?;
+    if source.is_dir(){
+        fs::remove_dir_all(source.as_path()).map_err(io_failed)?;
+    }else{
+        fs::remove_file(source.as_path()).map_err(io_failed)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duration(v:impl Into<Value>)->PipelineResult<Duration>{
+        parse_duration(&v.into())
+    }
+
+    #[test]
+    fn parses_numbers_as_seconds(){
+        assert_eq!(duration(5i64).unwrap(),Duration::from_secs(5));
+        assert_eq!(duration(1.5).unwrap(),Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn parses_units(){
+        assert_eq!(duration("500ms".to_string()).unwrap(),Duration::from_millis(500));
+        assert_eq!(duration("3".to_string()).unwrap(),Duration::from_secs(3));
+        assert_eq!(duration(" 2s ".to_string()).unwrap(),Duration::from_secs(2));
+        assert_eq!(duration("10m".to_string()).unwrap(),Duration::from_secs(600));
+        assert_eq!(duration("1h".to_string()).unwrap(),Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn rejects_too_large_durations(){
+        assert!(matches!(duration("1e20".to_string()),Err(PipelineError::InvalidArgument(_))));
+        assert!(matches!(duration(1e300),Err(PipelineError::InvalidArgument(_))));
+        let e=deadline_after(Instant::now(),Duration::MAX).unwrap_err();
+        assert!(matches!(e,PipelineError::InvalidArgument(_)));
+    }
+
+    #[test]
+    fn rejects_invalid_durations(){
+        for s in ["5x","abc","","-1s","0ms"]{
+            assert!(matches!(duration(s.to_string()),Err(PipelineError::InvalidArgument(_))),"{s}");
         }
+        assert!(matches!(duration(0i64),Err(PipelineError::InvalidArgument(_))));
+        assert!(matches!(duration(-2.0),Err(PipelineError::InvalidArgument(_))));
+        assert!(matches!(duration(true),Err(PipelineError::UnexpectedType(_))));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reads_words_and_whole_lines(){
+        let mut sc=Scanner::new("hello world\n\n  42\nrest of line\r\n".as_bytes());
+        assert_eq!(read_input(&mut sc,PromptKind::String,false).unwrap(),Some("hello".into()));
+        assert_eq!(read_input(&mut sc,PromptKind::String,false).unwrap(),Some("world".into()));
+        assert_eq!(read_input(&mut sc,PromptKind::Int,false).unwrap(),Some("42".into()));
+        assert_eq!(read_input(&mut sc,PromptKind::Line,false).unwrap(),Some("rest of line\n".into()));
+        assert_eq!(read_input(&mut sc,PromptKind::String,false).unwrap(),None);
+        assert_eq!(read_input(&mut sc,PromptKind::Line,false).unwrap(),Some(String::new()));
+    }
+
+    #[test]
+    fn blank_line_selects_the_default(){
+        let mut sc=Scanner::new("  \nvalue\n".as_bytes());
+        assert_eq!(read_input(&mut sc,PromptKind::String,true).unwrap(),Some(String::new()));
+        assert_eq!(read_input(&mut sc,PromptKind::String,true).unwrap(),Some("value".into()));
+    }
+
+    #[test]
+    fn parses_answers(){
+        let options=PromptOptions{choices:vec!["a".into(),"b".into()],..Default::default()};
+        assert_eq!(parse_answer(PromptKind::Line,"b\n",&options).unwrap().as_string(),Some("b\n".into()));
+        assert!(parse_answer(PromptKind::String,"c",&options).is_err());
+        assert_eq!(parse_answer(PromptKind::Int," 8 ",&PromptOptions::default()).unwrap().as_integer(),Some(8));
+        assert!(parse_answer(PromptKind::Float,"x",&PromptOptions::default()).is_err());
+    }
+
+    fn prop(v:&Value,name:&str)->Dynamic{
+        v.as_dynamic().as_struct().and_then(|s|s.get_prop(name)).unwrap().as_dynamic()
+    }
+    #[test]
+    fn parses_cmd_options(){
+        assert!(CmdOptions::from_value(Some(&true.into())).unwrap().capture);
+        let options=PipelineEngine::default().eval_expr("{\"capture\":true,\"allowFailure\":true,\"timeout\":\"2s\"}").unwrap();
+        let options=CmdOptions::from_value(Some(&options)).unwrap();
+        assert!(options.capture&&options.allow_failure);
+        assert_eq!(options.timeout,Some(Duration::from_secs(2)));
+        assert!(CmdOptions::from_value(Some(&1i64.into())).is_err());
+    }
+    #[cfg(unix)]
+    #[test]
+    fn captured_cmd_returns_code_and_output(){
+        let options=CmdOptions{capture:true,allow_failure:true,..Default::default()};
+        let r=cmd("printf out; printf err >&2; exit 3",options,PipelineEngine::background()).unwrap();
+        assert_eq!(r.as_dynamic().type_name(),"CmdResult");
+        assert_eq!(prop(&r,"code"),Dynamic::Integer(3));
+        assert_eq!(prop(&r,"stdout"),Dynamic::String("out".into()));
+        assert_eq!(prop(&r,"stderr"),Dynamic::String("err".into()));
+        assert!(matches!(prop(&r,"duration"),Dynamic::Integer(d) if d>=0));
+    }
+    #[cfg(unix)]
+    #[test]
+    fn cmd_result_is_usable_from_scripts(){
+        let r=PipelineEngine::default().run_script("let r=cmd(\"echo hi\",true)\nreturn \"${r.code}:${r.stdout.trim()}\"").unwrap();
+        assert_eq!(r.as_string(),Some("0:hi".into()));
+    }
+    #[cfg(unix)]
+    #[test]
+    fn failed_cmd_returns_command_failed(){
+        let e=cmd("exit 2",CmdOptions::default(),PipelineEngine::background()).unwrap_err();
+        assert!(matches!(e,PipelineError::CommandFailed(c,2) if c=="exit 2"));
+    }
+    #[test]
+    fn coerces_params_to_the_default_type(){
+        assert_eq!(coerce_param("n"," 8 ",&Dynamic::Integer(1)).unwrap().as_integer(),Some(8));
+        assert_eq!(coerce_param("f","0.5",&Dynamic::Float(1.0)).unwrap().as_dynamic(),Dynamic::Float(0.5));
+        assert_eq!(coerce_param("b","Yes",&Dynamic::Boolean(false)).unwrap().as_bool(),Some(true));
+        assert_eq!(coerce_param("b","off",&Dynamic::Boolean(true)).unwrap().as_bool(),Some(false));
+        let a=coerce_param("a","x, y,,z",&Dynamic::Array(vec![])).unwrap();
+        assert_eq!(a.as_dynamic().to_string(),"[x,y,z]");
+        assert_eq!(coerce_param("s","1.4.0",&Dynamic::String(String::new())).unwrap().as_string(),Some("1.4.0".into()));
+        let e=coerce_param("n","abc",&Dynamic::Integer(1)).unwrap_err();
+        assert!(matches!(e,PipelineError::InvalidArgument(m) if m.contains("\"n\" expects Int")));
+        assert!(coerce_param("b","maybe",&Dynamic::Boolean(true)).is_err());
+    }
+    #[test]
+    fn param_reads_command_line_values(){
+        let ctx=PipelineEngine::background();
+        let global=PipelineEngine::context_with_global_state(&ctx);
+        global.write().unwrap().set_value("param.version","1.4.0".into());
+        global.write().unwrap().set_value("param.jobs","4".into());
+        let script="return [param(\"version\"),param(\"jobs\",1)+1,param(\"dryRun\",false)]";
+        let r=PipelineEngine::default().run_script_with_context(ctx.clone(),script).unwrap();
+        assert_eq!(r.as_dynamic().to_string(),"[1.4.0,5,false]");
+        let e=PipelineEngine::default().run_script_with_context(ctx,"return param(\"target\")").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::ParamRequired(p) if p=="target"));
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use crate::error::{PipelineError, PipelineResult};
+use crate::module::{Class, Function};
+use crate::v1::expr::{Expr, FnCallExpr, WhenCondition};
+use crate::v1::parser::FnDef;
+use crate::v1::stmt::Stmt;
+use crate::v1::types::{Dynamic, FnPtr, Value};
+
+/// 内置的类型名，Closure表示lambda和闭包，Unit表示没有返回值
+const BUILTIN_TYPES:[&str;10]=["Int","Float","String","Bool","Array","Map","Range","Closure","Any","Unit"];
+
+/// 是否为内置类型，std中为内置类型注册的class只用于挂载方法，不能创建对象
+pub fn is_builtin_type(name:&str)->bool{
+    BUILTIN_TYPES.contains(&name)
+}
+/// 值是否符合声明的类型。Any接受任意值，Float同时接受Int，类型名后带?时还接受null
+pub fn type_matches(value:&Dynamic,declared:&str)->bool{
+    let (name,nullable)=match declared.strip_suffix('?') {
+        Some(name)=>(name,true),
+        None=>(declared,false)
+    };
+    match (name,value) {
+        ("Any",_)=>true,
+        (_,Dynamic::Unit)=>nullable||name=="Unit",
+        ("Float",Dynamic::Integer(_))=>true,
+        ("Closure",Dynamic::FnPtr(_))=>true,
+        (name,v)=>v.type_name()==name
+    }
+}
+/// 检查值是否符合声明的类型，target描述被检查的是哪个参数或返回值
+pub fn check_type(value:&Value,declared:&str,target:impl FnOnce()->String)->PipelineResult<()>{
+    let value=value.as_dynamic();
+    if type_matches(&value,declared){
+        return Ok(())
+    }
+    let actual=match value {
+        Dynamic::Unit=>"null".into(),
+        v=>v.type_name()
+    };
+    Err(PipelineError::TypeMismatch(target(),declared.into(),actual))
+}
+/// 编译后的静态检查：声明中使用的类型必须存在，调用脚本函数时以常量传入的参数必须符合声明的类型。
+/// 无法静态确定类型的参数在调用时检查
+pub fn check_types(stmts:&[Stmt],fn_lib:&[FnDef],classes:&HashMap<String,Class>)->PipelineResult<()>{
+    let known=|t:&str|{
+        let t=t.trim_end_matches('?');
+        BUILTIN_TYPES.contains(&t)||classes.contains_key(t)
+    };
+    let mut bodies=vec![];
+    for f in fn_lib{
+        check_declaration(f,format!("function \"{}\"",f.name),&known)?;
+        bodies.push(&f.body);
+    }
+    for class in classes.values(){
+        for a in class.get_attributions(){
+            if !known(&a.declaration_type){
+                return Err(PipelineError::UnknownType(a.declaration_type.clone(),format!("class \"{}\"",class.get_name())))
+            }
+        }
+        for (name,method) in class.get_methods(){
+            if let Function::Method(def)=method{
+                check_declaration(def,format!("method \"{}.{name}\"",class.get_name()),&known)?;
+                bodies.push(&def.body);
+            }
+        }
+    }
+    let functions:HashMap<&str,&FnDef>=fn_lib.iter().map(|f|(f.name.as_str(),f)).collect();
+    check_stmts(stmts,&functions)?;
+    for body in bodies{
+        check_stmts(body,&functions)?;
+    }
+    Ok(())
+}
+fn check_declaration(f:&FnDef,owner:String,known:&impl Fn(&str)->bool)->PipelineResult<()>{
+    let types=f.args.iter().map(|a|&a.declaration_type).chain([&f.return_type]);
+    for t in types{
+        if !known(t){
+            return Err(PipelineError::UnknownType(t.clone(),owner))
+        }
+    }
+    Ok(())
+}
+fn check_stmts(stmts:&[Stmt],functions:&HashMap<&str,&FnDef>)->PipelineResult<()>{
+    for stmt in stmts{
+        match stmt {
+            Stmt::FnCall(call,_)=>check_call(call,functions)?,
+            Stmt::Let(l,_)=>check_expr(&l.1,functions)?,
+            Stmt::Assign(a,_)=>{
+                check_expr(&a.0,functions)?;
+                check_expr(&a.1,functions)?;
+            }
+            Stmt::Return(e,_)|Stmt::Throw(e,_)|Stmt::Expr(e,_)=>check_expr(e,functions)?,
+            Stmt::If(if_stmt,_)=>{
+                for branch in if_stmt.get_branches(){
+                    check_expr(branch.get_condition(),functions)?;
+                    check_stmts(branch.get_body(),functions)?;
+                }
+                if let Some(body)=if_stmt.get_else_body(){
+                    check_stmts(&body,functions)?;
+                }
+            }
+            Stmt::While(condition,body,_)=>{
+                check_expr(condition,functions)?;
+                check_stmts(body,functions)?;
+            }
+            Stmt::ForIn(_,_,target,body,_)=>{
+                check_expr(target,functions)?;
+                check_stmts(body,functions)?;
+            }
+            Stmt::CompoundAssign(a,_)=>{
+                check_expr(&a.0,functions)?;
+                check_expr(&a.2,functions)?;
+            }
+            Stmt::IndexAssign(target,index,value,_)=>{
+                check_expr(target,functions)?;
+                check_expr(index,functions)?;
+                check_expr(value,functions)?;
+            }
+            Stmt::Try(t,_)=>{
+                check_stmts(t.get_body(),functions)?;
+                if let Some((_,body))=t.get_catch(){
+                    check_stmts(body,functions)?;
+                }
+                if let Some(body)=t.get_finally(){
+                    check_stmts(body,functions)?;
+                }
+            }
+            _=>{}
+        }
+    }
+    Ok(())
+}
+fn check_expr(expr:&Expr,functions:&HashMap<&str,&FnDef>)->PipelineResult<()>{
+    match expr {
+        Expr::FnCall(call,_)=>check_call(call,functions),
+        Expr::FnClosure(c,_)=>check_stmts(&c.def.body,functions),
+        Expr::StringTemplate(items,_)|Expr::Array(items,_)=>{
+            items.iter().try_for_each(|e|check_expr(e,functions))
+        }
+        Expr::Map(entries,_)=>{
+            entries.iter().try_for_each(|(k,v)|{
+                check_expr(k,functions)?;
+                check_expr(v,functions)
+            })
+        }
+        Expr::BinaryExpr(_,l,r,_)|Expr::Index(l,r,_)=>{
+            check_expr(l,functions)?;
+            check_expr(r,functions)
+        }
+        Expr::UnaryExpr(_,e,_)|Expr::MemberAccess(e,_,_)|Expr::SafeAccess(e,_)=>check_expr(e,functions),
+        Expr::Struct(s,_)=>s.get_props().values().try_for_each(|e|check_expr(e,functions)),
+        Expr::If(if_stmt,_)=>{
+            for branch in if_stmt.get_branches(){
+                check_expr(branch.get_condition(),functions)?;
+                check_stmts(branch.get_body(),functions)?;
+            }
+            match if_stmt.get_else_body() {
+                Some(body)=>check_stmts(&body,functions),
+                None=>Ok(())
+            }
+        }
+        Expr::When(when,_)=>{
+            if let Some(subject)=when.get_subject(){
+                check_expr(subject,functions)?;
+            }
+            for branch in when.get_branches(){
+                for condition in branch.get_conditions(){
+                    if let WhenCondition::Value(e)|WhenCondition::In(e,_)=condition{
+                        check_expr(e,functions)?;
+                    }
+                }
+                check_stmts(branch.get_body(),functions)?;
+            }
+            match when.get_else_body() {
+                Some(body)=>check_stmts(body,functions),
+                None=>Ok(())
+            }
+        }
+        _=>Ok(())
+    }
+}
+/// 检查对脚本函数的调用中以常量传入的参数，参数个数不符时交由运行时报错。
+/// a.f()形式的方法调用优先查找接收者类型的方法，调用的是哪个函数要到运行时才能确定，因此不检查
+fn check_call(call:&FnCallExpr,functions:&HashMap<&str,&FnDef>)->PipelineResult<()>{
+    for arg in &call.args{
+        check_expr(arg,functions)?;
+    }
+    if call.method{
+        return Ok(())
+    }
+    let Some(f)=functions.get(call.name.as_str()) else {return Ok(())};
+    if f.args.len()!=call.args.len(){
+        return Ok(())
+    }
+    for (declaration,arg) in f.args.iter().zip(&call.args){
+        let value=match arg {
+            Expr::IntConstant(i,_)=>Dynamic::Integer(*i),
+            Expr::FloatConstant(f,_)=>Dynamic::Float(*f),
+            Expr::StringConstant(s,_)=>Dynamic::String(s.clone()),
+            Expr::StringTemplate(_,_)=>Dynamic::String(String::new()),
+            Expr::FnClosure(_,_)=>Dynamic::FnPtr(Box::new(FnPtr::new(""))),
+            _=>continue
+        };
+        check_type(&Value::Immutable(value),&declaration.declaration_type,||argument_target(&declaration.name,&f.name))
+            .map_err(|e|e.with_position(&arg.position()))?;
+    }
+    Ok(())
+}
+/// 参数类型不符时的错误描述
+pub fn argument_target(arg:&str,function:&str)->String{
+    format!("argument \"{arg}\" of function \"{function}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::PipelineEngine;
+    use super::*;
+
+    fn run(script:&str)->PipelineResult<Value>{
+        PipelineEngine::default().run_script(script)
+    }
+
+    #[test]
+    fn checks_constant_arguments_of_script_functions(){
+        let e=run("fun f(a:Int){ return a }\nf(\"x\")").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::TypeMismatch(_,expected,actual) if expected=="Int"&&actual=="String"),"{e}");
+        assert_eq!(run("fun f(a:Float){ return a }\nreturn f(1)").unwrap().as_dynamic(),Dynamic::Integer(1));
+    }
+    #[test]
+    fn skips_method_calls_resolved_at_runtime(){
+        let script="fun contains(a:Array,x:String){ return false }\nreturn \"abc\".contains(\"b\")";
+        assert_eq!(run(script).unwrap().as_bool(),Some(true));
+    }
+    #[test]
+    fn matches_declared_types(){
+        assert!(type_matches(&Dynamic::Integer(1),"Float"));
+        assert!(!type_matches(&Dynamic::Float(1.0),"Int"));
+        assert!(type_matches(&Dynamic::Unit,"String?"));
+        assert!(!type_matches(&Dynamic::Unit,"String"));
+        assert!(type_matches(&Dynamic::Unit,"Unit"));
+        assert!(type_matches(&Dynamic::Boolean(true),"Any"));
+    }
+    #[test]
+    fn checks_arguments_and_return_values_at_runtime(){
+        let e=run("fun f(a:Int){ return a }\nlet s=\"x\"\nf(s)").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::TypeMismatch(t,_,_) if t.contains("\"a\"")),"{e}");
+        let e=run("fun f(a:Any):Int{ return a }\nreturn f(\"x\")").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::TypeMismatch(_,expected,actual) if expected=="Int"&&actual=="String"),"{e}");
+        let e=run("fun f(a:String){ return a }\nreturn f(null)").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::TypeMismatch(_,_,actual) if actual=="null"));
+        assert_eq!(e.hint().unwrap(),"append ? to the declared type to accept null.");
+        assert!(run("fun f(a:String?){ return a }\nreturn f(null)").is_ok());
+    }
+    #[test]
+    fn checks_class_properties(){
+        let e=run("class P(port:Int)\nlet p=P(\"80\")").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::TypeMismatch(..)),"{e}");
+        let e=run("class P(port:Port)").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::UnknownType(t,owner) if t=="Port"&&owner=="class \"P\""));
+        let e=run("fun f(a:Strin){ return a }").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::UnknownType(t,_) if t=="Strin"));
+    }
+}
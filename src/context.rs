@@ -2,11 +2,13 @@ use std::any::Any;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock, Weak};
 use std::thread::JoinHandle;
-use crate::error::PipelineResult;
+use std::time::{Duration, Instant};
+use crate::error::{PipelineResult, SourceFile};
 
 
 use crate::logger::PipelineLogger;
 use crate::module::Module;
+use crate::task::{Task, TaskStates};
 use crate::v1::position::Position;
 use crate::v1::types::{Dynamic, Value};
 
@@ -50,7 +52,7 @@ impl<T> Context<T> for EmptyContext{
         None
     }
 }
-#[derive(Debug)]
+#[derive(Debug,Clone)]
 pub struct AppContext<T>{
     map:HashMap<String,T>,
 }
@@ -77,7 +79,15 @@ pub enum PipelineContextValue{
     Logger(Arc<RwLock<PipelineLogger>>),
     SharedModule(Arc<RwLock<Module>>),
     Modules(Arc<RwLock<HashMap<String,Module>>>),
-    Native(Arc<RwLock<dyn Any+Send+Sync>>)
+    Native(Arc<RwLock<dyn Any+Send+Sync>>),
+    TaskStates(Arc<RwLock<TaskStates>>),
+    Tasks(Arc<RwLock<Vec<Task>>>),
+    /// dry-run模式下当前任务将要执行的操作
+    Plan(Arc<RwLock<Vec<String>>>),
+    /// 设置了timeout的任务的(截止时间,超时时间)，到达截止时间时正在执行的命令会被终止
+    Deadline(Instant,Duration),
+    /// 当前正在执行的脚本，调用导入模块中的函数时为模块文件
+    Source(Arc<SourceFile>)
 }
 #[derive(Debug,Clone)]
 pub struct Scope{
@@ -89,7 +99,19 @@ impl Scope {
     pub fn new()->Self{
         Self{data:HashMap::new(),parent:None}
     }
+    /// 以p为上级作用域创建一个新的作用域
+    pub fn with_parent(p:Arc<RwLock<Scope>>)->Self{
+        Self{data:HashMap::new(),parent:Some(p)}
+    }
     pub fn set_parent(&mut self,p:Arc<RwLock<Scope>>){self.parent=Some(p)}
+    /// 沿上级作用域找到最外层的全局作用域
+    pub fn global(scope:&Arc<RwLock<Scope>>)->Arc<RwLock<Scope>>{
+        let parent=scope.read().unwrap().parent.clone();
+        match parent {
+            None => scope.clone(),
+            Some(p) => Scope::global(&p)
+        }
+    }
     pub fn get(&self, key:&str) ->Option<Value>{
         let r=self.data.get(key);
         match r {
@@ -151,6 +173,18 @@ impl PipelineContextValue{
             _=>None
         }
     }
+    pub fn as_deadline(&self)->Option<(Instant,Duration)>{
+        match self {
+            PipelineContextValue::Deadline(d,t)=>Some((*d,*t)),
+            _=>None
+        }
+    }
+    pub fn as_source(&self)->Option<Arc<SourceFile>>{
+        match self {
+            PipelineContextValue::Source(s)=>Some(s.clone()),
+            _=>None
+        }
+    }
     pub fn as_local(&self)->Option<String>{
         match self {
             PipelineContextValue::Local(s)=>Some( s.clone()),
@@ -175,4 +209,22 @@ impl PipelineContextValue{
             _=>None
         }
     }
+    pub fn as_task_states(&self)->Option<Arc<RwLock<TaskStates>>>{
+        match self {
+            PipelineContextValue::TaskStates(s)=>Some( s.clone()),
+            _=>None
+        }
+    }
+    pub fn as_tasks(&self)->Option<Arc<RwLock<Vec<Task>>>>{
+        match self {
+            PipelineContextValue::Tasks(s)=>Some( s.clone()),
+            _=>None
+        }
+    }
+    pub fn as_plan(&self)->Option<Arc<RwLock<Vec<String>>>>{
+        match self {
+            PipelineContextValue::Plan(s)=>Some( s.clone()),
+            _=>None
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,475 @@
+use indexmap::IndexMap;
+use crate::error::{PipelineError, PipelineResult};
+use crate::v1::types::{Dynamic, Struct, Value};
+
+/// 对象的属性，按属性名排序使输出稳定
+fn struct_props(s:&Struct)->Vec<(&String,&Value)>{
+    let mut props:Vec<_>=s.get_props().iter().collect();
+    props.sort_by(|a,b|a.0.cmp(b.0));
+    props
+}
+/// 将值转换为JSON，Map的键转换为字符串，对象转换为以属性名为键的JSON对象，Range转换为数组
+pub fn to_json(value:&Dynamic)->PipelineResult<serde_json::Value>{
+    Ok(match value {
+        Dynamic::Unit=>serde_json::Value::Null,
+        Dynamic::Integer(i)=>(*i).into(),
+        Dynamic::Float(f)=>{
+            let n=serde_json::Number::from_f64(*f)
+                .ok_or(PipelineError::InvalidArgument(format!("{f} can not be represented in JSON")))?;
+            serde_json::Value::Number(n)
+        }
+        Dynamic::String(s)=>s.clone().into(),
+        Dynamic::Boolean(b)=>(*b).into(),
+        Dynamic::Array(a)=>{
+            serde_json::Value::Array(a.iter().map(|i|to_json(&i.as_dynamic())).collect::<PipelineResult<_>>()?)
+        }
+        Dynamic::Range(r)=>serde_json::Value::Array(r.iter().map(|i|i.into()).collect()),
+        Dynamic::Map(m)=>{
+            let mut object=serde_json::Map::new();
+            for (k,v) in m{
+                object.insert(k.to_string(),to_json(&v.as_dynamic())?);
+            }
+            serde_json::Value::Object(object)
+        }
+        Dynamic::Struct(s)=>{
+            let mut object=serde_json::Map::new();
+            for (k,v) in struct_props(s){
+                object.insert(k.clone(),to_json(&v.as_dynamic())?);
+            }
+            serde_json::Value::Object(object)
+        }
+        t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+    })
+}
+/// 将JSON转换为值，对象的键保持文档中的顺序。
+/// 超出Int范围的整数不能无损地转换，返回错误而不是转换为Float丢失精度
+pub fn from_json(value:serde_json::Value)->PipelineResult<Value>{
+    Ok(match value {
+        serde_json::Value::Null=>().into(),
+        serde_json::Value::Bool(b)=>b.into(),
+        serde_json::Value::Number(n)=>match (n.as_i64(),n.as_u64()) {
+            (Some(i),_)=>i.into(),
+            (None,Some(u))=>return Err(PipelineError::InvalidArgument(format!("integer {u} is out of range of Int"))),
+            _=>n.as_f64().unwrap_or(f64::NAN).into()
+        },
+        serde_json::Value::String(s)=>s.into(),
+        serde_json::Value::Array(a)=>{
+            Value::with_mutable(Dynamic::Array(a.into_iter().map(from_json).collect::<PipelineResult<_>>()?))
+        }
+        serde_json::Value::Object(o)=>{
+            let m=o.into_iter().map(|(k,v)|Ok((Dynamic::String(k),from_json(v)?))).collect::<PipelineResult<IndexMap<_,_>>>()?;
+            Value::with_mutable(Dynamic::Map(m))
+        }
+    })
+}
+/// 路径中的一段，a.b[0]依次为Key("a")、Key("b")、Index(0)
+#[derive(Debug,Clone,PartialEq)]
+pub enum PathSegment{
+    Key(String),
+    Index(i64)
+}
+/// 解析a.b[0]形式的路径
+pub fn parse_path(path:&str)->PipelineResult<Vec<PathSegment>>{
+    let invalid=||PipelineError::InvalidArgument(format!("invalid path \"{path}\""));
+    let mut segments=vec![];
+    let mut chars=path.chars().peekable();
+    let mut key=String::new();
+    while let Some(c)=chars.next(){
+        match c {
+            '.'=>{
+                if !key.is_empty(){
+                    segments.push(PathSegment::Key(std::mem::take(&mut key)));
+                }else if !matches!(segments.last(),Some(PathSegment::Index(_))){
+                    return Err(invalid())
+                }
+            }
+            '['=>{
+                if !key.is_empty(){
+                    segments.push(PathSegment::Key(std::mem::take(&mut key)));
+                }
+                let mut index=String::new();
+                loop {
+                    match chars.next() {
+                        Some(']')=>break,
+                        Some(c)=>index.push(c),
+                        None=>return Err(invalid())
+                    }
+                }
+                segments.push(PathSegment::Index(index.trim().parse().map_err(|_|invalid())?));
+            }
+            c=>key.push(c)
+        }
+    }
+    if !key.is_empty(){
+        segments.push(PathSegment::Key(key));
+    }else if path.ends_with('.'){
+        return Err(invalid())
+    }
+    Ok(segments)
+}
+/// 按路径查找Map、Array或对象中的值，路径不存在时返回null
+pub fn lookup(value:&Value,path:&str)->PipelineResult<Value>{
+    let mut current=value.clone();
+    for segment in parse_path(path)?{
+        let next=match (current.as_dynamic(),segment) {
+            (Dynamic::Map(m),PathSegment::Key(k))=>m.get(&Dynamic::String(k)).cloned(),
+            (Dynamic::Struct(s),PathSegment::Key(k))=>s.get_prop(&k),
+            (Dynamic::Array(a),PathSegment::Index(i))=>usize::try_from(i).ok().and_then(|i|a.get(i).cloned()),
+            _=>None
+        };
+        match next {
+            Some(v)=>current=v,
+            None=>return Ok(().into())
+        }
+    }
+    Ok(current)
+}
+/// 将TOML转换为值，表的键保持文档中的顺序，日期时间转换为字符串
+pub fn from_toml(value:toml::Value)->Value{
+    match value {
+        toml::Value::String(s)=>s.into(),
+        toml::Value::Integer(i)=>i.into(),
+        toml::Value::Float(f)=>f.into(),
+        toml::Value::Boolean(b)=>b.into(),
+        toml::Value::Datetime(d)=>d.to_string().into(),
+        toml::Value::Array(a)=>{
+            Value::with_mutable(Dynamic::Array(a.into_iter().map(from_toml).collect()))
+        }
+        toml::Value::Table(t)=>{
+            let m:IndexMap<Dynamic,Value>=t.into_iter().map(|(k,v)|(Dynamic::String(k),from_toml(v))).collect();
+            Value::with_mutable(Dynamic::Map(m))
+        }
+    }
+}
+/// 将值转换为TOML，TOML中没有null，表中值为null的键会被省略
+pub fn to_toml(value:&Dynamic)->PipelineResult<toml::Value>{
+    Ok(match value {
+        Dynamic::Integer(i)=>toml::Value::Integer(*i),
+        Dynamic::Float(f)=>toml::Value::Float(*f),
+        Dynamic::String(s)=>toml::Value::String(s.clone()),
+        Dynamic::Boolean(b)=>toml::Value::Boolean(*b),
+        Dynamic::Array(a)=>{
+            toml::Value::Array(a.iter().map(|i|to_toml(&i.as_dynamic())).collect::<PipelineResult<_>>()?)
+        }
+        Dynamic::Range(r)=>toml::Value::Array(r.iter().map(toml::Value::Integer).collect()),
+        Dynamic::Map(m)=>{
+            let mut table=toml::Table::new();
+            for (k,v) in m{
+                let v=v.as_dynamic();
+                if !matches!(v,Dynamic::Unit){
+                    table.insert(k.to_string(),to_toml(&v)?);
+                }
+            }
+            toml::Value::Table(table)
+        }
+        Dynamic::Struct(s)=>{
+            let m=struct_props(s).into_iter().map(|(k,v)|(Dynamic::String(k.clone()),v.clone())).collect();
+            to_toml(&Dynamic::Map(m))?
+        }
+        Dynamic::Unit=>return Err(PipelineError::InvalidArgument("null can not be represented in TOML".into())),
+        t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+    })
+}
+/// 将值转换为toml_edit中的值，Map转换为内联表
+fn to_toml_edit(value:&Dynamic)->PipelineResult<toml_edit::Value>{
+    Ok(match to_toml(value)? {
+        toml::Value::String(s)=>s.into(),
+        toml::Value::Integer(i)=>i.into(),
+        toml::Value::Float(f)=>f.into(),
+        toml::Value::Boolean(b)=>b.into(),
+        toml::Value::Datetime(d)=>d.to_string().into(),
+        toml::Value::Array(a)=>{
+            let items=a.iter().map(|i|to_toml_edit(&from_toml(i.clone()).as_dynamic())).collect::<PipelineResult<Vec<_>>>()?;
+            toml_edit::Value::Array(items.into_iter().collect())
+        }
+        toml::Value::Table(t)=>{
+            let mut table=toml_edit::InlineTable::new();
+            for (k,v) in t{
+                table.insert(k.as_str(),to_toml_edit(&from_toml(v).as_dynamic())?);
+            }
+            toml_edit::Value::InlineTable(table)
+        }
+    })
+}
+/// 修改TOML文档中path处的值，保留文档中的注释、顺序和格式，不存在的键和表会被创建
+pub fn set_toml(document:&mut toml_edit::DocumentMut,path:&str,value:&Dynamic)->PipelineResult<()>{
+    let segments=parse_path(path)?;
+    if segments.is_empty(){
+        return Err(PipelineError::InvalidArgument(format!("invalid path \"{path}\"")))
+    }
+    set_toml_item(document.as_item_mut(),&segments,value,path)
+}
+fn set_toml_item(item:&mut toml_edit::Item,segments:&[PathSegment],value:&Dynamic,path:&str)->PipelineResult<()>{
+    let unsupported=||PipelineError::InvalidArgument(format!("can not set \"{path}\" in TOML"));
+    let Some((first,rest))=segments.split_first() else {
+        let mut new=to_toml_edit(value)?;
+        //保留原来的值前后的空白和注释
+        if let Some(old)=item.as_value(){
+            *new.decor_mut()=old.decor().clone();
+        }
+        *item=toml_edit::Item::Value(new);
+        return Ok(())
+    };
+    match first {
+        PathSegment::Key(key)=>{
+            if item.is_none(){
+                let mut table=toml_edit::Table::new();
+                table.set_implicit(true);
+                *item=toml_edit::Item::Table(table);
+            }
+            let table=item.as_table_like_mut().ok_or_else(unsupported)?;
+            let child=table.entry(key).or_insert(toml_edit::Item::None);
+            set_toml_item(child,rest,value,path)
+        }
+        PathSegment::Index(index)=>{
+            let out_of_bounds=|len:usize|PipelineError::IndexOutOfBounds(*index,len);
+            match item {
+                //[[bin]]这样的表数组
+                toml_edit::Item::ArrayOfTables(tables)=>{
+                    let len=tables.len();
+                    let table=usize::try_from(*index).ok().and_then(|i|tables.get_mut(i)).ok_or(out_of_bounds(len))?;
+                    let mut child=toml_edit::Item::Table(std::mem::replace(table,toml_edit::Table::new()));
+                    let r=set_toml_item(&mut child,rest,value,path);
+                    if let toml_edit::Item::Table(t)=child{
+                        *table=t;
+                    }
+                    r
+                }
+                toml_edit::Item::Value(toml_edit::Value::Array(array)) if rest.is_empty()=>{
+                    let len=array.len();
+                    let old=usize::try_from(*index).ok().and_then(|i|array.get_mut(i)).ok_or(out_of_bounds(len))?;
+                    let mut new=to_toml_edit(value)?;
+                    *new.decor_mut()=old.decor().clone();
+                    *old=new;
+                    Ok(())
+                }
+                _=>Err(unsupported())
+            }
+        }
+    }
+}
+/// 将YAML转换为值，带tag的值只保留其内容，超出Int范围的整数与from_json一样报错
+pub fn from_yaml(value:serde_yaml::Value)->PipelineResult<Value>{
+    Ok(match value {
+        serde_yaml::Value::Null=>().into(),
+        serde_yaml::Value::Bool(b)=>b.into(),
+        serde_yaml::Value::Number(n)=>match (n.as_i64(),n.as_u64()) {
+            (Some(i),_)=>i.into(),
+            (None,Some(u))=>return Err(PipelineError::InvalidArgument(format!("integer {u} is out of range of Int"))),
+            _=>n.as_f64().unwrap_or(f64::NAN).into()
+        },
+        serde_yaml::Value::String(s)=>s.into(),
+        serde_yaml::Value::Sequence(a)=>{
+            Value::with_mutable(Dynamic::Array(a.into_iter().map(from_yaml).collect::<PipelineResult<_>>()?))
+        }
+        serde_yaml::Value::Mapping(m)=>{
+            let m=m.into_iter().map(|(k,v)|Ok((from_yaml(k)?.as_dynamic(),from_yaml(v)?))).collect::<PipelineResult<IndexMap<_,_>>>()?;
+            Value::with_mutable(Dynamic::Map(m))
+        }
+        serde_yaml::Value::Tagged(t)=>from_yaml(t.value)?
+    })
+}
+/// 将值转换为YAML，Map的键按插入顺序输出
+pub fn to_yaml(value:&Dynamic)->PipelineResult<serde_yaml::Value>{
+    Ok(match value {
+        Dynamic::Unit=>serde_yaml::Value::Null,
+        Dynamic::Integer(i)=>(*i).into(),
+        Dynamic::Float(f)=>(*f).into(),
+        Dynamic::String(s)=>s.clone().into(),
+        Dynamic::Boolean(b)=>(*b).into(),
+        Dynamic::Array(a)=>{
+            serde_yaml::Value::Sequence(a.iter().map(|i|to_yaml(&i.as_dynamic())).collect::<PipelineResult<_>>()?)
+        }
+        Dynamic::Range(r)=>serde_yaml::Value::Sequence(r.iter().map(|i|i.into()).collect()),
+        Dynamic::Map(m)=>{
+            let mut mapping=serde_yaml::Mapping::new();
+            for (k,v) in m{
+                mapping.insert(to_yaml(k)?,to_yaml(&v.as_dynamic())?);
+            }
+            serde_yaml::Value::Mapping(mapping)
+        }
+        Dynamic::Struct(s)=>{
+            let m=struct_props(s).into_iter().map(|(k,v)|(Dynamic::String(k.clone()),v.clone())).collect();
+            to_yaml(&Dynamic::Map(m))?
+        }
+        t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+    })
+}
+/// 解析.env文件，每行为KEY=VALUE，支持#注释、export前缀以及单引号和双引号包裹的值，
+/// 双引号中可以使用\n、\t、\"和\\转义，未加引号的值中空格后的#开始的内容为注释。
+/// KEY只能由字母、数字、_和.组成
+pub fn parse_env_file(text:&str)->PipelineResult<Vec<(String,String)>>{
+    parse_key_value_lines(text,|key|key.chars().all(|c|c.is_ascii_alphanumeric()||c=='_'||c=='.'))
+}
+/// 按.env格式逐行解析KEY=VALUE，valid_key检查KEY是否合法
+fn parse_key_value_lines(text:&str,valid_key:impl Fn(&str)->bool)->PipelineResult<Vec<(String,String)>>{
+    let mut vars=vec![];
+    for (row,line) in text.lines().enumerate(){
+        let line=line.trim();
+        if line.is_empty()||line.starts_with('#'){
+            continue
+        }
+        let invalid=||PipelineError::InvalidArgument(format!("invalid env line {}: \"{line}\"",row+1));
+        let line=line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+        let (key,value)=line.split_once('=').ok_or_else(invalid)?;
+        let key=key.trim();
+        if key.is_empty()||!valid_key(key){
+            return Err(invalid())
+        }
+        let value=value.trim();
+        let value=if let Some(rest)=value.strip_prefix('"'){
+            let mut v=String::new();
+            let mut chars=rest.chars();
+            loop {
+                match chars.next() {
+                    Some('"')=>break,
+                    Some('\\')=>match chars.next() {
+                        Some('n')=>v.push('\n'),
+                        Some('t')=>v.push('\t'),
+                        Some(c)=>v.push(c),
+                        None=>return Err(invalid())
+                    },
+                    Some(c)=>v.push(c),
+                    None=>return Err(invalid())
+                }
+            }
+            v
+        }else if let Some(rest)=value.strip_prefix('\''){
+            rest.split_once('\'').ok_or_else(invalid)?.0.to_string()
+        }else{
+            match value.find(" #") {
+                Some(i)=>value[..i].trim_end().to_string(),
+                None=>value.to_string()
+            }
+        };
+        vars.push((key.to_string(),value));
+    }
+    Ok(vars)
+}
+/// 解析--params-file和--answers-file指定的文件，按扩展名支持json、toml和yaml，其余按.env格式解析，
+/// 此时KEY可以是任意不含空白字符的文本(例如提示的问题)。
+/// 值只能是String、Int、Float或Bool，统一转换为字符串，由param或readInt等函数转换为需要的类型
+pub fn parse_key_value_file(path:&str,text:&str)->PipelineResult<Vec<(String,String)>>{
+    let invalid=|e:String|PipelineError::InvalidArgument(format!("invalid file {path}: {e}"));
+    let value=match path.rsplit_once('.').map(|(_,ext)|ext) {
+        Some("json")=>from_json(serde_json::from_str(text).map_err(|e|invalid(e.to_string()))?)?,
+        Some("toml")=>from_toml(toml::Value::Table(text.parse::<toml::Table>().map_err(|e|invalid(e.to_string()))?)),
+        Some("yaml"|"yml")=>from_yaml(serde_yaml::from_str(text).map_err(|e|invalid(e.to_string()))?)?,
+        _=>return parse_key_value_lines(text,|key|!key.chars().any(char::is_whitespace))
+    };
+    let m=match value.as_dynamic() {
+        Dynamic::Map(m)=>m,
+        t=>return Err(invalid(format!("expected a map,but got {}",t.type_name())))
+    };
+    let mut params=vec![];
+    for (k,v) in m{
+        let v=match v.as_dynamic() {
+            v@(Dynamic::String(_)|Dynamic::Integer(_)|Dynamic::Float(_)|Dynamic::Boolean(_))=>v.to_string(),
+            t=>return Err(invalid(format!("\"{k}\" can not be {}",t.type_name())))
+        };
+        params.push((k.to_string(),v));
+    }
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_paths(){
+        assert_eq!(parse_path("a.b[0].c").unwrap(),vec![
+            PathSegment::Key("a".into()),
+            PathSegment::Key("b".into()),
+            PathSegment::Index(0),
+            PathSegment::Key("c".into())
+        ]);
+        assert_eq!(parse_path("[1][2]").unwrap(),vec![PathSegment::Index(1),PathSegment::Index(2)]);
+        assert!(parse_path("a..b").is_err());
+        assert!(parse_path("a.").is_err());
+        assert!(parse_path("a[x]").is_err());
+        assert!(parse_path("a[0").is_err());
+    }
+    #[test]
+    fn json_keeps_key_order(){
+        let text=r#"{"zeta":1,"alpha":{"y":true,"b":null},"mid":[1.5,"s"]}"#;
+        let value=from_json(serde_json::from_str(text).unwrap()).unwrap();
+        assert_eq!(serde_json::to_string(&to_json(&value.as_dynamic()).unwrap()).unwrap(),text);
+    }
+    #[test]
+    fn json_rejects_integers_out_of_range(){
+        let value=from_json(serde_json::from_str("9223372036854775807").unwrap()).unwrap();
+        assert_eq!(value.as_dynamic().as_integer(),Some(i64::MAX));
+        assert!(from_json(serde_json::from_str("[12345678901234567890]").unwrap()).is_err());
+    }
+    #[test]
+    fn yaml_rejects_integers_out_of_range(){
+        let value=from_yaml(serde_yaml::from_str("a: -9223372036854775808").unwrap()).unwrap();
+        assert_eq!(lookup(&value,"a").unwrap().as_integer(),Some(i64::MIN));
+        let e=from_yaml(serde_yaml::from_str("a: [12345678901234567890]").unwrap()).unwrap_err();
+        assert!(matches!(e,PipelineError::InvalidArgument(m) if m.contains("12345678901234567890")));
+    }
+    #[test]
+    fn looks_up_paths(){
+        let value=from_json(serde_json::from_str(r#"{"a":{"b":[10,{"c":"x"}]}}"#).unwrap()).unwrap();
+        assert_eq!(lookup(&value,"a.b[1].c").unwrap().as_string(),Some("x".into()));
+        assert!(matches!(lookup(&value,"a.missing[3]").unwrap().as_dynamic(),Dynamic::Unit));
+    }
+    #[test]
+    fn set_toml_preserves_formatting(){
+        let text="# package\n[package]\nname = \"demo\" # name\nversion = \"0.1.0\"\n\n[[bin]]\nname = \"a\"\n";
+        let mut document:toml_edit::DocumentMut=text.parse().unwrap();
+        set_toml(&mut document,"package.version",&Dynamic::from("0.2.0")).unwrap();
+        set_toml(&mut document,"package.name",&Dynamic::from("app")).unwrap();
+        set_toml(&mut document,"bin[0].path",&Dynamic::from("src/a.rs")).unwrap();
+        set_toml(&mut document,"dependencies.serde",&Dynamic::from("1.0")).unwrap();
+        assert_eq!(document.to_string(),"# package\n[package]\nname = \"app\" # name\nversion = \"0.2.0\"\n\n[[bin]]\nname = \"a\"\npath = \"src/a.rs\"\n\n[dependencies]\nserde = \"1.0\"\n");
+    }
+    #[test]
+    fn set_toml_rejects_invalid_targets(){
+        let mut document:toml_edit::DocumentMut="a = 1\nb = [1, 2]\n[[bin]]\nname = \"a\"\n".parse().unwrap();
+        assert!(set_toml(&mut document,"a.b",&Dynamic::from("x")).is_err());
+        assert!(set_toml(&mut document,"",&Dynamic::from("x")).is_err());
+        assert!(matches!(set_toml(&mut document,"bin[3].name",&Dynamic::from("x")),Err(PipelineError::IndexOutOfBounds(3,1))));
+        set_toml(&mut document,"b[1]",&Dynamic::Integer(5)).unwrap();
+        assert_eq!(document.to_string(),"a = 1\nb = [1, 5]\n[[bin]]\nname = \"a\"\n");
+    }
+    #[test]
+    fn toml_and_yaml_keep_key_order(){
+        let value=from_toml(toml::Value::Table("z = 1\na = 2\n".parse::<toml::Table>().unwrap()));
+        assert_eq!(toml::to_string(&to_toml(&value.as_dynamic()).unwrap()).unwrap(),"z = 1\na = 2\n");
+        let value=from_yaml(serde_yaml::from_str("z: 1\na:\n  y: true\n  b: null\n").unwrap()).unwrap();
+        assert_eq!(serde_yaml::to_string(&to_yaml(&value.as_dynamic()).unwrap()).unwrap(),"z: 1\na:\n  y: true\n  b: null\n");
+    }
+    #[test]
+    fn parses_env_files(){
+        let text="# comment\n\nexport A=1\nB = \"x\\ny \\\"q\\\"\"\nC='a b # c'\nD=v # comment\nE=a#b\nF=\n";
+        assert_eq!(parse_env_file(text).unwrap(),vec![
+            ("A".to_string(),"1".to_string()),
+            ("B".to_string(),"x\ny \"q\"".to_string()),
+            ("C".to_string(),"a b # c".to_string()),
+            ("D".to_string(),"v".to_string()),
+            ("E".to_string(),"a#b".to_string()),
+            ("F".to_string(),"".to_string())
+        ]);
+    }
+    #[test]
+    fn rejects_invalid_env_lines(){
+        assert!(parse_env_file("A").is_err());
+        assert!(parse_env_file("=1").is_err());
+        assert!(parse_env_file("A B=1").is_err());
+        assert!(parse_env_file("数据库=1").is_err());
+        assert!(parse_env_file("A=\"unterminated").is_err());
+        assert!(parse_env_file("A='unterminated").is_err());
+    }
+    #[test]
+    fn key_value_files_accept_any_key_without_whitespace(){
+        assert_eq!(parse_key_value_file("answers.env","数据库=mysql\n").unwrap(),vec![("数据库".to_string(),"mysql".to_string())]);
+        assert!(parse_key_value_file("answers.env","a b=1\n").is_err());
+        assert_eq!(parse_key_value_file("params.json",r#"{"n":1,"b":true}"#).unwrap(),vec![
+            ("n".to_string(),"1".to_string()),
+            ("b".to_string(),"true".to_string())
+        ]);
+        assert!(parse_key_value_file("params.json",r#"{"a":[1]}"#).is_err());
+    }
+}
@@ -5,12 +5,15 @@ use std::io::Read;
 use std::rc::Weak;
 use std::sync::{Arc,RwLock};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use scanner_rust::Scanner;
+use crate::check::check_types;
 use crate::context::{AppContext, Context, EmptyContext, Scope, ValueContext};
 use crate::context::PipelineContextValue;
-use crate::error::{ PipelineResult};
+use crate::error::{PipelineResult, SourceFile};
 use crate::logger::PipelineLogger;
 use crate::module::Module;
+use crate::task::{Task, TaskStates};
 use crate::v1::ast::AST;
 use crate::v1::expr::{Expr, FnCallExpr};
 use crate::v1::interpreter::{  Interpreter};
@@ -105,6 +108,15 @@ impl PipelineEngine{
         let scope=join.as_scope().unwrap();
         scope
     }
+    /// 以scope作为当前作用域
+    pub fn with_scope(ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,scope:Scope)->Arc<RwLock<dyn Context<PipelineContextValue>>>{
+        PipelineEngine::with_value(ctx,"$scope",PipelineContextValue::Scope(Arc::new(RwLock::new(scope))))
+    }
+    /// 在当前作用域之下创建一个块作用域，块中声明的变量在块结束后不可见
+    pub fn with_block_scope(ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>)->Arc<RwLock<dyn Context<PipelineContextValue>>>{
+        let scope=Scope::with_parent(PipelineEngine::context_with_scope(&ctx));
+        PipelineEngine::with_scope(ctx,scope)
+    }
     pub  fn context_with_logger(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,key:&str)->PipelineContextValue{
         let  join =ctx.read().unwrap().value(key).unwrap();
         return join
@@ -125,6 +137,28 @@ impl PipelineEngine{
         let  join =ctx.read().unwrap().value(key).unwrap();
         return join.as_native().unwrap()
     }
+    pub  fn context_with_task_states(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->Option<Arc<RwLock<TaskStates>>>{
+        let  states =ctx.read().unwrap().value("$task_states");
+        states.and_then(|s|s.as_task_states())
+    }
+    pub  fn context_with_tasks(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->Option<Arc<RwLock<Vec<Task>>>>{
+        let  tasks =ctx.read().unwrap().value("$tasks");
+        tasks.and_then(|s|s.as_tasks())
+    }
+    pub  fn context_with_plan(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->Option<Arc<RwLock<Vec<String>>>>{
+        let  plan =ctx.read().unwrap().value("$plan");
+        plan.and_then(|s|s.as_plan())
+    }
+    /// 当前任务的截止时间和超时时间，任务没有设置timeout时为None
+    pub  fn context_with_deadline(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->Option<(Instant,Duration)>{
+        let  deadline =ctx.read().unwrap().value("$deadline");
+        deadline.and_then(|d|d.as_deadline())
+    }
+    /// 当前正在执行的脚本，没有关联脚本文件(例如直接执行的表达式)时为None
+    pub  fn context_with_source(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->Option<Arc<SourceFile>>{
+        let  source =ctx.read().unwrap().value("$source");
+        source.and_then(|s|s.as_source())
+    }
     pub  fn context_with_env(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->Arc<RwLock<HashMap<String,String>>>{
         let  join =ctx.read().unwrap().value("$env");
         match join {
@@ -140,15 +174,18 @@ impl PipelineEngine{
         global.set_value("workspace","./".into());
         global.set_value("path_pipeline","all".into());
         global.set_value("path_task","all".into());
+        global.set_value("dry_run","false".into());
         let ctx=Arc::new(RwLock::new(ValueContext::with_value(empty,"$global_state",PipelineContextValue::GlobalState(Arc::new(RwLock::new(global))))));
         let ctx=Arc::new(RwLock::new(ValueContext::with_value(ctx,"logger",PipelineContextValue::Logger(Arc::new(RwLock::new(PipelineLogger::new()))))));
         //全局作用域
         let mut scope=Scope::new();
         scope.set("true",true.into());
         scope.set("false",false.into());
+        scope.set("null",().into());
         let ctx=PipelineEngine::with_value(ctx,"$scope",PipelineContextValue::Scope(Arc::new(RwLock::new(scope))));
         let ctx=PipelineEngine::with_value(ctx,"$sc",PipelineContextValue::Native(Arc::new(RwLock::new(Scanner::new(io::stdin())))));
-        // let ctx=PipelineEngine::with_value(ctx,"$env",PipelineContextValue::Env(Arc::new(RwLock::new(HashMap::new()))));
+        //任务之外执行cmd时使用的环境变量，任务中会使用各自独立的环境变量
+        let ctx=PipelineEngine::with_value(ctx,"$env",PipelineContextValue::Env(Arc::new(RwLock::new(HashMap::new()))));
 
         return ctx
     }
@@ -184,7 +221,7 @@ impl PipelineEngine{
         for (_,class) in self.interpreter.main_module.read().unwrap().get_classes(){
             self.parser.register_predefined_class(class.clone());
         }
-        let stmts=self.parser.parse_stmt_blocks()?;
+        let stmts=self.parser.parse_stmt_blocks().map_err(|e|e.with_position(&self.parser.last_position()))?;
         let classes=self.parser.get_classes();
         for (_,class) in classes{
             self.interpreter.main_module.write().unwrap().register_class(class.clone())
@@ -198,6 +235,7 @@ impl PipelineEngine{
         for lib in &self.fn_lib{
             self.interpreter.main_module.write().unwrap().register_script_function(lib.clone().name,lib.clone());
         }
+        check_types(&stmts,&self.fn_lib,self.interpreter.main_module.read().unwrap().get_classes())?;
         return Ok(stmts)
     }
     #[allow(unused)]
@@ -225,6 +263,9 @@ impl PipelineEngine{
         let a=self.interpreter.eval_expr(ctx,expr).unwrap();;
         return Ok(a)
     }
+    pub  fn eval_expr_from_ast_with_context(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,expr:Expr)->PipelineResult<Value>{
+        self.interpreter.eval_expr(ctx,expr)
+    }
     #[allow(unused)]
     pub  fn eval_expr(&mut self,script:impl AsRef<str>)->PipelineResult<Value>{
         let lexer=Lexer::from_script(script);
@@ -266,4 +307,17 @@ impl PipelineEngine{
         let r=self.interpreter.eval_fn_call_expr_with_context(ctx,expr);
         return r
     }
-}
\ No newline at end of file
+}#[cfg(test)]
+impl PipelineEngine {
+    /// 编译并在新的上下文中执行脚本，返回第一个非Unit的语句结果(例如顶层的return)，供单元测试使用
+    pub fn run_script(&mut self,script:&str)->PipelineResult<Value>{
+        self.run_script_with_context(PipelineEngine::background(),script)
+    }
+    /// 在指定的上下文中编译并执行脚本，用于测试dry-run等需要预先设置上下文的场景
+    pub fn run_script_with_context(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,script:&str)->PipelineResult<Value>{
+        let source=SourceFile::new("test.kts",script);
+        let stmts=self.compile_stmt_blocks(script).map_err(|e|e.with_source(&source))?;
+        let ctx=PipelineEngine::with_value(ctx,"$source",PipelineContextValue::Source(source.clone()));
+        self.eval_stmt_blocks_from_ast_with_context(ctx,stmts).map_err(|e|e.with_source(&source))
+    }
+}
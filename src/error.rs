@@ -1,5 +1,22 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::v1::position::Position;
+use crate::v1::token::Token;
+use crate::v1::types::Value;
 
 pub type PipelineResult<T>=Result<T,PipelineError>;
+/// 编译过的脚本文件，保存编译时的源码，渲染错误时不需要重新读取文件
+#[derive(Debug)]
+pub struct SourceFile{
+    pub path:String,
+    pub text:String
+}
+impl SourceFile {
+    pub fn new(path:impl Into<String>,text:impl Into<String>)->Arc<Self>{
+        Arc::new(Self{path:path.into(),text:text.into()})
+    }
+}
 #[derive(Debug,Clone)]
 pub enum PipelineError{
     FunctionUndefined(String),
@@ -9,6 +26,287 @@ pub enum PipelineError{
     UnexpectedToken(crate::v1::token::Token),
     UnusedKeyword(String),
     UnknownModule(String),
-    UndefinedOperation(String)
+    UndefinedOperation(String),
+    /// 命令以非0状态码退出，(命令,状态码)
+    CommandFailed(String,i64),
+    /// pipeline中存在失败的任务，(pipeline名,失败的任务名)
+    TaskFailed(String,Vec<String>),
+    /// pipeline中不存在该任务
+    UnknownTask(String),
+    /// 任务依赖形成了环，按依赖顺序记录环上的任务
+    DependencyCycle(Vec<String>),
+    /// 同一个pipeline中声明了同名的任务
+    DuplicateTask(String),
+    /// 路径不存在
+    PathNotFound(String),
+    /// 下标越界，(下标,长度)
+    IndexOutOfBounds(i64,usize),
+    /// Map中不存在该键
+    KeyNotFound(String),
+    /// 函数参数个数不匹配，(函数名,期望的参数个数,实际的参数个数)
+    ArityMismatch(String,usize,usize),
+    /// 对不可变的值赋值
+    NotMutable(String),
+    /// 对象上不存在该属性，(类型名,属性名)
+    PropertyUndefined(String,String),
+    /// 创建对象时缺少没有默认值的属性，(类型名,属性名)
+    PropertyRequired(String,String),
+    /// 整数除以0
+    DivisionByZero,
+    /// 整数运算的结果超出Int的范围，(溢出的运算)
+    IntegerOverflow(String),
+    /// 参数的值不合法
+    InvalidArgument(String),
+    /// 文件、命令等IO操作失败，(操作,原因)
+    IoFailed(String,String),
+    /// 脚本中通过throw抛出的值
+    Thrown(Value),
+    /// 值与声明的类型不符，(被检查的参数或返回值,声明的类型,实际的类型)
+    TypeMismatch(String,String,String),
+    /// 声明中使用了不存在的类型，(类型名,声明所在的函数或类)
+    UnknownType(String,String),
+    /// 没有默认值的pipeline参数未通过命令行传入
+    ParamRequired(String),
+    /// stdin不是终端且提示没有答案和默认值，(提示的键)
+    PromptUnanswered(String),
+    /// 命令或任务超时，(超时的命令或任务,超时时间)
+    TimedOut(String,Duration),
+    /// 带有位置的错误，(错误,出错的语句或表达式的位置,位置所在的脚本)，
+    /// 脚本为None时表示尚未关联到具体的脚本文件
+    Located(Box<PipelineError>,Position,Option<Arc<SourceFile>>)
 }
 
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::FunctionUndefined(name) => {
+                write!(f,"eval failed,function {name} undefined.")
+            }
+            PipelineError::VariableUndefined(name) => {
+                write!(f,"eval failed,variable \"{name}\" undefined.")
+            }
+            PipelineError::ExpectedType(s) => {
+                write!(f,"eval failed,expected type \"{s}\".")
+            }
+            PipelineError::UnexpectedType(s)=>{
+                write!(f,"eval failed,unexpected type \"{s}\".")
+            }
+            PipelineError::UnexpectedToken(Token::Invalid(message))=> {
+                write!(f,"parse failed,{message}.")
+            }
+            PipelineError::UnexpectedToken(t)=> {
+                write!(f,"parse failed,due to an unexpected token \"{t:?}\".")
+            }
+            PipelineError::UnusedKeyword(k)=>{
+                write!(f,"parse failed,due to an reserved and unimplemented keyword \"{k}\".")
+            }
+            PipelineError::UnknownModule(m)=>{
+                write!(f,"unknown module \"{m:}\".")
+            }
+            PipelineError::UndefinedOperation(msg)=>{
+                write!(f,"undefined operation \"{msg:}\".")
+            }
+            PipelineError::CommandFailed(command,code)=>{
+                write!(f,"command \"{command}\" exited with code {code}.")
+            }
+            PipelineError::TaskFailed(pipeline,tasks)=>{
+                write!(f,"pipeline \"{pipeline}\" failed,failed tasks: {}.",tasks.join(","))
+            }
+            PipelineError::UnknownTask(name)=>{
+                write!(f,"unknown task \"{name}\".")
+            }
+            PipelineError::DependencyCycle(tasks)=>{
+                write!(f,"task dependencies form a cycle: {}.",tasks.join(" -> "))
+            }
+            PipelineError::DuplicateTask(name)=>{
+                write!(f,"task \"{name}\" is declared more than once.")
+            }
+            PipelineError::PathNotFound(path)=>{
+                write!(f,"path \"{path}\" does not exist.")
+            }
+            PipelineError::IndexOutOfBounds(index,len)=>{
+                write!(f,"index {index} out of bounds for length {len}.")
+            }
+            PipelineError::KeyNotFound(key)=>{
+                write!(f,"key \"{key}\" not found.")
+            }
+            PipelineError::ArityMismatch(name,expected,actual)=>{
+                write!(f,"function {name} expected {expected} arguments,but got {actual}.")
+            }
+            PipelineError::NotMutable(target)=>{
+                write!(f,"\"{target}\" is immutable and can not be assigned.")
+            }
+            PipelineError::PropertyUndefined(class,prop)=>{
+                write!(f,"eval failed,property \"{prop}\" undefined in {class}.")
+            }
+            PipelineError::PropertyRequired(class,prop)=>{
+                write!(f,"property \"{prop}\" of {class} is required.")
+            }
+            PipelineError::DivisionByZero=>{
+                write!(f,"eval failed,division by zero.")
+            }
+            PipelineError::IntegerOverflow(op)=>{
+                write!(f,"eval failed,integer overflow in \"{op}\".")
+            }
+            PipelineError::InvalidArgument(msg)=>{
+                write!(f,"invalid argument,{msg}.")
+            }
+            PipelineError::IoFailed(op,reason)=>{
+                write!(f,"{op} failed: {reason}.")
+            }
+            PipelineError::Thrown(v)=>{
+                //重新抛出catch到的Error时只显示其中的message
+                let d=v.as_dynamic();
+                match d.as_struct().and_then(|s|s.get_prop("message")) {
+                    Some(message) if d.type_name()=="Error"=>write!(f,"{}",message.as_dynamic()),
+                    _=>write!(f,"{d}")
+                }
+            }
+            PipelineError::TypeMismatch(target,expected,actual)=>{
+                write!(f,"type mismatch,{target} expected \"{expected}\",but got \"{actual}\".")
+            }
+            PipelineError::UnknownType(name,owner)=>{
+                write!(f,"unknown type \"{name}\" in {owner}.")
+            }
+            PipelineError::ParamRequired(name)=>{
+                write!(f,"parameter \"{name}\" is required.")
+            }
+            PipelineError::PromptUnanswered(key)=>{
+                write!(f,"prompt \"{key}\" needs an answer,but stdin is not a terminal.")
+            }
+            PipelineError::TimedOut(target,timeout)=>{
+                write!(f,"{target} timed out after {timeout:?}.")
+            }
+            PipelineError::Located(e,_,_)=>{
+                write!(f,"{e}")
+            }
+        }
+    }
+}
+
+impl PipelineError {
+    /// 为错误附加位置，已经带有位置的错误保留更内层(更精确)的位置
+    pub fn with_position(self,pos:&Position)->Self{
+        match self {
+            PipelineError::Located(..)=>self,
+            e if pos.is_none()=>e,
+            e=>PipelineError::Located(Box::new(e),pos.clone(),None)
+        }
+    }
+    /// 将错误的位置关联到脚本文件，已经关联过的错误保持不变
+    pub fn with_source(self,source:&Arc<SourceFile>)->Self{
+        match self {
+            PipelineError::Located(e,pos,None)=>PipelineError::Located(e,pos,Some(source.clone())),
+            e=>e
+        }
+    }
+    /// 去掉位置信息后的错误
+    pub fn kind(&self)->&PipelineError{
+        match self {
+            PipelineError::Located(e,_,_)=>e.kind(),
+            e=>e
+        }
+    }
+    /// 错误的类型名，作为catch到的Error的kind
+    pub fn kind_name(&self)->&'static str{
+        match self.kind() {
+            PipelineError::FunctionUndefined(_)=>"FunctionUndefined",
+            PipelineError::VariableUndefined(_)=>"VariableUndefined",
+            PipelineError::ExpectedType(_)=>"ExpectedType",
+            PipelineError::UnexpectedType(_)=>"UnexpectedType",
+            PipelineError::UnexpectedToken(_)=>"UnexpectedToken",
+            PipelineError::UnusedKeyword(_)=>"UnusedKeyword",
+            PipelineError::UnknownModule(_)=>"UnknownModule",
+            PipelineError::UndefinedOperation(_)=>"UndefinedOperation",
+            PipelineError::CommandFailed(_,_)=>"CommandFailed",
+            PipelineError::TaskFailed(_,_)=>"TaskFailed",
+            PipelineError::UnknownTask(_)=>"UnknownTask",
+            PipelineError::DependencyCycle(_)=>"DependencyCycle",
+            PipelineError::DuplicateTask(_)=>"DuplicateTask",
+            PipelineError::PathNotFound(_)=>"PathNotFound",
+            PipelineError::IndexOutOfBounds(_,_)=>"IndexOutOfBounds",
+            PipelineError::KeyNotFound(_)=>"KeyNotFound",
+            PipelineError::ArityMismatch(_,_,_)=>"ArityMismatch",
+            PipelineError::NotMutable(_)=>"NotMutable",
+            PipelineError::PropertyUndefined(_,_)=>"PropertyUndefined",
+            PipelineError::PropertyRequired(_,_)=>"PropertyRequired",
+            PipelineError::DivisionByZero=>"DivisionByZero",
+            PipelineError::IntegerOverflow(_)=>"IntegerOverflow",
+            PipelineError::InvalidArgument(_)=>"InvalidArgument",
+            PipelineError::IoFailed(_,_)=>"IoFailed",
+            PipelineError::Thrown(_)=>"Thrown",
+            PipelineError::TypeMismatch(_,_,_)=>"TypeMismatch",
+            PipelineError::UnknownType(_,_)=>"UnknownType",
+            PipelineError::ParamRequired(_)=>"ParamRequired",
+            PipelineError::PromptUnanswered(_)=>"PromptUnanswered",
+            PipelineError::TimedOut(_,_)=>"TimedOut",
+            PipelineError::Located(e,_,_)=>e.kind_name()
+        }
+    }
+    /// 出错的位置
+    pub fn position(&self)->Option<&Position>{
+        match self {
+            PipelineError::Located(_,pos,_)=>Some(pos),
+            _=>None
+        }
+    }
+    /// 出错位置所在的脚本，尚未关联时为None
+    pub fn source(&self)->Option<&Arc<SourceFile>>{
+        match self {
+            PipelineError::Located(_,_,source)=>source.as_ref(),
+            _=>None
+        }
+    }
+    /// 针对错误给出的修复建议
+    pub fn hint(&self)->Option<String>{
+        let hint=match self.kind() {
+            PipelineError::FunctionUndefined(name) if name=="pipeline"=>"add 'import pipe' to use pipeline.",
+            PipelineError::FunctionUndefined(_)=>"check the function name, or import the module which defines it.",
+            PipelineError::VariableUndefined(_)=>"declare the variable with let before using it.",
+            PipelineError::UnexpectedToken(Token::EOF)=>"the script ended unexpectedly, check for an unclosed bracket or string.",
+            PipelineError::UnknownModule(_)=>"use a builtin module, or put the module file <name>.kts in the current directory or ~/.pipeline/package.",
+            PipelineError::CommandFailed(_,_)=>"pass allowFailure=true to cmd or the task to continue when the command fails.",
+            PipelineError::UnknownTask(_)=>"run 'pipeline list' to see the available tasks.",
+            PipelineError::DependencyCycle(_)=>"remove one of the needs to break the cycle.",
+            PipelineError::DuplicateTask(_)=>"task names must be unique within a pipeline.",
+            PipelineError::IndexOutOfBounds(_,_)=>"indices start at 0 and must be less than len().",
+            PipelineError::IntegerOverflow(_)=>"Int is a 64-bit signed integer, use Float values for larger numbers.",
+            PipelineError::ArityMismatch(_,_,_)=>"check the parameters in the function declaration.",
+            PipelineError::NotMutable(_)=>"only variables declared with let and their elements can be assigned.",
+            PipelineError::TypeMismatch(_,_,actual) if actual=="null"=>"append ? to the declared type to accept null.",
+            PipelineError::PropertyRequired(_,_)=>"pass a value for the property, or give it a default value in the class declaration.",
+            PipelineError::ParamRequired(_)=>"pass it with -p <name>=<value> or --params-file, or give param a default value.",
+            PipelineError::PromptUnanswered(_)=>"pass it with --answer <key>=<value> or --answers-file, or give the prompt a default value.",
+            PipelineError::TimedOut(_,_)=>"increase the timeout option of the cmd or the task.",
+            PipelineError::UnknownType(_,_)=>"use Int, Float, String, Bool, Array, Map, Closure, Any, Unit or a declared class.",
+            _=>return None
+        };
+        Some(hint.into())
+    }
+    /// 以rustc的风格渲染错误：错误信息、文件名与行列号、出错的源码行、标出出错位置的^以及修复建议
+    pub fn render(&self)->String{
+        let mut out=format!("\x1b[31m[Error]:{self}\x1b[0m\n");
+        if let PipelineError::Located(_,pos,Some(source))=self{
+            let chars:Vec<char>=source.text.chars().collect();
+            if pos.pos<chars.len(){
+                let (row,col)=pos.get_row_col(&chars);
+                let line=source.text.lines().nth(row).unwrap_or("");
+                let width=(row+1).to_string().len();
+                let span=pos.span.min(line.chars().count().saturating_sub(col)).max(1);
+                let blank=" ".repeat(width);
+                out.push_str(&format!("{blank}--> {}:{}:{}\n",source.path,row+1,col+1));
+                out.push_str(&format!("{blank} |\n"));
+                out.push_str(&format!("{} | {line}\n",row+1));
+                out.push_str(&format!("{blank} | {}\x1b[31m{}\x1b[0m\n"," ".repeat(col),"^".repeat(span)));
+                if let Some(hint)=self.hint(){
+                    out.push_str(&format!("{blank} = hint: {hint}\n"));
+                }
+                return out
+            }
+        }
+        if let Some(hint)=self.hint(){
+            out.push_str(&format!("  = hint: {hint}\n"));
+        }
+        out
+    }
+}
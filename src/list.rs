@@ -0,0 +1,141 @@
+use serde::Serialize;
+use crate::v1::expr::Expr;
+use crate::v1::stmt::Stmt;
+
+/// 脚本中声明的一个pipeline，由静态遍历语法树得到，不会执行脚本
+#[derive(Debug,Clone,Serialize)]
+pub struct PipelineInfo{
+    pub name:String,
+    pub tasks:Vec<TaskInfo>
+}
+#[derive(Debug,Clone,Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskInfo{
+    pub name:String,
+    /// step或parallel
+    pub kind:String,
+    pub needs:Vec<String>,
+    pub allow_failure:bool
+}
+
+/// 遍历编译后的语句，收集所有名字为常量字符串的pipeline及其中的step和parallel。
+/// 位于if、while、for中的声明也会被收集，名字由运行时计算的任务无法静态得到，会被忽略
+pub fn collect_pipelines(stmts:&[Stmt])->Vec<PipelineInfo>{
+    let mut pipelines=vec![];
+    walk(stmts,&mut |call_name,args|{
+        if call_name!="pipeline"{
+            return false
+        }
+        if let Some(name)=constant_name(args){
+            let mut tasks=vec![];
+            if let Some(Expr::FnClosure(c,_))=args.last(){
+                walk(&c.def.body,&mut |call_name,args|{
+                    if call_name!="step"&&call_name!="parallel"{
+                        return false
+                    }
+                    if let Some(name)=constant_name(args){
+                        tasks.push(task_info(name,call_name,args));
+                    }
+                    true
+                });
+            }
+            pipelines.push(PipelineInfo{name,tasks});
+        }
+        true
+    });
+    pipelines
+}
+/// 深度优先遍历语句中的函数调用，visitor返回true时不再进入该调用的闭包
+fn walk(stmts:&[Stmt],visitor:&mut impl FnMut(&str,&[Expr])->bool){
+    for stmt in stmts{
+        match stmt {
+            Stmt::FnCall(call,_)=>{
+                if visitor(call.name.as_str(),&call.args){
+                    continue
+                }
+                for arg in &call.args{
+                    if let Expr::FnClosure(c,_)=arg{
+                        walk(&c.def.body,visitor);
+                    }
+                }
+            }
+            Stmt::If(if_stmt,_)=>{
+                for branch in if_stmt.get_branches(){
+                    walk(branch.get_body(),visitor);
+                }
+                if let Some(body)=if_stmt.get_else_body(){
+                    walk(&body,visitor);
+                }
+            }
+            Stmt::Expr(e,_)=>{
+                if let Expr::When(when,_)=&**e{
+                    for branch in when.get_branches(){
+                        walk(branch.get_body(),visitor);
+                    }
+                    if let Some(body)=when.get_else_body(){
+                        walk(body,visitor);
+                    }
+                }
+            }
+            Stmt::While(_,body,_)=>walk(body,visitor),
+            Stmt::ForIn(_,_,_,body,_)=>walk(body,visitor),
+            Stmt::Try(t,_)=>{
+                walk(t.get_body(),visitor);
+                if let Some((_,body))=t.get_catch(){
+                    walk(body,visitor);
+                }
+                if let Some(body)=t.get_finally(){
+                    walk(body,visitor);
+                }
+            }
+            _=>{}
+        }
+    }
+}
+fn constant_name(args:&[Expr])->Option<String>{
+    match args.first() {
+        Some(Expr::StringConstant(s,_))=>Some(s.clone()),
+        _=>None
+    }
+}
+fn task_info(name:String,kind:&str,args:&[Expr])->TaskInfo{
+    let mut info=TaskInfo{name,kind:kind.into(),needs:vec![],allow_failure:false};
+    // 具名参数被解析为闭包前的一个Map
+    let named=match args.get(1) {
+        Some(Expr::Map(m,_)) if args.len()>=3=>m,
+        _=>return info
+    };
+    for (k,v) in named{
+        let Expr::StringConstant(k,_)=k else {continue};
+        match (k.as_str(),v) {
+            ("needs",Expr::StringConstant(s,_))=>info.needs.push(s.clone()),
+            ("needs",Expr::Array(a,_))=>{
+                for n in a{
+                    if let Expr::StringConstant(s,_)=n{
+                        info.needs.push(s.clone())
+                    }
+                }
+            }
+            ("allowFailure",Expr::Variable(b,_))=>info.allow_failure=b=="true",
+            _=>{}
+        }
+    }
+    info
+}
+/// 以树的形式输出pipeline及其任务
+pub fn print_pipelines(pipelines:&[PipelineInfo]){
+    for p in pipelines{
+        println!("{}",p.name);
+        for (i,t) in p.tasks.iter().enumerate(){
+            let branch=if i+1==p.tasks.len(){"└──"}else{"├──"};
+            let mut line=format!("{branch} {} ({})",t.name,t.kind);
+            if !t.needs.is_empty(){
+                line.push_str(&format!(" needs: {}",t.needs.join(",")));
+            }
+            if t.allow_failure{
+                line.push_str(" allowFailure");
+            }
+            println!("{line}");
+        }
+    }
+}
@@ -7,6 +7,10 @@ mod engine;
 mod logger;
 mod module;
 mod error;
+mod task;
+mod list;
+mod check;
+mod data;
 
 use std::any::Any;
 use std::{fs, thread};
@@ -14,9 +18,11 @@ use clap::{Args, Parser, Subcommand};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use crate::context::Context;
+use std::process::exit;
+use std::sync::{Arc, RwLock};
+use crate::context::{Context, PipelineContextValue};
 use crate::engine::{PipelineEngine};
-use crate::error::{PipelineError, PipelineResult};
+use crate::error::{PipelineError, PipelineResult, SourceFile};
 use crate::module::Module;
 
 
@@ -35,18 +41,49 @@ enum Commands {
     /// Run special project script.
     Run(RunArgs),
     /// List tasks which can execute.
-    // List,
+    List(ListArgs),
     Template(TemplateArgs),
     // using special layout to generate project struct.
     Layout(LayoutArgs)
 }
 #[derive(Args)]
 struct RunArgs{
-    path:Option<String>
+    path:Option<String>,
+    ///Print what would be executed without running commands or touching files.
+    #[arg(long)]
+    dry_run:bool,
+    ///Pass a parameter to the script, read it with param("name",default).
+    #[arg(short, long = "param", value_name = "KEY=VALUE")]
+    params:Vec<String>,
+    ///Read parameters from a json, toml, yaml or .env file, -p takes precedence.
+    #[arg(long, value_name = "FILE")]
+    params_file:Option<String>,
+    #[command(flatten)]
+    answers:AnswerArgs
+}
+#[derive(Args)]
+struct AnswerArgs{
+    ///Answer a prompt of readInt, readString etc. without waiting for input.
+    #[arg(long = "answer", value_name = "KEY=VALUE")]
+    answers:Vec<String>,
+    ///Read answers of prompts from a json, toml, yaml or .env file, --answer takes precedence.
+    #[arg(long, value_name = "FILE")]
+    answers_file:Option<String>
+}
+#[derive(Args)]
+struct ListArgs{
+    ///Print pipelines and tasks as json.
+    #[arg(long)]
+    json:bool
 }
 #[derive(Args)]
 struct LayoutArgs{
-    layout:Option<String>
+    layout:Option<String>,
+    ///Print which folders and files would be generated without creating them.
+    #[arg(long)]
+    dry_run:bool,
+    #[command(flatten)]
+    answers:AnswerArgs
 }
 #[derive(Args)]
 struct InitArgs{
@@ -93,60 +130,79 @@ fn handle_init(t:&str){
         }
     }
 }
-fn handle_pipeline_err(e:PipelineError){
-    match e {
-        PipelineError::FunctionUndefined(name) => {
-            println!("\x1b[31m[Error]:eval failed,function {name} undefined.\x1b[0m");
-            if name=="pipeline"{
-                println!("\x1b[31m[Error]:You can try to add 'import pipe' to use pipeline.\x1b[0m")
-            }
-        }
-        PipelineError::VariableUndefined(name) => {
-            println!("\x1b[31m[Error]:eval failed,variable \"{name}\" undefined.\x1b[0m")
-        }
-        PipelineError::ExpectedType(s) => {
-            println!("\x1b[31m[Error]:eval failed,expected type \"{s}\".\x1b[0m")
-        }
-        PipelineError::UnexpectedType(s)=>{
-            println!("\x1b[31m[Error]:eval failed,unexpected type \"{s}\".\x1b[0m")
-        }
-        PipelineError::UnexpectedToken(t)=> {
-            println!("\x1b[31m[Error]:parse failed,due to an unexpected token \"{t:?}\".\x1b[0m")
-        }
-        PipelineError::UnusedKeyword(k)=>{
-            println!("\x1b[31m[Error]:parse failed,due to an reserved and unimplemented keyword \"{k}\".\x1b[0m")
-        }
-        PipelineError::UnknownModule(m)=>{
-            println!("\x1b[31m[Error]:unknown module \"{m:}\".\x1b[0m")
-        }
-        PipelineError::UndefinedOperation(msg)=>{
-            println!("\x1b[31m[Error]:undefined operation \"{msg:}\".\x1b[0m")
-        }
+/// 合并文件和命令行中的KEY=VALUE，同名的键以命令行为准
+fn key_values(file:&Option<String>,args:&[String])->PipelineResult<Vec<(String,String)>>{
+    let mut values=vec![];
+    if let Some(file)=file{
+        let text=fs::read_to_string(file).map_err(|e|PipelineError::IoFailed(format!("read {file}"),e.to_string()))?;
+        values=data::parse_key_value_file(file,&text)?;
+    }
+    for p in args{
+        let (k,v)=p.split_once('=').ok_or(PipelineError::InvalidArgument(format!("\"{p}\" should be KEY=VALUE")))?;
+        values.push((k.trim().to_string(),v.to_string()));
+    }
+    Ok(values)
+}
+/// 将参数和提示的答案保存到全局状态中，分别以param.和answer.为前缀
+fn set_inputs(background:&Arc<RwLock<dyn Context<PipelineContextValue>>>,params:Vec<(String,String)>,answers:Vec<(String,String)>){
+    let global=PipelineEngine::context_with_global_state(background);
+    let mut global=global.write().unwrap();
+    for (k,v) in params{
+        global.set_value(&format!("param.{k}"),v);
+    }
+    for (k,v) in answers{
+        global.set_value(&format!("answer.{k}"),v);
     }
 }
+fn handle_pipeline_err(e:PipelineError){
+    print!("{}",e.render());
+}
 
-fn cli(){
+/// 创建执行pipeline.kts所需的引擎，注册了所有内置模块
+fn pipeline_engine()->PipelineEngine{
+    let mut engine=PipelineEngine::default_with_pipeline();
+    let math=Module::with_math_module();
+    engine.register_module(math);
+    let layout=Module::with_layout_module();
+    engine.register_module(layout);
+    let ssh=Module::with_ssh_module();
+    engine.register_module(ssh);
+    let fs=Module::with_fs_module();
+    engine.register_module(fs);
+    let json=Module::with_json_module();
+    engine.register_module(json);
+    let toml=Module::with_toml_module();
+    engine.register_module(toml);
+    let yaml=Module::with_yaml_module();
+    engine.register_module(yaml);
+    engine
+}
+fn cli()->PipelineResult<()>{
     let cli=Cli::parse();
     match &cli.command {
         Commands::Layout(s)=>{
             match &s.layout {
                 None => {}
                 Some(layout_name) => {
+                    let answers=key_values(&s.answers.answers_file,&s.answers.answers)?;
                     let mut engine=PipelineEngine::default();
                     let layout=Module::with_layout_module();
                     engine.register_module(layout);
                     let home_dir = dirs::home_dir().expect("无法获取用户根目录");
                     let path=home_dir.join(".pipeline").join(format!("layout/{}/layout.kts",layout_name));
-                    let script=fs::read_to_string(path).unwrap();
-                    let stmt=engine.compile_stmt_blocks(script.clone()).unwrap();
+                    let script=fs::read_to_string(&path).unwrap();
+                    let source=SourceFile::new(path.to_string_lossy(),script.as_str());
+                    let stmt=engine.compile_stmt_blocks(script).map_err(|e|e.with_source(&source))?;
                     let background=PipelineEngine::background();
-                    let r=engine.eval_stmt_blocks_from_ast_with_context(background,stmt);
-                    match r {
-                        Ok(_) => {}
-                        Err(e) => {
-                            handle_pipeline_err(e);
-                        }
+                    let global=PipelineEngine::context_with_global_state(&background);
+                    let mut global=global.write().unwrap();
+                    if s.dry_run{
+                        global.set_value("dry_run","true".into());
                     }
+                    drop(global);
+                    set_inputs(&background,vec![],answers);
+                    let background=PipelineEngine::with_value(background,"$source",PipelineContextValue::Source(source.clone()));
+                    engine.eval_stmt_blocks_from_ast_with_context(background,stmt).map_err(|e|e.with_source(&source))?;
                 }
             }
 
@@ -165,15 +221,12 @@ fn cli(){
             if paths.len()<2{
                 paths.push("all".into());
             }
-            let mut engine=PipelineEngine::default_with_pipeline();
-            let math=Module::with_math_module();
-            engine.register_module(math);
-            let layout=Module::with_layout_module();
-            engine.register_module(layout);
-            let ssh=Module::with_ssh_module();
-            engine.register_module(ssh);
+            let params=key_values(&path.params_file,&path.params)?;
+            let answers=key_values(&path.answers.answers_file,&path.answers.answers)?;
+            let mut engine=pipeline_engine();
             let script=fs::read_to_string("pipeline.kts").unwrap();
-            let stmt=engine.compile_stmt_blocks(script.clone());
+            let source=SourceFile::new("pipeline.kts",script.as_str());
+            let stmt=engine.compile_stmt_blocks(script).map_err(|e|e.with_source(&source));
             // println!("{:#?}",stmt);
             match stmt {
                 Ok(stmt) => {
@@ -186,23 +239,33 @@ fn cli(){
                     global.set_value("path_pipeline",pipeline.into());
                     let task=paths.get(1).unwrap().as_str();
                     global.set_value("path_task",task.into());
-                    global.set_value("source",script.as_str().into());
+                    if path.dry_run{
+                        global.set_value("dry_run","true".into());
+                    }
 
                     drop(global);
-                    let r=engine.eval_stmt_blocks_from_ast_with_context(background,stmt);
-                    match r {
-                        Ok(_) => {}
-                        Err(e) => {
-                            handle_pipeline_err(e);
-                        }
-                    }
+                    set_inputs(&background,params,answers);
+                    let background=PipelineEngine::with_value(background,"$source",PipelineContextValue::Source(source.clone()));
+                    engine.eval_stmt_blocks_from_ast_with_context(background,stmt).map_err(|e|e.with_source(&source))?;
                 }
                 Err(e) => {
-                    handle_pipeline_err(e);
+                    return Err(e);
                 }
             }
 
         }
+        Commands::List(args)=>{
+            let mut engine=pipeline_engine();
+            let script=fs::read_to_string("pipeline.kts").unwrap();
+            let source=SourceFile::new("pipeline.kts",script.as_str());
+            let stmt=engine.compile_stmt_blocks(script).map_err(|e|e.with_source(&source))?;
+            let pipelines=list::collect_pipelines(&stmt);
+            if args.json{
+                println!("{}",serde_json::to_string_pretty(&pipelines).unwrap());
+            }else {
+                list::print_pipelines(&pipelines);
+            }
+        }
         Commands::Template(args)=>{
             if let Some(add)=&args.add{
                 let home_dir = dirs::home_dir().expect("无法获取用户根目录");
@@ -214,7 +277,7 @@ fn cli(){
                     Ok(file) => file,
                     Err(err) => {
                         println!("无法创建文件: {:?}", err);
-                        return;
+                        return Ok(());
                     }
                 };
                 match file.write_all(file_content.as_bytes()) {
@@ -225,7 +288,7 @@ fn cli(){
                         println!("Failed:无法写入文件内容: {:?}", err);
                     }
                 }
-                return
+                return Ok(())
             }
             if let Some(remove)=&args.remove{
                 let home_dir = dirs::home_dir().expect("无法获取用户根目录");
@@ -234,7 +297,7 @@ fn cli(){
                     Ok(())=>println!("{}.kts has been successfully removed.",remove),
                     Err(_)=>println!("removed failed.")
                 }
-                return
+                return Ok(())
             }
             let home_dir = dirs::home_dir().expect("无法获取用户根目录");
             let dir_path=home_dir.join(".pipeline");
@@ -247,9 +310,18 @@ fn cli(){
             }
         }
     }
+    Ok(())
 }
 
-fn main() ->PipelineResult<()>{
-    cli();
-    Ok(())
+fn main(){
+    //Ctrl-C时终止所有任务中正在执行的命令及其子进程后再退出
+    let _=ctrlc::set_handler(||{
+        let killed=builtin::kill_running_commands();
+        println!("\x1b[31m[Error]:Interrupted,killed {killed} running command(s).\x1b[0m");
+        exit(130);
+    });
+    if let Err(e)=cli(){
+        handle_pipeline_err(e);
+        exit(1);
+    }
 }
@@ -1,26 +1,33 @@
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
-use std::{fs, io, ptr, thread};
+use std::{fs, io, ptr};
 use std::fs::File;
 use std::io::{Stdin, Write};
 use std::net::TcpStream;
 use std::path::{Path, PathBuf};
-use std::process::exit;
 use std::sync::{Arc, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use indexmap::IndexMap;
 use rand::{random, Rng};
 use regex::Regex;
 use scanner_rust::Scanner;
 use ssh::LocalSession;
-use crate::builtin::{cmd, copy, move_file, replace};
-use crate::context::{Context, PipelineContextValue};
+use crate::check::{argument_target, check_type};
+use crate::builtin::{check_deadline, cmd, CmdOptions, coerce_param, copy, deadline_after, get_env, get_param, is_dry_run, move_file, prompt, PromptKind, PromptOptions, record_effect, remaining_time, replace, with_env_scope, workspace_path};
+use crate::context::{Context, PipelineContextValue, Scope};
+use crate::data::{from_json, from_toml, from_yaml, lookup, parse_env_file, set_toml, to_json, to_toml, to_yaml};
 use crate::engine::{PipelineEngine};
 use crate::error::{PipelineError, PipelineResult};
+use crate::task::{env_from_value, finish_task, Task, TaskGraph, TaskKind, TaskOptions, TaskStates};
 use crate::v1;
 use crate::v1::interpreter::Interpreter;
 
+use crate::v1::expr::Expr;
 use crate::v1::parser::{FnDef, VariableDeclaration};
-use crate::v1::types::{Dynamic, Struct, Value};
+use crate::v1::position::NONE;
+use crate::v1::types::{Dynamic, FnPtr, Struct, Value};
 
 trait NativeFunction<Marker>{
     fn into_pipe_function(self) ->Arc<PipeFn>;
@@ -63,13 +70,15 @@ pub struct Module{
 pub struct Class{
     name:String,
     attributions:Vec<VariableDeclaration>,
+    /// 属性的默认值，在创建对象时求值
+    defaults:HashMap<String,Expr>,
     methods:HashMap<String,Function>
 }
 
 impl Class {
     pub fn new(name:String,attributions:Vec<VariableDeclaration>)->Self{
         Self{
-            name,attributions,methods:HashMap::new()
+            name,attributions,defaults:HashMap::new(),methods:HashMap::new()
         }
     }
     pub fn get_name(&self)->String{
@@ -78,6 +87,22 @@ impl Class {
     pub fn register_method(&mut self,name:String,method:Function){
         self.methods.insert(name,method);
     }
+    pub fn register_native_method(&mut self,name:impl Into<String>,f:impl Send+Sync+Fn(Arc<RwLock<dyn Context<PipelineContextValue>>>,Vec<Value>)->PipelineResult<Value> + 'static){
+        let a: Arc<PipeFn> = Arc::new(f);
+        self.methods.insert(name.into(),Function::Native(a));
+    }
+    pub fn get_attributions(&self)->&Vec<VariableDeclaration>{
+        &self.attributions
+    }
+    pub fn set_default(&mut self,name:String,default:Expr){
+        self.defaults.insert(name,default);
+    }
+    pub fn get_default(&self,name:&str)->Option<&Expr>{
+        self.defaults.get(name)
+    }
+    pub fn get_methods(&self)->&HashMap<String,Function>{
+        &self.methods
+    }
 }
 impl Function {
     pub fn call(&self, ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,  args:Vec<Value>) ->PipelineResult<Value>{
@@ -95,15 +120,26 @@ impl Function {
                     i.register_module(m.0.clone(),m.1.clone());
                 }
                 e.set_interpreter(&i);
-                let scope=PipelineEngine::context_with_scope(&ctx);
-                let mut scope=scope.write().unwrap();
-                let mut i=0;
-                for a in &s.args{
-                    scope.set(a.name.as_str(),args.get(i).unwrap().clone());
-                    i+=1;
+                if args.len()!=s.args.len(){
+                    return Err(PipelineError::ArityMismatch(s.name.clone(),s.args.len(),args.len()))
                 }
-                drop(scope);
-                e.eval_stmt_blocks_from_ast_with_context(ctx,s.body.clone())
+                //每次调用拥有独立的作用域，只能访问参数和全局变量
+                let mut scope=Scope::with_parent(Scope::global(&PipelineEngine::context_with_scope(&ctx)));
+                for (a,v) in s.args.iter().zip(args){
+                    check_type(&v,&a.declaration_type,||argument_target(&a.name,&s.name))?;
+                    scope.set(a.name.as_str(),v);
+                }
+                let ctx=PipelineEngine::with_scope(ctx,scope);
+                //导入模块中的函数在模块文件中报告错误位置
+                let r=match &s.source {
+                    Some(source)=>{
+                        let ctx=PipelineEngine::with_value(ctx,"$source",PipelineContextValue::Source(source.clone()));
+                        e.eval_stmt_blocks_from_ast_with_context(ctx,s.body.clone()).map_err(|e|e.with_source(source))?
+                    }
+                    None=>e.eval_stmt_blocks_from_ast_with_context(ctx,s.body.clone())?
+                };
+                check_type(&r,&s.return_type,||format!("return value of function \"{}\"",s.name))?;
+                Ok(r)
             }
             Function::Method(s) => {
                 let mut e=PipelineEngine::default_with_pipeline();
@@ -115,15 +151,21 @@ impl Function {
                     i.register_module(m.0.clone(),m.1.clone());
                 }
                 e.set_interpreter(&i);
-                let scope=PipelineEngine::context_with_scope(&ctx);
-                let mut scope=scope.write().unwrap();
-                let mut i=0;
-                for a in s.args.iter(){
-                    scope.set(a.name.as_str(),args.get(i+1).unwrap().clone());
-                    i+=1;
+                //第一个参数为this
+                if args.len()!=s.args.len()+1{
+                    return Err(PipelineError::ArityMismatch(s.name.clone(),s.args.len(),args.len().saturating_sub(1)))
+                }
+                let mut scope=Scope::with_parent(Scope::global(&PipelineEngine::context_with_scope(&ctx)));
+                let mut args=args.into_iter();
+                scope.set("this",args.next().unwrap());
+                for (a,v) in s.args.iter().zip(args){
+                    check_type(&v,&a.declaration_type,||argument_target(&a.name,&s.name))?;
+                    scope.set(a.name.as_str(),v);
                 }
-                drop(scope);
-                e.eval_stmt_blocks_from_ast_with_context(ctx,s.body.clone())
+                let ctx=PipelineEngine::with_scope(ctx,scope);
+                let r=e.eval_stmt_blocks_from_ast_with_context(ctx,s.body.clone())?;
+                check_type(&r,&s.return_type,||format!("return value of method \"{}\"",s.name))?;
+                Ok(r)
             }
         }
     }
@@ -174,116 +216,217 @@ impl Module{
         let mut std=Module::new("std");
         std.register_pipe_function("print",|ctx,args|{
             for v in args{
-                let v=v.as_dynamic();
-                if v.is_variable(){
-                    let variable=v.as_variable().unwrap();
+                let d=v.as_dynamic();
+                if d.is_variable(){
+                    let variable=d.as_variable().unwrap();
                     let v=PipelineEngine::context_with_dynamic(&ctx,variable.as_str());
                     match v {
                         None => {
                             return Err(PipelineError::VariableUndefined(variable))
                         }
                         Some(v) => {
-                            print!("{}",v.as_dynamic());
+                            print!("{}",display_value(&ctx,&v)?);
                             continue
                         }
                     }
                 }
-                print!("{v}");
+                print!("{}",display_value(&ctx,&v)?);
             }
             Ok(().into())
         });
         std.register_pipe_function("call",|ctx,args|{
-            let blocks=args.get(0).unwrap().as_dynamic().as_fn_ptr().unwrap().fn_def.unwrap().body;
-            let mut e=PipelineEngine::default_with_pipeline();
-            let share_module=PipelineEngine::context_with_shared_module(&ctx);
-            let i=Interpreter::with_shared_module(share_module);
-            e.set_interpreter(&i);
-            if args.len()>1{
-                let scope=PipelineEngine::context_with_scope(&ctx);
-                let  mut scope=scope.write().unwrap();
-                if args.len()==2{
-                    let v=args.get(1).unwrap().clone();
-                    scope.set("it",v);
-                }else{
-                    let args_list=args[1..args.len()].to_vec();
-                    scope.set("it",Value::Mutable(Arc::new(RwLock::new(Dynamic::Array(args_list)))));
-                }
+            check_arity("call",&args,1)?;
+            let closure=closure_arg(&args,0)?;
+            let mut e=task_engine(&ctx);
+            closure.call_with_args(&mut e,ctx,args[1..].to_vec())
+        });
+        std.register_pipe_function("forEach",|ctx,args|{
+            check_arity("forEach",&args,2)?;
+            let f=closure_arg(&args,1)?;
+            let mut e=task_engine(&ctx);
+            for item in elements(&args[0])?{
+                f.call_with_args(&mut e,ctx.clone(),item)?;
             }
-            e.eval_stmt_blocks_from_ast_with_context(ctx,blocks).unwrap();
             Ok(().into())
         });
+        std.register_pipe_function("map",|ctx,args|{
+            check_arity("map",&args,2)?;
+            let f=closure_arg(&args,1)?;
+            let mut e=task_engine(&ctx);
+            let mut r=vec![];
+            for item in elements(&args[0])?{
+                r.push(f.call_with_args(&mut e,ctx.clone(),item)?);
+            }
+            Ok(Value::with_mutable(Dynamic::Array(r)))
+        });
+        std.register_pipe_function("filter",|ctx,args|{
+            check_arity("filter",&args,2)?;
+            let f=closure_arg(&args,1)?;
+            let mut e=task_engine(&ctx);
+            match args[0].as_dynamic() {
+                Dynamic::Map(m)=>{
+                    let mut r=IndexMap::new();
+                    for (k,v) in m{
+                        if predicate(&f,&mut e,ctx.clone(),vec![Value::Immutable(k.clone()),v.clone()])?{
+                            r.insert(k,v);
+                        }
+                    }
+                    Ok(Value::with_mutable(Dynamic::Map(r)))
+                }
+                _=>{
+                    let mut r=vec![];
+                    for item in elements(&args[0])?{
+                        if predicate(&f,&mut e,ctx.clone(),item.clone())?{
+                            r.extend(item);
+                        }
+                    }
+                    Ok(Value::with_mutable(Dynamic::Array(r)))
+                }
+            }
+        });
+        std.register_pipe_function("reduce",|ctx,args|{
+            check_arity("reduce",&args,2)?;
+            let f=closure_arg(&args,args.len()-1)?;
+            let mut e=task_engine(&ctx);
+            let mut items=elements(&args[0])?.into_iter();
+            let mut acc=if args.len()>2{
+                args[1].clone()
+            }else{
+                if args[0].as_dynamic().is_map(){
+                    return Err(PipelineError::InvalidArgument("reduce on Map requires an initial value".into()))
+                }
+                match items.next() {
+                    None => return Err(PipelineError::InvalidArgument("reduce of empty Array with no initial value".into())),
+                    Some(mut first) => first.remove(0)
+                }
+            };
+            for item in items{
+                let mut call_args=vec![acc];
+                call_args.extend(item);
+                acc=f.call_with_args(&mut e,ctx.clone(),call_args)?;
+            }
+            Ok(acc)
+        });
+        std.register_pipe_function("any",|ctx,args|{
+            check_arity("any",&args,2)?;
+            let f=closure_arg(&args,1)?;
+            let mut e=task_engine(&ctx);
+            for item in elements(&args[0])?{
+                if predicate(&f,&mut e,ctx.clone(),item)?{
+                    return Ok(true.into())
+                }
+            }
+            Ok(false.into())
+        });
+        std.register_pipe_function("all",|ctx,args|{
+            check_arity("all",&args,2)?;
+            let f=closure_arg(&args,1)?;
+            let mut e=task_engine(&ctx);
+            for item in elements(&args[0])?{
+                if !predicate(&f,&mut e,ctx.clone(),item)?{
+                    return Ok(false.into())
+                }
+            }
+            Ok(true.into())
+        });
+        std.register_pipe_function("sortBy",|ctx,args|{
+            check_arity("sortBy",&args,2)?;
+            let f=closure_arg(&args,1)?;
+            let mut e=task_engine(&ctx);
+            let is_map=args[0].as_dynamic().is_map();
+            let mut keyed=vec![];
+            for item in elements(&args[0])?{
+                let key=f.call_with_args(&mut e,ctx.clone(),item.clone())?.as_dynamic();
+                let item=if is_map{
+                    Value::with_mutable(Dynamic::Array(item))
+                }else{
+                    item.into_iter().next().unwrap()
+                };
+                keyed.push((key,item));
+            }
+            let mut error=None;
+            keyed.sort_by(|a,b|{
+                a.0.compare(&b.0).unwrap_or_else(|err|{
+                    error.get_or_insert(err);
+                    Ordering::Equal
+                })
+            });
+            if let Some(err)=error{
+                return Err(err)
+            }
+            Ok(Value::with_mutable(Dynamic::Array(keyed.into_iter().map(|i|i.1).collect())))
+        });
         std.register_pipe_function("println",|ctx,args|{
             for v in args{
-                let v=v.as_dynamic();
-                if v.is_variable(){
-                    let variable=v.as_variable().unwrap();
+                let d=v.as_dynamic();
+                if d.is_variable(){
+                    let variable=d.as_variable().unwrap();
                     let v=PipelineEngine::context_with_dynamic(&ctx,variable.as_str());
                     match v {
                         None => {
                             return Err(PipelineError::VariableUndefined(variable))
                         }
                         Some(v) => {
-                            print!("{}",v.as_dynamic());
+                            print!("{}",display_value(&ctx,&v)?);
                             continue
                         }
                     }
                 }
-                print!("{v}");
+                print!("{}",display_value(&ctx,&v)?);
             }
             println!();
             Ok(().into())
         });
         std.register_pipe_function("remove",|ctx,args|{
-            let target=args.get(0).unwrap().as_dynamic();
+            check_arity("remove",&args,2)?;
+            let target=args[0].as_dynamic();
             match target {
                 Dynamic::Array(a)=>{
-                    let a=args.get(0).unwrap().as_arc();
+                    let index=args[1].as_integer().ok_or(PipelineError::ExpectedType("Int".into()))?;
+                    if index<0||index as usize>=a.len(){
+                        return Err(PipelineError::IndexOutOfBounds(index,a.len()))
+                    }
+                    let a=args[0].as_arc();
                     let mut a=a.write().unwrap();
                     let a=a.as_mut_array().unwrap();
-                    let index=args.get(1).unwrap().as_dynamic();
-                    let index=index.as_integer().unwrap();
                     a.remove(index as usize);
                 }
                 Dynamic::Map(_)=>{
-                    let key=args.get(1).unwrap().as_dynamic();
-                    let m=args.get(0).unwrap().as_arc();
+                    let key=args[1].as_dynamic();
+                    let m=args[0].as_arc();
                     let mut m=m.write().unwrap();
                     let m=m.as_mut_map().unwrap();
-                    m.remove(&key);
+                    m.shift_remove(&key);
                 }
                 t=>{
-                    panic!("{} not support remove",t.type_name())
+                    return Err(PipelineError::UndefinedOperation(format!("remove from {}",t.type_name())))
                 }
             }
             Ok(().into())
         });
         std.register_pipe_function("append",|ctx,args|{
-            let target=args.get(0).unwrap().as_arc();
+            check_arity("append",&args,1)?;
+            let target=args[0].as_arc();
             let mut target0=target.write().unwrap();
-            let target_array=target0.as_mut_array().unwrap();
+            let target_array=target0.as_mut_array().ok_or(PipelineError::ExpectedType("Array".into()))?;
             for it in args.iter().skip(1){
                 if ptr::eq(&*target,&*it.as_arc()){
-                    panic!("append can not add itself,please consider clone it")
+                    return Err(PipelineError::InvalidArgument("append can not add an array to itself,please consider clone it".into()))
                 }
                 target_array.push(it.clone());
             }
             Ok(().into())
         });
-        std.register_pipe_function("readLine",|_,args|{
-            if args.len()>0{
-                let c=args.get(0).unwrap().as_dynamic().as_string().unwrap();
-                print!("{c}");
-                io::stdout().flush().unwrap();
-            }
-            let mut input = String::new();
-            io::stdin().read_line(&mut input).expect("无法读取输入");
-            Ok(input.into())
+        std.register_pipe_function("readLine",|ctx,args|{
+            read_prompt(&ctx,PromptKind::Line,&args)
         });
         std.register_pipe_function("len",|_,args|{
-            let c=args.get(0).unwrap().as_dynamic();
+            check_arity("len",&args,1)?;
+            let c=args[0].as_dynamic();
             match c {
+                //按字符计算，与String方法中的下标一致
                 Dynamic::String(s) => {
-                    Ok((s.len() as i64).into())
+                    Ok((s.chars().count() as i64).into())
                 }
                 Dynamic::Array(a) => {
                     Ok((a.len() as i64).into())
@@ -296,128 +439,154 @@ impl Module{
 
         });
         std.register_pipe_function("type",|_,args|{
-            let c=args.get(0).unwrap();
+            check_arity("type",&args,1)?;
+            let c=&args[0];
             Ok(c.as_dynamic().type_name().into())
         });
         std.register_pipe_function("clone",|_,args|{
-            let c=args.get(0).unwrap();
-            Ok(match c {
+            check_arity("clone",&args,1)?;
+            Ok(match &args[0] {
                 Value::Immutable(i) => {
                     i.clone().into()
                 }
-                Value::Mutable(m) => {
-                    let a=m.read().unwrap().clone();
-                    Value::Mutable(Arc::new(RwLock::new(a)))
-                }
-                Value::Refer(r) => {
-                    let a=r.upgrade().unwrap().read().unwrap().clone();
-                    Value::Mutable(Arc::new(RwLock::new(a)))
+                c => {
+                    Value::Mutable(Arc::new(RwLock::new(c.as_dynamic())))
                 }
-                _=>panic!("signal can not be cloned")
             })
         });
+        //readInt(hint,[key],[default],[choices])，可选参数以具名参数传入
         std.register_pipe_function("readInt",|ctx,args|{
-            if args.len()>0{
-                let c=args.get(0).unwrap().as_dynamic().as_string().unwrap();
-                print!("{c}");
-                io::stdout().flush().unwrap();
-            }
-            let sc=PipelineEngine::context_with_native(&ctx,"$sc");
-
-            let mut sc=sc.write().unwrap();
-            let mut sc=sc.downcast_mut::<Scanner<Stdin>>().unwrap();
-            let i =sc.next_i64().unwrap().unwrap();
-            Ok(i.into())
+            read_prompt(&ctx,PromptKind::Int,&args)
         });
         std.register_pipe_function("readFloat",|ctx,args|{
-            if args.len()>0{
-                let c=args.get(0).unwrap().as_dynamic().as_string().unwrap();
-                print!("{c}");
-                io::stdout().flush().unwrap();
-            }
-            let sc=PipelineEngine::context_with_native(&ctx,"$sc");
-
-            let mut sc=sc.write().unwrap();
-            let mut sc=sc.downcast_mut::<Scanner<Stdin>>().unwrap();
-            let i =sc.next_f64().unwrap().unwrap();
-            Ok(i.into())
+            read_prompt(&ctx,PromptKind::Float,&args)
         });
         std.register_pipe_function("readString",|ctx,args|{
-            if args.len()>0{
-                let c=args.get(0).unwrap().as_dynamic().as_string().unwrap();
-                print!("{c}");
-                io::stdout().flush().unwrap();
-            }
-            let sc=PipelineEngine::context_with_native(&ctx,"$sc");
-
-            let mut sc=sc.write().unwrap();
-            let mut sc=sc.downcast_mut::<Scanner<Stdin>>().unwrap();
-            let i =sc.next().unwrap().unwrap();
-            Ok(i.into())
+            read_prompt(&ctx,PromptKind::String,&args)
         });
         std.register_pipe_function("cmd",|ctx,args| {
-            let c=args.get(0).unwrap().as_dynamic().as_string().unwrap();
-            return cmd(c.as_str(),ctx);
+            check_arity("cmd",&args,1)?;
+            let c=string_arg(&args,0)?;
+            let options=CmdOptions::from_value(args.get(1))?;
+            return cmd(c.as_str(),options,ctx);
 
         });
         std.register_pipe_function("env",|ctx,args| {
-            let k=args.get(0).unwrap().as_dynamic().as_string().unwrap();
-            let v=args.get(1).unwrap().as_dynamic().as_string().unwrap();
+            check_arity("env",&args,2)?;
+            let k=string_arg(&args,0)?;
+            let v=string_arg(&args,1)?;
             let env=PipelineEngine::context_with_env(&ctx);;
             let mut env=env.write().unwrap();
             env.insert(k,v);
             Ok(().into())
 
         });
+        //getEnv(key,[default])，依次查找当前作用域中设置的环境变量和进程的环境变量，都不存在时返回default或null
+        std.register_pipe_function("getEnv",|ctx,args| {
+            check_arity("getEnv",&args,1)?;
+            let k=string_arg(&args,0)?;
+            match get_env(&ctx,&k) {
+                Some(v)=>Ok(v.into()),
+                None=>Ok(args.get(1).cloned().unwrap_or(().into()))
+            }
+        });
+        //loadEnv(file)，将.env文件中的环境变量加载到当前作用域，返回加载的环境变量
+        std.register_pipe_function("loadEnv",|ctx,args| {
+            check_arity("loadEnv",&args,1)?;
+            let file=string_arg(&args,0)?;
+            let text=fs::read_to_string(workspace_path(&ctx,&file)).map_err(io_failed(format!("read {file}")))?;
+            let vars=parse_env_file(&text)?;
+            let env=PipelineEngine::context_with_env(&ctx);
+            let mut env=env.write().unwrap();
+            let mut loaded=IndexMap::new();
+            for (k,v) in vars{
+                loaded.insert(Dynamic::String(k.clone()),v.clone().into());
+                env.insert(k,v);
+            }
+            Ok(Value::with_mutable(Dynamic::Map(loaded)))
+        });
+        //param(name,[default])，读取命令行传入的参数并转换为默认值的类型，没有默认值的参数必须传入
+        std.register_pipe_function("param",|ctx,args| {
+            check_arity("param",&args,1)?;
+            let name=string_arg(&args,0)?;
+            match (get_param(&ctx,&name),args.get(1)) {
+                (Some(raw),Some(default))=>coerce_param(&name,&raw,&default.as_dynamic()),
+                (Some(raw),None)=>Ok(raw.into()),
+                (None,Some(default))=>Ok(default.clone()),
+                (None,None)=>Err(PipelineError::ParamRequired(name))
+            }
+        });
         std.register_pipe_function("workspace",|ctx,args| {
             let global=PipelineEngine::context_with_global_state(&ctx);
-            let arg=args.get(0).unwrap().as_dynamic().as_string().unwrap();
+            check_arity("workspace",&args,1)?;
+            let arg=string_arg(&args,0)?;
+            if is_dry_run(&ctx){
+                //前面的操作可能只是被记录而没有真正创建该目录，因此不检查路径是否存在
+                record_effect(&ctx,format!("workspace {arg}"));
+                global.write().unwrap().set_value("workspace",arg);
+                return Ok(().into())
+            }
             if !Path::new(arg.as_str()).exists(){
-                let source=PipelineEngine::context_with_global_value(&ctx,"source");
-                let pos=PipelineEngine::context_with_position(&ctx);
-                let c=source.chars().collect();
-                let (row,col)=pos.get_row_col(&c);
-                println!("\x1b[31m  {}|{col}   {:}\x1b[0m",row+1,pos.get_raw_string(&c));
-                println!("\x1b[31m[Error]:路径\"{arg}\"不存在\x1b[0m");
-                exit(0);
+                return Err(PipelineError::PathNotFound(arg))
             }
             global.write().unwrap().set_value("workspace",arg);
             return Ok(().into())
         });
         std.register_pipe_function("copy",|ctx,args| {
-            let source=args.get(0).unwrap().as_dynamic().as_string().unwrap();
-            let target=args.get(1).unwrap().as_dynamic().as_string().unwrap();
-            copy(ctx,source.as_str(),target.as_str());
+            check_arity("copy",&args,2)?;
+            let source=string_arg(&args,0)?;
+            let target=string_arg(&args,1)?;
+            copy(ctx,source.as_str(),target.as_str())?;
             return Ok(().into())
         });
         std.register_pipe_function("replace",|ctx,args| {
-            let path=args.get(0).unwrap().as_string().unwrap();
-            let regex=args.get(1).unwrap().as_string().unwrap();
-            let replace_content=args.get(2).unwrap().as_string().unwrap();
-            replace(ctx,path.as_str(),regex.as_str(),replace_content.as_str());
+            check_arity("replace",&args,3)?;
+            let path=string_arg(&args,0)?;
+            let regex=string_arg(&args,1)?;
+            let replace_content=string_arg(&args,2)?;
+            replace(ctx,path.as_str(),regex.as_str(),replace_content.as_str())?;
             return Ok(().into())
         });
         std.register_pipe_function("move",|ctx,args| {
-            let source=args.get(0).unwrap().as_string().unwrap();
-            let target=args.get(1).unwrap().as_string().unwrap();
-            move_file(ctx,source.as_str(),target.as_str());
+            check_arity("move",&args,2)?;
+            let source=string_arg(&args,0)?;
+            let target=string_arg(&args,1)?;
+            move_file(ctx,source.as_str(),target.as_str())?;
             return Ok(().into())
         });
         std.register_class(Class::new("Int".into(),vec![]));
         std.register_class(Class::new("Float".into(),vec![]));
-        std.register_class(Class::new("String".into(),vec![]));
-        std.register_class(Class::new("Array".into(),vec![]));
+        std.register_class(string_class());
+        std.register_class(array_class());
         std.register_class(Class::new("Map".into(),vec![]));
         std.register_class(Class::new("Bool".into(),vec![]));
+        let mut error=Class::new("Error".into(),vec![
+            VariableDeclaration::new("message".into(),"String".into()),
+            VariableDeclaration::new("kind".into(),"String".into()),
+            VariableDeclaration::new("line".into(),"Int".into()),
+            VariableDeclaration::new("column".into(),"Int".into()),
+        ]);
+        //脚本中通过Error("message")创建的错误
+        error.set_default("kind".into(),Expr::StringConstant("Thrown".into(),NONE.clone()));
+        error.set_default("line".into(),Expr::IntConstant(0,NONE.clone()));
+        error.set_default("column".into(),Expr::IntConstant(0,NONE.clone()));
+        std.register_class(error);
+        std.register_class(Class::new("CmdResult".into(),vec![
+            VariableDeclaration::new("code".into(),"Int".into()),
+            VariableDeclaration::new("stdout".into(),"String".into()),
+            VariableDeclaration::new("stderr".into(),"String".into()),
+            VariableDeclaration::new("duration".into(),"Int".into()),
+        ]));
         return std
     }
     pub fn with_math_module()->Self{
         let mut math=Module::new("math");
         math.register_pipe_function("max",|ctx,args| {
-            let first=args.get(0).unwrap().as_dynamic();
-            let mut max=first.convert_float().unwrap();
+            check_arity("max",&args,1)?;
+            let first=args[0].as_dynamic();
+            let mut max=first.convert_float().ok_or(PipelineError::ExpectedType("Float".into()))?;
             for a in &args{
-                let i=a.as_dynamic().convert_float().unwrap();
+                let i=a.as_dynamic().convert_float().ok_or(PipelineError::ExpectedType("Float".into()))?;
                 if i>max{
                     max=i
                 }
@@ -426,13 +595,16 @@ impl Module{
         });
         math.register_pipe_function("randomInt",|ctx,args| {
             if args.len()>0{
-                let a=args[0].as_integer().unwrap();
-                if args.len()>1{
-                    let b=args[1].as_integer().unwrap();
-                    let random_number = rand::thread_rng().gen_range(a..=b);
-                    return Ok(random_number.into())
+                let a=args[0].as_integer().ok_or(PipelineError::ExpectedType("Int".into()))?;
+                let (a,b)=if args.len()>1{
+                    (a,args[1].as_integer().ok_or(PipelineError::ExpectedType("Int".into()))?)
+                }else{
+                    (0,a)
+                };
+                if a>b{
+                    return Err(PipelineError::InvalidArgument(format!("randomInt range {a}..{b} is empty")))
                 }
-                let random_number = rand::thread_rng().gen_range(0..=a);
+                let random_number = rand::thread_rng().gen_range(a..=b);
                 return Ok(random_number.into())
             }
             let i=random::<i64>();
@@ -443,80 +615,338 @@ impl Module{
     pub fn with_pipe_module()->Self{
         let  mut pipe=Module::new("pipe");
         pipe.register_pipe_function("pipeline",|ctx,args| {
-            let pipeline_name=args.get(0).unwrap().as_string().unwrap();
-            let blocks=args.get(1).unwrap().as_dynamic().as_fn_ptr().unwrap().fn_def.unwrap().body;
-            let mut e=PipelineEngine::default_with_pipeline();
-            let share_module=PipelineEngine::context_with_shared_module(&ctx);
-            let i=Interpreter::with_shared_module(share_module);
-            e.set_interpreter(&i);
+            check_arity("pipeline",&args,2)?;
+            let pipeline_name=string_arg(&args,0)?;
+            let mut closure=closure_arg(&args,args.len()-1)?;
+            //pipeline("release",env={...},clearEnv=true){...}，pipeline中的任务继承这些环境变量
+            let mut env=HashMap::new();
+            let mut clear_env=false;
+            if args.len()>2{
+                let options=match args[1].as_dynamic() {
+                    Dynamic::Map(m)=>m,
+                    t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+                };
+                if let Some(v)=options.get(&Dynamic::from("env")){
+                    env=env_from_value(v)?;
+                }
+                if let Some(v)=options.get(&Dynamic::from("clearEnv")){
+                    clear_env=v.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
+                }
+            }
+            let ctx=with_env_scope(ctx,env,clear_env);
+            let mut e=task_engine(&ctx);
             let pipeline=PipelineEngine::context_with_global_value(&ctx,"path_pipeline");
-            let ctx=PipelineEngine::with_value(ctx,"join_set",PipelineContextValue::JoinSet(Arc::new(std::sync::RwLock::new(vec![]))));
+            let tasks=Arc::new(RwLock::new(vec![]));
+            let ctx=PipelineEngine::with_value(ctx,"$tasks",PipelineContextValue::Tasks(tasks.clone()));
+            let states=Arc::new(RwLock::new(TaskStates::new()));
+            let ctx=PipelineEngine::with_value(ctx,"$task_states",PipelineContextValue::TaskStates(states.clone()));
             if pipeline==pipeline_name||pipeline=="all"{
-                e.eval_stmt_blocks_from_ast_with_context(ctx.clone(),blocks).unwrap();
+                //先执行pipeline闭包收集其中声明的任务，再根据依赖关系统一调度
+                closure.call(&mut e,ctx.clone())?;
+                let graph=TaskGraph::new(tasks.read().unwrap().clone())?;
+                let target=PipelineEngine::context_with_global_value(&ctx,"path_task");
+                graph.run(target.as_str(),states.clone(),run_task)?;
             }
-            let join_set=PipelineEngine::context_with_join_set(&ctx,"join_set");
-            let mut join_set=join_set.write().unwrap();
-            while !join_set.is_empty(){
-               let e= join_set.pop().unwrap();
-                e.join().unwrap().unwrap();
+            let failed=states.read().unwrap().failed();
+            if !failed.is_empty(){
+                return Err(PipelineError::TaskFailed(pipeline_name,failed))
             }
             Ok(().into())
         });
         pipe.register_pipe_function("parallel",|ctx,args| {
-            let pipeline_name=args.get(0).unwrap().as_string().unwrap();
-            let blocks=args.get(1).unwrap().as_dynamic().as_fn_ptr().unwrap().fn_def.unwrap().body;
-            let mut e=PipelineEngine::default();
-            let ctx=PipelineEngine::with_value(ctx,"$env",PipelineContextValue::Env(Arc::new(std::sync::RwLock::new(HashMap::new()))));
-            let pipeline=PipelineEngine::context_with_global_value(&ctx,"path_task");
             let logger=PipelineEngine::context_with_logger(&ctx,"logger");
             let logger=logger.as_logger().unwrap();
             logger.write().unwrap().set_parallel(true);
-            if pipeline==pipeline_name||pipeline.as_str()=="all"{
-                let join=PipelineEngine::context_with_join_set(&ctx,"join_set");
-                let mut join=join.write().unwrap();
-                let handle=thread::spawn(move||{
-                    let ctx=PipelineEngine::with_value(ctx,"op_join_set",PipelineContextValue::JoinSet(Arc::new(RwLock::new(vec![]))));
-                    let ctx=PipelineEngine::with_value(ctx,"$task_name",PipelineContextValue::Local(pipeline_name.into()));
-                    e.eval_stmt_blocks_from_ast_with_context(ctx.clone(),blocks).unwrap();
-                    let join_set=PipelineEngine::context_with_join_set(&ctx,"op_join_set");
-                    let mut join_set=join_set.write().unwrap();
-                    while !join_set.is_empty(){
-                        let e= join_set.pop().unwrap();
-                        e.join().unwrap().unwrap();
-                    }
-                    return Ok(())
-                });
-                join.push(handle);
+            declare_task(ctx,args,TaskKind::Parallel)
+        });
+        pipe.register_pipe_function("step",|ctx,args| {
+            declare_task(ctx,args,TaskKind::Step)
+        });
+        return pipe
+    }
+    /// 文件系统操作，路径相对于当前workspace，失败时返回错误而不是退出。
+    /// dry-run模式下写入、创建和删除操作只记录不执行
+    pub fn with_fs_module()->Self{
+        let mut fs_module=Module::new("fs");
+        fs_module.register_pipe_function("readText",|ctx,args|{
+            check_arity("readText",&args,1)?;
+            let path=string_arg(&args,0)?;
+            let content=fs::read_to_string(workspace_path(&ctx,&path)).map_err(io_failed(format!("read {path}")))?;
+            Ok(content.into())
+        });
+        fs_module.register_pipe_function("writeText",|ctx,args|{
+            check_arity("writeText",&args,2)?;
+            let path=string_arg(&args,0)?;
+            let content=string_arg(&args,1)?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("write {path}"));
+                return Ok(().into())
             }
+            fs::write(workspace_path(&ctx,&path),content).map_err(io_failed(format!("write {path}")))?;
             Ok(().into())
         });
-        pipe.register_pipe_function("step",|ctx,args| {
-            let pipeline_name=args.get(0).unwrap().as_string().unwrap();
-            let mut ptr=args.get(1).unwrap().as_dynamic().as_fn_ptr().unwrap();
-            let mut e=PipelineEngine::default();
-            let ctx=PipelineEngine::with_value(ctx,"$env",PipelineContextValue::Env(Arc::new(std::sync::RwLock::new(HashMap::new()))));
-            let pipeline=PipelineEngine::context_with_global_value(&ctx,"path_task");
-            if pipeline==pipeline_name||pipeline.as_str()=="all"{
-                let ctx=PipelineEngine::with_value(ctx,"op_join_set",PipelineContextValue::JoinSet(Arc::new(std::sync::RwLock::new(vec![]))));
-                let ctx=PipelineEngine::with_value(ctx,"$task_name",PipelineContextValue::Local(pipeline_name.into()));
-                ptr.call(&mut e,ctx.clone()).unwrap();
-                let join_set=PipelineEngine::context_with_join_set(&ctx,"op_join_set");
-                let mut join_set=join_set.write().unwrap();
-                while !join_set.is_empty(){
-                    let e= join_set.pop().unwrap();
-                    e.join().unwrap().unwrap();
-                }
+        fs_module.register_pipe_function("appendText",|ctx,args|{
+            check_arity("appendText",&args,2)?;
+            let path=string_arg(&args,0)?;
+            let content=string_arg(&args,1)?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("append {path}"));
+                return Ok(().into())
             }
+            let mut file=fs::OpenOptions::new().create(true).append(true)
+                .open(workspace_path(&ctx,&path))
+                .map_err(io_failed(format!("append {path}")))?;
+            file.write_all(content.as_bytes()).map_err(io_failed(format!("append {path}")))?;
             Ok(().into())
         });
-        return pipe
+        fs_module.register_pipe_function("exists",|ctx,args|{
+            check_arity("exists",&args,1)?;
+            let path=string_arg(&args,0)?;
+            Ok(workspace_path(&ctx,&path).exists().into())
+        });
+        fs_module.register_pipe_function("isDir",|ctx,args|{
+            check_arity("isDir",&args,1)?;
+            let path=string_arg(&args,0)?;
+            Ok(workspace_path(&ctx,&path).is_dir().into())
+        });
+        fs_module.register_pipe_function("mkdirs",|ctx,args|{
+            check_arity("mkdirs",&args,1)?;
+            let path=string_arg(&args,0)?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("mkdirs {path}"));
+                return Ok(().into())
+            }
+            fs::create_dir_all(workspace_path(&ctx,&path)).map_err(io_failed(format!("create directory {path}")))?;
+            Ok(().into())
+        });
+        //删除文件或空目录
+        fs_module.register_pipe_function("remove",|ctx,args|{
+            check_arity("remove",&args,1)?;
+            let path=string_arg(&args,0)?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("remove {path}"));
+                return Ok(().into())
+            }
+            let target=workspace_path(&ctx,&path);
+            let r=if target.is_dir()&&!target.is_symlink(){
+                fs::remove_dir(target)
+            }else{
+                fs::remove_file(target)
+            };
+            r.map_err(io_failed(format!("remove {path}")))?;
+            Ok(().into())
+        });
+        //递归删除，路径不存在时什么也不做
+        fs_module.register_pipe_function("removeAll",|ctx,args|{
+            check_arity("removeAll",&args,1)?;
+            let path=string_arg(&args,0)?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("remove all {path}"));
+                return Ok(().into())
+            }
+            let target=workspace_path(&ctx,&path);
+            let r=if target.is_dir()&&!target.is_symlink(){
+                fs::remove_dir_all(target)
+            }else if target.symlink_metadata().is_ok(){
+                fs::remove_file(target)
+            }else{
+                Ok(())
+            };
+            r.map_err(io_failed(format!("remove all {path}")))?;
+            Ok(().into())
+        });
+        //目录下的文件名，按名称排序
+        fs_module.register_pipe_function("list",|ctx,args|{
+            check_arity("list",&args,1)?;
+            let path=string_arg(&args,0)?;
+            let entries=fs::read_dir(workspace_path(&ctx,&path)).map_err(io_failed(format!("list {path}")))?;
+            let mut names=vec![];
+            for entry in entries{
+                let entry=entry.map_err(io_failed(format!("list {path}")))?;
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+            names.sort();
+            Ok(Value::with_mutable(Dynamic::Array(names.into_iter().map(|n|n.into()).collect())))
+        });
+        //glob("src/**/*.rs")，返回相对于workspace的路径，使用/分隔
+        fs_module.register_pipe_function("glob",|ctx,args|{
+            check_arity("glob",&args,1)?;
+            let pattern=string_arg(&args,0)?;
+            let root=workspace_path(&ctx,"");
+            //workspace路径中的[、*等字符需要转义，只有用户传入的部分作为glob模式
+            let escaped=glob::Pattern::escape(&root.to_string_lossy());
+            let full=Path::new(&escaped).join(&pattern).to_string_lossy().to_string();
+            let paths=glob::glob(&full).map_err(|e|PipelineError::InvalidArgument(format!("invalid glob pattern \"{pattern}\": {e}")))?;
+            let mut r=vec![];
+            for p in paths{
+                let p=p.map_err(|e|PipelineError::IoFailed(format!("glob {pattern}"),e.to_string()))?;
+                let p=p.strip_prefix(&root).unwrap_or(&p);
+                let p=p.components().map(|c|c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+                r.push(p.into());
+            }
+            Ok(Value::with_mutable(Dynamic::Array(r)))
+        });
+        //FileStat{size,mtime,isDir}，mtime为毫秒时间戳
+        fs_module.register_pipe_function("stat",|ctx,args|{
+            check_arity("stat",&args,1)?;
+            let path=string_arg(&args,0)?;
+            let metadata=fs::metadata(workspace_path(&ctx,&path)).map_err(io_failed(format!("stat {path}")))?;
+            let mtime=metadata.modified().map_err(io_failed(format!("stat {path}")))?
+                .duration_since(UNIX_EPOCH).map(|d|d.as_millis() as i64).unwrap_or(0);
+            let mut props=HashMap::new();
+            props.insert("size".into(),(metadata.len() as i64).into());
+            props.insert("mtime".into(),mtime.into());
+            props.insert("isDir".into(),metadata.is_dir().into());
+            Ok(Value::Immutable(Dynamic::Struct(Box::new(Struct::new("FileStat".into(),props)))))
+        });
+        //在系统临时目录下创建一个新的目录，返回其绝对路径
+        //dry-run模式下只返回路径，不创建目录
+        fs_module.register_pipe_function("tempDir",|ctx,_|{
+            let nanos=SystemTime::now().duration_since(UNIX_EPOCH).map(|d|d.as_nanos()).unwrap_or(0);
+            let dir=std::env::temp_dir().join(format!("pipeline-{}-{nanos}",std::process::id()));
+            let path=dir.to_string_lossy().to_string();
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("tempDir {path}"));
+                return Ok(path.into())
+            }
+            fs::create_dir_all(&dir).map_err(io_failed("create temp directory".into()))?;
+            Ok(path.into())
+        });
+        //symlink(target,link)，target按原样写入链接
+        fs_module.register_pipe_function("symlink",|ctx,args|{
+            check_arity("symlink",&args,2)?;
+            let target=string_arg(&args,0)?;
+            let link=string_arg(&args,1)?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("symlink {link} -> {target}"));
+                return Ok(().into())
+            }
+            let link_path=workspace_path(&ctx,&link);
+            #[cfg(unix)]
+            let r=std::os::unix::fs::symlink(&target,&link_path);
+            #[cfg(windows)]
+            let r=if workspace_path(&ctx,&target).is_dir(){
+                std::os::windows::fs::symlink_dir(&target,&link_path)
+            }else{
+                std::os::windows::fs::symlink_file(&target,&link_path)
+            };
+            r.map_err(io_failed(format!("symlink {link} -> {target}")))?;
+            Ok(().into())
+        });
+        fs_module.register_class(Class::new("FileStat".into(),vec![
+            VariableDeclaration::new("size".into(),"Int".into()),
+            VariableDeclaration::new("mtime".into(),"Int".into()),
+            VariableDeclaration::new("isDir".into(),"Bool".into()),
+        ]));
+        fs_module
+    }
+    /// JSON与Map、Array等值之间的转换，null对应Unit
+    pub fn with_json_module()->Self{
+        let mut json=Module::new("json");
+        json.register_pipe_function("parse",|_,args|{
+            check_arity("parse",&args,1)?;
+            let text=string_arg(&args,0)?;
+            let value=serde_json::from_str(&text).map_err(|e|PipelineError::InvalidArgument(format!("invalid JSON: {e}")))?;
+            from_json(value)
+        });
+        //stringify(value,[pretty:Bool])，pretty为true时缩进两个空格并换行
+        json.register_pipe_function("stringify",|_,args|{
+            check_arity("stringify",&args,1)?;
+            let pretty=match args.get(1) {
+                Some(p)=>p.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?,
+                None=>false
+            };
+            let value=to_json(&args[0].as_dynamic())?;
+            let text=if pretty{
+                serde_json::to_string_pretty(&value)
+            }else{
+                serde_json::to_string(&value)
+            };
+            Ok(text.map_err(|e|PipelineError::InvalidArgument(e.to_string()))?.into())
+        });
+        json.register_pipe_function("pretty",|_,args|{
+            check_arity("pretty",&args,1)?;
+            let value=to_json(&args[0].as_dynamic())?;
+            let text=serde_json::to_string_pretty(&value).map_err(|e|PipelineError::InvalidArgument(e.to_string()))?;
+            Ok(text.into())
+        });
+        //get(obj,"a.b[0]")，路径不存在时返回null
+        json.register_pipe_function("get",|_,args|{
+            check_arity("get",&args,2)?;
+            let path=string_arg(&args,1)?;
+            lookup(&args[0],&path)
+        });
+        json
+    }
+    pub fn with_toml_module()->Self{
+        let mut toml_module=Module::new("toml");
+        toml_module.register_pipe_function("parse",|_,args|{
+            check_arity("parse",&args,1)?;
+            let text=string_arg(&args,0)?;
+            let table=text.parse::<toml::Table>().map_err(|e|PipelineError::InvalidArgument(format!("invalid TOML: {e}")))?;
+            Ok(from_toml(toml::Value::Table(table)))
+        });
+        toml_module.register_pipe_function("stringify",|_,args|{
+            check_arity("stringify",&args,1)?;
+            let value=match to_toml(&args[0].as_dynamic())? {
+                toml::Value::Table(t)=>t,
+                _=>return Err(PipelineError::ExpectedType("Map".into()))
+            };
+            let text=toml::to_string(&value).map_err(|e|PipelineError::InvalidArgument(e.to_string()))?;
+            Ok(text.into())
+        });
+        toml_module.register_pipe_function("get",|_,args|{
+            check_arity("get",&args,2)?;
+            let path=string_arg(&args,1)?;
+            lookup(&args[0],&path)
+        });
+        //set(file,"package.version",value)，只修改对应的值，文件中的注释、顺序和格式保持不变
+        toml_module.register_pipe_function("set",|ctx,args|{
+            check_arity("set",&args,3)?;
+            let file=string_arg(&args,0)?;
+            let path=string_arg(&args,1)?;
+            let file_path=workspace_path(&ctx,&file);
+            let text=fs::read_to_string(&file_path).map_err(io_failed(format!("read {file}")))?;
+            let mut document=text.parse::<toml_edit::DocumentMut>()
+                .map_err(|e|PipelineError::InvalidArgument(format!("invalid TOML in {file}: {e}")))?;
+            set_toml(&mut document,&path,&args[2].as_dynamic())?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("set {path} in {file}"));
+                return Ok(().into())
+            }
+            fs::write(&file_path,document.to_string()).map_err(io_failed(format!("write {file}")))?;
+            Ok(().into())
+        });
+        toml_module
+    }
+    pub fn with_yaml_module()->Self{
+        let mut yaml=Module::new("yaml");
+        yaml.register_pipe_function("parse",|_,args|{
+            check_arity("parse",&args,1)?;
+            let text=string_arg(&args,0)?;
+            let value=serde_yaml::from_str(&text).map_err(|e|PipelineError::InvalidArgument(format!("invalid YAML: {e}")))?;
+            from_yaml(value)
+        });
+        yaml.register_pipe_function("stringify",|_,args|{
+            check_arity("stringify",&args,1)?;
+            let value=to_yaml(&args[0].as_dynamic())?;
+            let text=serde_yaml::to_string(&value).map_err(|e|PipelineError::InvalidArgument(e.to_string()))?;
+            Ok(text.into())
+        });
+        yaml.register_pipe_function("get",|_,args|{
+            check_arity("get",&args,2)?;
+            let path=string_arg(&args,1)?;
+            lookup(&args[0],&path)
+        });
+        yaml
     }
     pub fn with_ssh_module()->Self{
         let mut ssh=Module::new("ssh");
         ssh.register_pipe_function("withContext",|ctx,args|{
-            let user=args.get(0).unwrap().as_string().unwrap();
-            let password=args.get(1).unwrap().as_string().unwrap();
-            let host=args.get(2).unwrap().as_string().unwrap();
+            check_arity("withContext",&args,3)?;
+            let user=string_arg(&args,0)?;
+            let password=string_arg(&args,1)?;
+            let host=string_arg(&args,2)?;
             // let mut session = ssh::create_session()
             //     .username(user.as_str())
             //     .password(password.as_str())
@@ -532,60 +962,51 @@ impl Module{
             Ok(Value::Immutable(obj))
         });
         ssh.register_pipe_function("exec",|ctx,args|{
-            let ssh_ctx=args.get(0).unwrap().as_dynamic().as_struct().unwrap();
-            let user=ssh_ctx.get_prop("user").unwrap().as_string().unwrap();
-            let password=ssh_ctx.get_prop("password").unwrap().as_string().unwrap();
-            let host=ssh_ctx.get_prop("host").unwrap().as_string().unwrap();
-            let mut session = ssh::create_session()
-                .username(user.as_str())
-                .password(password.as_str())
-                .private_key_path("./id_rsa")
-                .connect(host.as_str())
-                .unwrap()
-                .run_local();
-            let exec=session.open_exec().unwrap();
-            let cmd=args.get(1).unwrap().as_string().unwrap();
-            let res=exec.send_command(cmd.as_str()).unwrap();
-            let s=String::from_utf8(res).unwrap();
+            check_arity("exec",&args,2)?;
+            let (user,password,host)=ssh_context(&args[0])?;
+            let cmd=string_arg(&args,1)?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("ssh exec \"{cmd}\" on {user}@{host}"));
+                return Ok(String::new().into())
+            }
+            let mut session=ssh_session(&ctx,&user,&password,&host)?;
+            let exec=session.open_exec().map_err(|e|ssh_failed(&ctx,&host,e))?;
+            let res=exec.send_command(cmd.as_str()).map_err(|e|ssh_failed(&ctx,&host,e))?;
+            let s=String::from_utf8_lossy(&res).to_string();
             session.close();
+            check_deadline(&ctx)?;
             Ok(s.into())
         });
         ssh.register_pipe_function("upload",|ctx,args|{
-            let ssh_ctx=args.get(0).unwrap().as_dynamic().as_struct().unwrap();
-            let user=ssh_ctx.get_prop("user").unwrap().as_string().unwrap();
-            let password=ssh_ctx.get_prop("password").unwrap().as_string().unwrap();
-            let host=ssh_ctx.get_prop("host").unwrap().as_string().unwrap();
-            let mut session = ssh::create_session()
-                .username(user.as_str())
-                .password(password.as_str())
-                .private_key_path("./id_rsa")
-                .connect(host.as_str())
-                .unwrap()
-                .run_local();
-            let scp=session.open_scp().unwrap();
-            let local=args.get(1).unwrap().as_string().unwrap();
-            let remote=args.get(2).unwrap().as_string().unwrap();
-            scp.upload(local.as_str(),remote.as_str()).unwrap();
+            check_arity("upload",&args,3)?;
+            let (user,password,host)=ssh_context(&args[0])?;
+            let local=string_arg(&args,1)?;
+            let remote=string_arg(&args,2)?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("ssh upload {local} -> {user}@{host}:{remote}"));
+                return Ok(().into())
+            }
+            let mut session=ssh_session(&ctx,&user,&password,&host)?;
+            let scp=session.open_scp().map_err(|e|ssh_failed(&ctx,&host,e))?;
+            scp.upload(local.as_str(),remote.as_str()).map_err(|e|ssh_failed(&ctx,&host,e))?;
             session.close();
+            check_deadline(&ctx)?;
             Ok(().into())
         });
         ssh.register_pipe_function("download",|ctx,args|{
-            let ssh_ctx=args.get(0).unwrap().as_dynamic().as_struct().unwrap();
-            let user=ssh_ctx.get_prop("user").unwrap().as_string().unwrap();
-            let password=ssh_ctx.get_prop("password").unwrap().as_string().unwrap();
-            let host=ssh_ctx.get_prop("host").unwrap().as_string().unwrap();
-            let mut session = ssh::create_session()
-                .username(user.as_str())
-                .password(password.as_str())
-                .private_key_path("./id_rsa")
-                .connect(host.as_str())
-                .unwrap()
-                .run_local();
-            let scp=session.open_scp().unwrap();
-            let local=args.get(1).unwrap().as_string().unwrap();
-            let remote=args.get(2).unwrap().as_string().unwrap();
-            scp.download(local.as_str(),remote.as_str()).unwrap();
+            check_arity("download",&args,3)?;
+            let (user,password,host)=ssh_context(&args[0])?;
+            let local=string_arg(&args,1)?;
+            let remote=string_arg(&args,2)?;
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("ssh download {user}@{host}:{remote} -> {local}"));
+                return Ok(().into())
+            }
+            let mut session=ssh_session(&ctx,&user,&password,&host)?;
+            let scp=session.open_scp().map_err(|e|ssh_failed(&ctx,&host,e))?;
+            scp.download(local.as_str(),remote.as_str()).map_err(|e|ssh_failed(&ctx,&host,e))?;
             session.close();
+            check_deadline(&ctx)?;
             Ok(().into())
         });
         return ssh
@@ -593,76 +1014,92 @@ impl Module{
     pub fn with_layout_module()->Self{
         let  mut layout=Module::new("layout");
         layout.register_pipe_function("layout",|ctx,args|{
-            let name=args.get(0).unwrap().as_string().unwrap();
+            check_arity("layout",&args,2)?;
+            let name=string_arg(&args,0)?;
             println!("\x1b[32musing layout {}",name);
-            let mut ptr=args.get(1).unwrap().as_dynamic().as_fn_ptr().unwrap();
+            let mut ptr=closure_arg(&args,1)?;
             let mut e=PipelineEngine::default();
             let share_module=PipelineEngine::context_with_shared_module(&ctx);
             let i=Interpreter::with_shared_module(share_module);
             e.set_interpreter(&i);
-            let scope=PipelineEngine::context_with_scope(&ctx);
-            let mut scope=scope.write().unwrap();
-            let v:Arc<RwLock<HashMap<String,String>>>=Arc::new(RwLock::new(HashMap::new()));
+            let mut scope=Scope::new();
             scope.set("layoutName",Value::Mutable(Arc::new(RwLock::new(Dynamic::String(name)))));
-            drop(scope);
-            ptr.call(&mut e,ctx.clone()).unwrap();
+            ptr.call_with_scope(&mut e,ctx.clone(),scope)?;
             println!("╰─▶successfully finished.\x1b[0m");
             Ok(().into())
         });
         layout.register_pipe_function("template",|ctx,args|{
-            let target=args.get(0).unwrap().as_string().unwrap();
-            let template=args.get(1).unwrap().as_string().unwrap();
+            check_arity("template",&args,2)?;
+            let target=string_arg(&args,0)?;
+            let template=string_arg(&args,1)?;
             println!("╰─▶using template {} to generate {}.",template,target);
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("template {template} -> {target}"));
+                return Ok(().into())
+            }
 
             let mut e=PipelineEngine::default();
-            let scope=PipelineEngine::context_with_scope(&ctx);
-            let mut scope=scope.write().unwrap();
             let v:Arc<RwLock<HashMap<String,String>>>=Arc::new(RwLock::new(HashMap::new()));
+            let mut scope=Scope::new();
             scope.set("ctx",Value::Mutable(Arc::new(RwLock::new(Dynamic::Native(v.clone())))));
-            drop(scope);
             let share_module=PipelineEngine::context_with_shared_module(&ctx);
             let i=Interpreter::with_shared_module(share_module);
             e.set_interpreter(&i);
             let mut ptr=args.get(2);
             // let mut ptr=args.get(2).unwrap().as_dynamic().as_fn_ptr().unwrap();
-            if let Some(p)=ptr{
-                let mut ptr=p.as_dynamic().as_fn_ptr().unwrap();
-                ptr.call(&mut e,ctx.clone()).unwrap();
+            if ptr.is_some(){
+                let ptr=closure_arg(&args,2)?;
+                ptr.call_with_scope(&mut e,ctx.clone(),scope)?;
             }
             let m=v.read().unwrap();
-            let layout_name=PipelineEngine::context_with_dynamic(&ctx,"layoutName").unwrap().as_string().unwrap();
+            let layout_name=PipelineEngine::context_with_dynamic(&ctx,"layoutName")
+                .and_then(|l|l.as_string())
+                .ok_or(PipelineError::VariableUndefined("layoutName".into()))?;
             let home_dir = dirs::home_dir().expect("无法获取用户根目录");
             let template_path=home_dir.join(".pipeline").join(format!("layout/{}/{}",layout_name,template));
-            let template_content=fs::read_to_string(template_path).unwrap();
+            let template_content=fs::read_to_string(&template_path)
+                .map_err(|e|PipelineError::IoFailed(format!("read template {}",template_path.display()),e.to_string()))?;
             let re = Regex::new(r"\$\{([a-zA-Z_][a-zA-Z0-9_]*)\}").unwrap();
+            //模板中的变量都需要通过set设置
+            if let Some(caps)=re.captures_iter(template_content.as_str()).find(|caps|!m.contains_key(&caps[1])){
+                return Err(PipelineError::KeyNotFound(caps[1].into()))
+            }
             let replaced = re.replace_all(template_content.as_str(), |caps: &regex::Captures| {
                 let key=&caps[1];
-                let r=m.get(key).unwrap();
-                r.as_str()
+                m[key].clone()
             });
             let target_path=PathBuf::from(target.as_str());
-            if !target_path.exists(){
-                fs::create_dir_all(target_path.parent().unwrap()).unwrap();
+            let io_failed=|e:io::Error|PipelineError::IoFailed(format!("generate {target}"),e.to_string());
+            if let Some(parent)=target_path.parent(){
+                fs::create_dir_all(parent).map_err(io_failed)?;
             }
-            let mut file=File::create(target).unwrap();
-            file.write_all(replaced.as_bytes()).unwrap();
+            let mut file=File::create(&target_path).map_err(io_failed)?;
+            file.write_all(replaced.as_bytes()).map_err(io_failed)?;
             Ok(().into())
         });
         layout.register_pipe_function("set",|ctx,args|{
-            let hashmap=args.get(0).unwrap().as_dynamic().as_native().unwrap();
+            check_arity("set",&args,3)?;
+            let hashmap=args[0].as_dynamic();
+            let hashmap=hashmap.as_native().ok_or(PipelineError::UnexpectedType(hashmap.type_name()))?;
             let mut hashmap=hashmap.write().unwrap();
-            let hashmap=hashmap.downcast_mut::<HashMap<String,String>>().unwrap();
-            let key=args.get(1).unwrap().as_string().unwrap();
-            let value=args.get(2).unwrap().as_string().unwrap();
+            let hashmap=hashmap.downcast_mut::<HashMap<String,String>>().ok_or(PipelineError::UnexpectedType("Native".into()))?;
+            let key=string_arg(&args,1)?;
+            let value=string_arg(&args,2)?;
             hashmap.insert(key,value);
             Ok(().into())
         });
         layout.register_pipe_function("folder",|ctx,args|{
-            let folder_name=args.get(0).unwrap().as_string().unwrap();
+            check_arity("folder",&args,1)?;
+            let folder_name=string_arg(&args,0)?;
             println!("╰─▶creating folder {}.",folder_name);
+            if is_dry_run(&ctx){
+                record_effect(&ctx,format!("folder {folder_name}"));
+                return Ok(().into())
+            }
             let target_path=PathBuf::from(folder_name.as_str());
             if !target_path.exists(){
-                fs::create_dir_all(target_path).unwrap();
+                fs::create_dir_all(target_path)
+                    .map_err(|e|PipelineError::IoFailed(format!("create folder {folder_name}"),e.to_string()))?;
             }
             Ok(().into())
         });
@@ -699,28 +1136,503 @@ impl Module{
         }
     }
 }
+/// 值的字符串形式，对象所属的class定义了toString方法时使用其返回值
+pub fn display_value(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,value:&Value)->PipelineResult<String>{
+    let d=value.as_dynamic();
+    if let Dynamic::Struct(_)=&d{
+        let method=PipelineEngine::context_with_shared_module(ctx).read().unwrap().get_class_function(&d.type_name(),"toString");
+        if let Some(method)=method{
+            return Ok(method.call(ctx.clone(),vec![value.clone()])?.as_dynamic().to_string())
+        }
+    }
+    Ok(d.to_string())
+}
+/// String的方法，第一个参数为字符串本身，下标和长度按字符计算
+fn string_class()->Class{
+    let mut string=Class::new("String".into(),vec![]);
+    string.register_native_method("split",|_,args|{
+        check_arity("split",&args,2)?;
+        let s=string_arg(&args,0)?;
+        let separator=string_arg(&args,1)?;
+        Ok(string_array(s.split(separator.as_str())))
+    });
+    string.register_native_method("lines",|_,args|{
+        check_arity("lines",&args,1)?;
+        Ok(string_array(string_arg(&args,0)?.lines()))
+    });
+    string.register_native_method("trim",|_,args|{
+        check_arity("trim",&args,1)?;
+        Ok(string_arg(&args,0)?.trim().to_string().into())
+    });
+    string.register_native_method("lowercase",|_,args|{
+        check_arity("lowercase",&args,1)?;
+        Ok(string_arg(&args,0)?.to_lowercase().into())
+    });
+    string.register_native_method("uppercase",|_,args|{
+        check_arity("uppercase",&args,1)?;
+        Ok(string_arg(&args,0)?.to_uppercase().into())
+    });
+    string.register_native_method("startsWith",|_,args|{
+        check_arity("startsWith",&args,2)?;
+        Ok(string_arg(&args,0)?.starts_with(string_arg(&args,1)?.as_str()).into())
+    });
+    string.register_native_method("endsWith",|_,args|{
+        check_arity("endsWith",&args,2)?;
+        Ok(string_arg(&args,0)?.ends_with(string_arg(&args,1)?.as_str()).into())
+    });
+    string.register_native_method("contains",|_,args|{
+        check_arity("contains",&args,2)?;
+        Ok(string_arg(&args,0)?.contains(string_arg(&args,1)?.as_str()).into())
+    });
+    string.register_native_method("replace",|_,args|{
+        check_arity("replace",&args,3)?;
+        let s=string_arg(&args,0)?;
+        Ok(s.replace(string_arg(&args,1)?.as_str(),string_arg(&args,2)?.as_str()).into())
+    });
+    string.register_native_method("substring",|_,args|{
+        check_arity("substring",&args,2)?;
+        let chars:Vec<char>=string_arg(&args,0)?.chars().collect();
+        let start=int_arg(&args,1)?;
+        let end=match args.get(2) {
+            Some(_)=>int_arg(&args,2)?,
+            None=>chars.len() as i64
+        };
+        if end<0||end as usize>chars.len(){
+            return Err(PipelineError::IndexOutOfBounds(end,chars.len()+1))
+        }
+        if start<0||start>end{
+            return Err(PipelineError::IndexOutOfBounds(start,end as usize+1))
+        }
+        Ok(chars[start as usize..end as usize].iter().collect::<String>().into())
+    });
+    string.register_native_method("padStart",|_,args|{
+        check_arity("padStart",&args,2)?;
+        let s=string_arg(&args,0)?;
+        let len=int_arg(&args,1)?;
+        let pad=match args.get(2) {
+            Some(_)=>string_arg(&args,2)?,
+            None=>" ".into()
+        };
+        let mut pad_chars=pad.chars();
+        let (Some(pad),None)=(pad_chars.next(),pad_chars.next()) else {
+            return Err(PipelineError::InvalidArgument(format!("padStart expects a single pad character, but got \"{pad}\"")))
+        };
+        let count=(len.max(0) as usize).saturating_sub(s.chars().count());
+        Ok(format!("{}{s}",pad.to_string().repeat(count)).into())
+    });
+    string.register_native_method("repeat",|_,args|{
+        check_arity("repeat",&args,2)?;
+        let s=string_arg(&args,0)?;
+        let n=int_arg(&args,1)?;
+        if n<0{
+            return Err(PipelineError::InvalidArgument(format!("repeat count must not be negative, but got {n}")))
+        }
+        Ok(s.repeat(n as usize).into())
+    });
+    string.register_native_method("toInt",|_,args|{
+        check_arity("toInt",&args,1)?;
+        let s=string_arg(&args,0)?;
+        let i:i64=s.parse().map_err(|_|PipelineError::InvalidArgument(format!("\"{s}\" is not a valid Int")))?;
+        Ok(i.into())
+    });
+    string.register_native_method("toFloat",|_,args|{
+        check_arity("toFloat",&args,1)?;
+        let s=string_arg(&args,0)?;
+        let f:f64=s.parse().map_err(|_|PipelineError::InvalidArgument(format!("\"{s}\" is not a valid Float")))?;
+        Ok(f.into())
+    });
+    //"{} is {}".format(a,b)，{}依次取参数，{0}按下标取参数，{{和}}为花括号本身
+    string.register_native_method("format",|ctx,args|{
+        check_arity("format",&args,1)?;
+        let template=string_arg(&args,0)?;
+        let values=&args[1..];
+        let mut r=String::new();
+        let mut next=0;
+        let mut chars=template.chars().peekable();
+        while let Some(c)=chars.next(){
+            match c {
+                '{' if chars.peek()==Some(&'{')=>{
+                    chars.next();
+                    r.push('{');
+                }
+                '}' if chars.peek()==Some(&'}')=>{
+                    chars.next();
+                    r.push('}');
+                }
+                '{'=>{
+                    let mut index=String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}')=>break,
+                            Some(c)=>index.push(c),
+                            None=>return Err(PipelineError::InvalidArgument(format!("unclosed placeholder in \"{template}\"")))
+                        }
+                    }
+                    let index=if index.is_empty(){
+                        next+=1;
+                        next-1
+                    }else{
+                        index.parse().map_err(|_|PipelineError::InvalidArgument(format!("invalid placeholder {{{index}}}")))?
+                    };
+                    let value=values.get(index).ok_or(PipelineError::IndexOutOfBounds(index as i64,values.len()))?;
+                    r.push_str(&display_value(&ctx,value)?);
+                }
+                c=>r.push(c)
+            }
+        }
+        Ok(r.into())
+    });
+    string
+}
+/// Array的方法
+fn array_class()->Class{
+    let mut array=Class::new("Array".into(),vec![]);
+    array.register_native_method("join",|ctx,args|{
+        check_arity("join",&args,1)?;
+        let separator=match args.get(1) {
+            Some(_)=>string_arg(&args,1)?,
+            None=>"".into()
+        };
+        let items=args[0].as_dynamic().as_array().ok_or(PipelineError::ExpectedType("Array".into()))?;
+        let items=items.iter().map(|i|display_value(&ctx,i)).collect::<PipelineResult<Vec<String>>>()?;
+        Ok(items.join(separator.as_str()).into())
+    });
+    array
+}
+fn string_array<'a>(items:impl Iterator<Item=&'a str>)->Value{
+    Value::with_mutable(Dynamic::Array(items.map(|i|i.to_string().into()).collect()))
+}
+/// 检查原生函数的参数个数，min为必需参数的个数
+fn check_arity(name:&str,args:&[Value],min:usize)->PipelineResult<()>{
+    if args.len()<min{
+        return Err(PipelineError::ArityMismatch(name.into(),min,args.len()))
+    }
+    Ok(())
+}
+/// 取出第index个参数并要求其为String
+fn string_arg(args:&[Value],index:usize)->PipelineResult<String>{
+    args.get(index).and_then(|a|a.as_string()).ok_or(PipelineError::ExpectedType("String".into()))
+}
+/// readInt等函数的公共实现，参数为[hint],[options]，两者都可以省略
+fn read_prompt(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,kind:PromptKind,args:&[Value])->PipelineResult<Value>{
+    let (hint,options)=match args.first().map(|a|a.as_dynamic()) {
+        Some(Dynamic::String(hint))=>(hint,args.get(1)),
+        _=>(String::new(),args.first())
+    };
+    prompt(ctx,kind,&hint,PromptOptions::from_value(options)?)
+}
+/// 文件操作失败时的错误，action描述失败的操作
+fn io_failed(action:String)->impl FnOnce(io::Error)->PipelineError{
+    move|e|PipelineError::IoFailed(action,e.to_string())
+}
+/// 取出第index个参数并要求其为Int
+fn int_arg(args:&[Value],index:usize)->PipelineResult<i64>{
+    args.get(index).and_then(|a|a.as_integer()).ok_or(PipelineError::ExpectedType("Int".into()))
+}
+/// 取出第index个参数并要求其为闭包
+fn closure_arg(args:&[Value],index:usize)->PipelineResult<Box<FnPtr>>{
+    args.get(index)
+        .and_then(|a|a.as_dynamic().as_fn_ptr())
+        .filter(|f|f.fn_def.is_some())
+        .ok_or(PipelineError::ExpectedType("Closure".into()))
+}
+/// 将Array、Range或Map展开为逐个传给lambda的参数，Array和Range元素为[x]，Map条目为[k,v]
+fn elements(value:&Value)->PipelineResult<Vec<Vec<Value>>>{
+    match value.as_dynamic() {
+        Dynamic::Array(a)=>Ok(a.into_iter().map(|i|vec![i]).collect()),
+        Dynamic::Range(r)=>Ok(r.iter().map(|i|vec![i.into()]).collect()),
+        Dynamic::Map(m)=>Ok(m.into_iter().map(|(k,v)|vec![Value::Immutable(k),v]).collect()),
+        t=>Err(PipelineError::UnexpectedType(t.type_name()))
+    }
+}
+/// 调用lambda并要求其返回Bool
+fn predicate(f:&FnPtr,engine:&mut PipelineEngine,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,args:Vec<Value>)->PipelineResult<bool>{
+    f.call_with_args(engine,ctx,args)?.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))
+}
+/// 从withContext创建的SSHContext中取出(user,password,host)
+fn ssh_context(value:&Value)->PipelineResult<(String,String,String)>{
+    let ssh_ctx=value.as_dynamic();
+    let ssh_ctx=ssh_ctx.as_struct().ok_or(PipelineError::ExpectedType("SSHContext".into()))?;
+    let prop=|name:&str|ssh_ctx.get_prop(name).and_then(|p|p.as_string())
+        .ok_or(PipelineError::PropertyUndefined("SSHContext".into(),name.into()));
+    Ok((prop("user")?,prop("password")?,prop("host")?))
+}
+/// 连接ssh服务器。在设置了timeout的任务中，连接以及之后每次读写等待的时间不超过任务的剩余时间
+fn ssh_session(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,user:&str,password:&str,host:&str)->PipelineResult<LocalSession<TcpStream>>{
+    check_deadline(ctx)?;
+    let mut builder=ssh::create_session()
+        .username(user)
+        .password(password)
+        .private_key_path("./id_rsa");
+    if let Some(remaining)=remaining_time(ctx){
+        builder=builder.timeout(Some(remaining));
+    }
+    let session=builder.connect(host).map_err(|e|ssh_failed(ctx,host,e))?;
+    Ok(session.run_local())
+}
+/// ssh操作失败的错误，任务已经超时时(例如读写等待超过了剩余时间)报告任务超时
+fn ssh_failed(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,host:&str,e:impl std::fmt::Display)->PipelineError{
+    match check_deadline(ctx) {
+        Err(timed_out)=>timed_out,
+        Ok(_)=>PipelineError::IoFailed(format!("ssh {host}"),e.to_string())
+    }
+}
+/// 创建一个能够访问脚本中定义的函数和已导入模块的引擎，用于执行任务闭包
+fn task_engine(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>)->PipelineEngine{
+    let mut e=PipelineEngine::default_with_pipeline();
+    let share_module=PipelineEngine::context_with_shared_module(ctx);
+    let modules=PipelineEngine::context_with_modules(ctx);
+    let modules=modules.read().unwrap();
+    let mut i=Interpreter::with_shared_module(share_module);
+    for m in modules.iter(){
+        i.register_module(m.0.clone(),m.1.clone());
+    }
+    e.set_interpreter(&i);
+    e
+}
+/// step(name,[options],closure)和parallel(name,[options],closure)的公共实现。
+/// 在pipeline中只登记任务，由pipeline统一调度；不在pipeline中时立即执行
+fn declare_task(ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,args:Vec<Value>,kind:TaskKind)->PipelineResult<Value>{
+    let function_name=match kind {
+        TaskKind::Step=>"step",
+        TaskKind::Parallel=>"parallel"
+    };
+    check_arity(function_name,&args,2)?;
+    let name=string_arg(&args,0)?;
+    let closure=closure_arg(&args,args.len()-1)?;
+    let options=TaskOptions::from_args(&args)?;
+    //在声明时复制全局状态，使任务看到的是声明处的workspace等设置，而不是pipeline闭包执行完后的状态
+    let global=PipelineEngine::context_with_global_state(&ctx).read().unwrap().clone();
+    let task_ctx=PipelineEngine::with_value(ctx.clone(),"$global_state",PipelineContextValue::GlobalState(Arc::new(RwLock::new(global))));
+    let task=Task{ name, kind, options, closure, ctx:task_ctx };
+    if let Some(tasks)=PipelineEngine::context_with_tasks(&ctx){
+        let mut tasks=tasks.write().unwrap();
+        if tasks.iter().any(|t|t.name==task.name){
+            return Err(PipelineError::DuplicateTask(task.name))
+        }
+        tasks.push(task);
+        return Ok(().into())
+    }
+    let path_task=PipelineEngine::context_with_global_value(&ctx,"path_task");
+    if path_task==task.name||path_task.as_str()=="all"{
+        let r=run_task(&task);
+        finish_task(None,&task.name,&task.options,r)?;
+    }
+    Ok(().into())
+}
+/// 执行一个任务，每个任务拥有独立的环境变量和全局状态副本，避免并发任务中的workspace互相影响
+fn run_task(task:&Task)->PipelineResult<Value>{
+    let ctx=task.ctx.clone();
+    let global=PipelineEngine::context_with_global_state(&ctx).read().unwrap().clone();
+    let ctx=PipelineEngine::with_value(ctx,"$global_state",PipelineContextValue::GlobalState(Arc::new(RwLock::new(global))));
+    let ctx=with_env_scope(ctx,task.options.env.clone(),task.options.clear_env);
+    let ctx=match task.options.timeout {
+        Some(timeout)=>PipelineEngine::with_value(ctx,"$deadline",PipelineContextValue::Deadline(deadline_after(Instant::now(),timeout)?,timeout)),
+        None=>ctx
+    };
+    //任务中再声明的step/parallel直接执行，而不是登记到已经开始调度的pipeline中
+    let ctx=PipelineEngine::with_value(ctx,"$tasks",PipelineContextValue::Local(String::new()));
+    let ctx=PipelineEngine::with_value(ctx,"op_join_set",PipelineContextValue::JoinSet(Arc::new(RwLock::new(vec![]))));
+    let ctx=PipelineEngine::with_value(ctx,"$task_name",PipelineContextValue::Local(task.name.clone()));
+    let plan=Arc::new(RwLock::new(vec![]));
+    let ctx=PipelineEngine::with_value(ctx,"$plan",PipelineContextValue::Plan(plan.clone()));
+    let mut e=task_engine(&ctx);
+    let mut closure=task.closure.clone();
+    let r=closure.call(&mut e,ctx.clone());
+    let r=join_task_threads(&ctx,r).map_err(|e|match PipelineEngine::context_with_source(&ctx) {
+        Some(source)=>e.with_source(&source),
+        None=>e
+    });
+    //任务中最后执行的内置函数无法被中断时，在任务结束后检查是否超时
+    let r=r.and_then(|v|check_deadline(&ctx).map(|_|v));
+    if is_dry_run(&ctx){
+        print_plan(&task.name,&plan.read().unwrap());
+    }
+    r
+}
+/// 输出一个任务在dry-run模式下的执行计划，整体输出避免与并发任务的输出交错
+fn print_plan(name:&str,plan:&[String]){
+    let mut out=io::stdout().lock();
+    let _=writeln!(out,"\x1b[36m[Plan]:Task {name}\x1b[0m");
+    if plan.is_empty(){
+        let _=writeln!(out,"  (nothing to do)");
+    }
+    for (i,effect) in plan.iter().enumerate(){
+        let _=writeln!(out,"  {}. {effect}",i+1);
+    }
+}
+/// 等待任务中通过op_join_set启动的线程结束，任务本身成功时返回第一个线程错误
+fn join_task_threads(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,r:PipelineResult<Value>)->PipelineResult<Value>{
+    let mut r=r;
+    let join_set=PipelineEngine::context_with_join_set(ctx,"op_join_set");
+    let mut join_set=join_set.write().unwrap();
+    while let Some(e)=join_set.pop(){
+        let r0=e.join().unwrap();
+        if r.is_ok(){
+            r=r0.map(|_|().into());
+        }
+    }
+    r
+}
 impl<
-    T:Fn(A,B)->Ret + 'static + Send + Sync,A:NativeType + From<Value>,
-    B:NativeType + From<Value>,
-    Ret:NativeType + From<Value>>
+    T:Fn(A,B)->Ret + 'static + Send + Sync,A:NativeType + TryFrom<Value,Error=PipelineError>,
+    B:NativeType + TryFrom<Value,Error=PipelineError>,
+    Ret:NativeType>
 NativeFunction<(A,B)> for T
     where Value: From<Ret>
 {
     fn into_pipe_function(self) -> Arc<PipeFn> {
         Arc::new(move |ctx:Arc<RwLock<dyn Context< PipelineContextValue >>>, args:Vec< Value >|->PipelineResult<Value>{
-            let mut it=args.iter();
-            let a=it.next().unwrap().clone().into();
-            let b=it.next().unwrap().clone().into();
+            check_arity("native",&args,2)?;
+            let a=args[0].clone().try_into()?;
+            let b=args[1].clone().try_into()?;
             let r=self(a,b);
             return Ok(r.into())
         })
     }
 }
-impl <T:Fn(A) + 'static + Send + Sync,A:NativeType + From<Value>>NativeFunction<(A)> for T {
+impl <T:Fn(A) + 'static + Send + Sync,A:NativeType + TryFrom<Value,Error=PipelineError>>NativeFunction<(A)> for T {
     fn into_pipe_function(self) -> Arc<PipeFn> {
         Arc::new(move|_:Arc<RwLock<dyn Context< PipelineContextValue >>>, args:Vec< Value >|->PipelineResult<Value>{
-            self(args[0].clone().into());
+            check_arity("native",&args,1)?;
+            self(args[0].clone().try_into()?);
             Ok(().into())
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(script:&str)->PipelineResult<Value>{
+        PipelineEngine::default().run_script(script)
+    }
+    fn string(script:&str)->String{
+        run(script).unwrap().as_dynamic().to_string()
+    }
+    fn run_pipeline(script:&str)->PipelineResult<Value>{
+        PipelineEngine::default_with_pipeline().run_script(&format!("import pipe\n{script}"))
+    }
+    /// 以workspace为工作目录，导入fs模块后执行脚本，dry_run为true时返回记录的执行计划
+    fn run_fs(workspace:&Path,dry_run:bool,script:&str)->(PipelineResult<Value>,Vec<String>){
+        let mut engine=PipelineEngine::default();
+        engine.register_module(Module::with_fs_module());
+        let ctx=PipelineEngine::background();
+        let global=PipelineEngine::context_with_global_state(&ctx);
+        global.write().unwrap().set_value("workspace",workspace.to_string_lossy().to_string());
+        global.write().unwrap().set_value("dry_run",dry_run.to_string());
+        let plan=Arc::new(RwLock::new(vec![]));
+        let ctx=PipelineEngine::with_value(ctx,"$plan",PipelineContextValue::Plan(plan.clone()));
+        let r=engine.run_script_with_context(ctx,&format!("import fs\n{script}"));
+        let plan=plan.read().unwrap().clone();
+        (r,plan)
+    }
+    fn dry_run(script:&str)->(PipelineResult<Value>,Vec<String>){
+        run_fs(Path::new("./"),true,script)
+    }
+    /// 测试用的临时workspace，名称中带有glob的特殊字符
+    fn test_workspace(name:&str)->PathBuf{
+        let dir=std::env::temp_dir().join(format!("pipeline-test-[{name}]-{}",std::process::id()));
+        let _=fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn len_and_string_methods_count_chars(){
+        assert_eq!(string("return len(\"héllo世界\")"),"7");
+        assert_eq!(string("let s=\"héllo世界\"\nreturn s.substring(0,len(s))"),"héllo世界");
+        assert_eq!(string("let s=\"世界\"\nreturn s[len(s)-1]"),"界");
+        assert_eq!(string("return \"é\".padStart(3,\"*\")"),"**é");
+    }
+    #[test]
+    fn temp_dir_is_only_recorded_in_dry_run(){
+        let (r,plan)=dry_run("return fs::tempDir()");
+        let path=r.unwrap().as_string().unwrap();
+        assert!(!Path::new(&path).exists());
+        assert_eq!(plan,vec![format!("tempDir {path}")]);
+    }
+    #[cfg(unix)]
+    #[test]
+    fn failed_command_fails_the_step_and_the_pipeline(){
+        let script="pipeline(\"ci\"){\n  step(\"a\"){ cmd(\"exit 1\") }\n  step(\"b\"){ cmd(\"exit 0\") }\n}";
+        let e=run_pipeline(script).unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::TaskFailed(p,tasks) if p=="ci"&&tasks==&vec!["a".to_string()]),"{e}");
+    }
+    #[cfg(unix)]
+    #[test]
+    fn allow_failure_keeps_the_pipeline_running(){
+        let script="let ran=[]\npipeline(\"ci\"){\n  step(\"a\",allowFailure=true){ cmd(\"exit 1\") }\n  step(\"b\"){ let r=cmd(\"exit 4\",allowFailure=true)\n ran.append(r.code) }\n}\nreturn ran";
+        assert_eq!(run_pipeline(script).unwrap().as_dynamic().to_string(),"[4]");
+    }
+    #[test]
+    fn string_methods(){
+        assert_eq!(string("return \"a,b,,c\".split(\",\")"),"[a,b,,c]");
+        assert_eq!(string("return \"x\\ny\".lines()"),"[x,y]");
+        assert_eq!(string("return \"  Hi \".trim().lowercase()+\"Hi\".uppercase()"),"hiHI");
+        assert_eq!(string("let s=\"cargo build\"\nreturn [s.startsWith(\"cargo\"),s.endsWith(\"x\"),s.contains(\"o b\")]"),"[true,false,true]");
+        assert_eq!(string("return \"a-b-c\".replace(\"-\",\"+\")"),"a+b+c");
+        assert_eq!(string("return \"hello\".substring(1,3)+\"hello\".substring(3)"),"ello");
+        assert_eq!(string("return \"7\".padStart(3,\"0\")+\"ab\".repeat(2)"),"007abab");
+        assert_eq!(string("return [1,\"b\",2.5].join(\"-\")"),"1-b-2.5");
+        assert_eq!(string("return \"{} is {1}{{}}\".format(\"x\",2)"),"x is 2{}");
+        assert_eq!(string("return \" 42\".trim().toInt()+\"0.5\".toFloat()"),"42.5");
+    }
+    #[test]
+    fn string_method_errors(){
+        let kind=|script:&str|run(script).unwrap_err().kind_name();
+        assert_eq!(kind("return \"abc\".substring(2,5)"),"IndexOutOfBounds");
+        assert_eq!(kind("return \"abc\".substring(2,1)"),"IndexOutOfBounds");
+        assert_eq!(kind("return \"a\".padStart(3,\"ab\")"),"InvalidArgument");
+        assert_eq!(kind("return \"a\".repeat(-1)"),"InvalidArgument");
+        assert_eq!(kind("return \"4x\".toInt()"),"InvalidArgument");
+        let r=run("let r=0\ntry { r=\"x\".toFloat() } catch (e) { r=e.kind }\nreturn r").unwrap();
+        assert_eq!(r.as_string(),Some("InvalidArgument".into()));
+    }
+    #[test]
+    fn fs_functions_work_relative_to_the_workspace(){
+        let dir=test_workspace("fs");
+        let script=r#"fs::mkdirs("src/a")
+fs::writeText("src/a/x.rs","fn")
+fs::appendText("src/a/x.rs","()")
+fs::writeText("src/y.rs","")
+fs::writeText("notes.txt","n")
+let stat=fs::stat("src/a/x.rs")
+let r=[fs::readText("src/a/x.rs"),fs::exists("notes.txt"),fs::isDir("src"),fs::list("src"),fs::glob("src/**/*.rs"),stat.size,stat.isDir]
+fs::remove("notes.txt")
+fs::removeAll("src/a")
+fs::removeAll("missing")
+r.append(fs::exists("notes.txt"))
+r.append(fs::list("src"))
+return r"#;
+        let (r,_)=run_fs(&dir,false,script);
+        assert_eq!(r.unwrap().as_dynamic().to_string(),"[fn(),true,true,[a,y.rs],[src/a/x.rs,src/y.rs],4,false,false,[y.rs]]");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn fs_failures_are_catchable_io_errors(){
+        let dir=test_workspace("errors");
+        let (r,_)=run_fs(&dir,false,"return fs::readText(\"missing.txt\")");
+        assert_eq!(r.unwrap_err().kind_name(),"IoFailed");
+        let (r,_)=run_fs(&dir,false,"let r=\"\"\ntry { fs::remove(\"missing\") } catch (e) { r=e.kind }\nreturn r");
+        assert_eq!(r.unwrap().as_string(),Some("IoFailed".into()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn fs_writes_are_only_recorded_in_dry_run(){
+        let dir=test_workspace("dry-run");
+        let (r,plan)=run_fs(&dir,true,"fs::writeText(\"a.txt\",\"x\")\nfs::mkdirs(\"b\")\nfs::removeAll(\"c\")\nreturn fs::list(\".\")");
+        assert_eq!(r.unwrap().as_dynamic().to_string(),"[]");
+        assert_eq!(plan.len(),3);
+        assert!(plan[1]=="mkdirs b");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[cfg(unix)]
+    #[test]
+    fn symlink_points_at_the_target(){
+        let dir=test_workspace("symlink");
+        let (r,_)=run_fs(&dir,false,"fs::writeText(\"t.txt\",\"hi\")\nfs::symlink(\"t.txt\",\"l.txt\")\nreturn fs::readText(\"l.txt\")");
+        assert_eq!(r.unwrap().as_string(),Some("hi".into()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
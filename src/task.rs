@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, mpsc, RwLock};
+use std::thread;
+use std::time::Duration;
+use crate::builtin::parse_duration;
+use crate::context::{Context, PipelineContextValue};
+use crate::error::{PipelineError, PipelineResult};
+use crate::v1::types::{Dynamic, FnPtr, Value};
+
+/// pipeline中一个任务(step或parallel)的运行状态
+#[derive(Debug,Clone,PartialEq)]
+pub enum TaskState{
+    Success,
+    /// 任务失败，携带失败原因
+    Failed(String),
+    /// 任务失败，但设置了allowFailure，不影响后续任务
+    AllowedFailure(String),
+    /// 任务超过timeout仍未完成，携带超时信息
+    TimedOut(String),
+    /// 前置任务失败，当前任务被跳过
+    Skipped
+}
+
+impl TaskState {
+    /// 任务失败或超时，会导致后续任务被跳过以及pipeline失败
+    pub fn is_failed(&self)->bool{
+        matches!(self,TaskState::Failed(_)|TaskState::TimedOut(_))
+    }
+}
+/// 按执行顺序记录一个pipeline中所有任务的状态
+#[derive(Debug,Clone,Default)]
+pub struct TaskStates{
+    states:Vec<(String,TaskState)>
+}
+
+impl TaskStates {
+    pub fn new()->Self{
+        Self{states:vec![]}
+    }
+    pub fn set(&mut self,name:&str,state:TaskState){
+        for (n,s) in self.states.iter_mut(){
+            if n==name{
+                *s=state;
+                return
+            }
+        }
+        self.states.push((name.into(),state));
+    }
+    pub fn get(&self,name:&str)->Option<TaskState>{
+        self.states.iter().find(|(n,_)|n==name).map(|(_,s)|s.clone())
+    }
+    pub fn has_failed(&self)->bool{
+        self.states.iter().any(|(_,s)|s.is_failed())
+    }
+    pub fn failed(&self)->Vec<String>{
+        self.states.iter()
+            .filter(|(_,s)|s.is_failed())
+            .map(|(n,_)|n.clone())
+            .collect()
+    }
+}
+/// step和parallel的可选参数，位于任务名与闭包之间，例如step("build",allowFailure=true){...}
+#[derive(Debug,Clone,Default)]
+pub struct TaskOptions{
+    /// 为true时任务失败不会导致pipeline失败
+    pub allow_failure:bool,
+    /// 显式声明的依赖任务，例如step("test",needs=["build"])
+    pub needs:Option<Vec<String>>,
+    /// 任务中额外设置的环境变量，覆盖从pipeline继承的同名环境变量
+    pub env:HashMap<String,String>,
+    /// 为true时任务中执行的命令不继承进程的环境变量
+    pub clear_env:bool,
+    /// 任务的超时时间，超时后终止正在执行的命令并将任务标记为超时
+    pub timeout:Option<Duration>
+}
+
+impl TaskOptions {
+    pub fn from_args(args:&[Value])->PipelineResult<Self>{
+        let mut options=TaskOptions::default();
+        if args.len()<3{
+            return Ok(options)
+        }
+        let m=match args[1].as_dynamic() {
+            Dynamic::Map(m)=>m,
+            t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+        };
+        if let Some(v)=m.get(&Dynamic::from("allowFailure")){
+            options.allow_failure=v.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
+        }
+        if let Some(v)=m.get(&Dynamic::from("needs")){
+            let needs=match v.as_dynamic() {
+                Dynamic::String(s)=>vec![s],
+                Dynamic::Array(a)=>{
+                    let mut needs=vec![];
+                    for n in a{
+                        needs.push(n.as_string().ok_or(PipelineError::ExpectedType("String".into()))?);
+                    }
+                    needs
+                }
+                t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+            };
+            options.needs=Some(needs);
+        }
+        if let Some(v)=m.get(&Dynamic::from("env")){
+            options.env=env_from_value(v)?;
+        }
+        if let Some(v)=m.get(&Dynamic::from("clearEnv")){
+            options.clear_env=v.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
+        }
+        if let Some(v)=m.get(&Dynamic::from("timeout")){
+            options.timeout=Some(parse_duration(v)?);
+        }
+        Ok(options)
+    }
+}
+/// 将env={"K":"v"}选项转换为环境变量，Int、Float和Bool类型的值转换为字符串
+pub fn env_from_value(value:&Value)->PipelineResult<HashMap<String,String>>{
+    let m=match value.as_dynamic() {
+        Dynamic::Map(m)=>m,
+        t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+    };
+    let mut env=HashMap::new();
+    for (k,v) in m{
+        let v=match v.as_dynamic() {
+            v@(Dynamic::String(_)|Dynamic::Integer(_)|Dynamic::Float(_)|Dynamic::Boolean(_))=>v.to_string(),
+            t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+        };
+        env.insert(k.to_string(),v);
+    }
+    Ok(env)
+}
+/// 根据任务的执行结果更新任务状态并输出提示，返回任务的最终状态。
+/// 任务失败且不在pipeline中(没有状态记录)时返回错误，否则由pipeline统一汇总失败的任务
+pub fn finish_task(states:Option<Arc<RwLock<TaskStates>>>,name:&str,options:&TaskOptions,result:PipelineResult<Value>)->PipelineResult<TaskState>{
+    let state=match result {
+        Ok(_) => TaskState::Success,
+        Err(e) if options.allow_failure => {
+            println!("\x1b[33m[Warn]:Task {name} failed but allowFailure is set:{e}\x1b[0m");
+            TaskState::AllowedFailure(e.to_string())
+        }
+        Err(e) => {
+            let timed_out=matches!(e.kind(),PipelineError::TimedOut(..));
+            if timed_out{
+                println!("\x1b[31m[Error]:Task {name} timed out.\x1b[0m");
+            }else{
+                println!("\x1b[31m[Error]:Task {name} failed.\x1b[0m");
+            }
+            match states {
+                //错误交由调用方输出
+                None => return Err(e),
+                Some(_) => {
+                    print!("{}",e.render());
+                    if timed_out{
+                        TaskState::TimedOut(e.to_string())
+                    }else{
+                        TaskState::Failed(e.to_string())
+                    }
+                }
+            }
+        }
+    };
+    if let Some(states)=states{
+        states.write().unwrap().set(name,state.clone());
+    }
+    Ok(state)
+}
+#[derive(Debug,Clone,PartialEq)]
+pub enum TaskKind{
+    /// 会阻塞后续的任务
+    Step,
+    /// 不阻塞后续的任务
+    Parallel
+}
+/// pipeline中声明的一个任务，在pipeline闭包执行完毕后统一调度
+#[derive(Clone)]
+pub struct Task{
+    pub name:String,
+    pub kind:TaskKind,
+    pub options:TaskOptions,
+    pub closure:Box<FnPtr>,
+    /// 声明任务时的上下文，任务执行时在此基础上运行
+    pub ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>
+}
+impl Debug for Task{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f,"Task({:?} {})",self.kind,self.name)
+    }
+}
+/// 任务依赖图。
+/// 声明了needs的任务只依赖needs中的任务，否则依赖它前面最近的一个step，
+/// 因此不声明needs时step依旧按书写顺序执行，parallel与其后的任务并发执行
+pub struct TaskGraph{
+    tasks:Vec<Task>,
+    index:HashMap<String,usize>
+}
+
+impl TaskGraph {
+    pub fn new(tasks:Vec<Task>)->PipelineResult<Self>{
+        let mut index=HashMap::new();
+        for (i,t) in tasks.iter().enumerate(){
+            index.entry(t.name.clone()).or_insert(i);
+        }
+        let graph=Self{tasks,index};
+        for t in &graph.tasks{
+            for n in t.options.needs.iter().flatten(){
+                if !graph.index.contains_key(n){
+                    return Err(PipelineError::UnknownTask(n.clone()))
+                }
+            }
+        }
+        graph.check_cycle()?;
+        Ok(graph)
+    }
+    /// 任务直接依赖的任务：声明了needs时为needs中的任务，否则为它前面最近的一个step
+    fn deps(&self,i:usize)->Vec<usize>{
+        match &self.tasks[i].options.needs {
+            Some(needs)=>needs.iter().map(|n|self.index[n]).collect(),
+            None=>self.tasks[..i].iter().rposition(|t|t.kind==TaskKind::Step).into_iter().collect()
+        }
+    }
+    fn check_cycle(&self)->PipelineResult<()>{
+        // 0:未访问 1:访问中 2:已完成
+        let mut color=vec![0;self.tasks.len()];
+        let mut path=vec![];
+        for i in 0..self.tasks.len(){
+            self.visit(i,&mut color,&mut path)?;
+        }
+        Ok(())
+    }
+    fn visit(&self,i:usize,color:&mut Vec<u8>,path:&mut Vec<usize>)->PipelineResult<()>{
+        if color[i]==2{
+            return Ok(())
+        }
+        if color[i]==1{
+            let start=path.iter().position(|p|*p==i).unwrap();
+            let mut cycle:Vec<String>=path[start..].iter().map(|p|self.tasks[*p].name.clone()).collect();
+            cycle.push(self.tasks[i].name.clone());
+            return Err(PipelineError::DependencyCycle(cycle))
+        }
+        color[i]=1;
+        path.push(i);
+        for n in self.deps(i){
+            self.visit(n,color,path)?;
+        }
+        path.pop();
+        color[i]=2;
+        Ok(())
+    }
+    /// 选出需要执行的任务：all表示全部任务，否则为目标任务及其传递依赖的任务(包括未声明needs时依赖的前一个step)
+    fn select(&self,target:&str)->PipelineResult<Vec<bool>>{
+        let mut selected=vec![target=="all";self.tasks.len()];
+        if target=="all"{
+            return Ok(selected)
+        }
+        let start=*self.index.get(target).ok_or(PipelineError::UnknownTask(target.into()))?;
+        let mut stack=vec![start];
+        while let Some(i)=stack.pop(){
+            if selected[i]{
+                continue
+            }
+            selected[i]=true;
+            stack.extend(self.deps(i));
+        }
+        Ok(selected)
+    }
+    /// 调度执行target选中的任务，相互独立的任务并发执行。
+    /// 任务失败时依赖它的任务会被跳过，任务状态记录在states中
+    pub fn run(&self,target:&str,states:Arc<RwLock<TaskStates>>,executor:impl Fn(&Task)->PipelineResult<Value>+Sync)->PipelineResult<()>{
+        let selected=self.select(target)?;
+        let deps:Vec<Vec<usize>>=(0..self.tasks.len()).map(|i|self.deps(i)).collect();
+        // None:未完成 Some(true):成功 Some(false):失败或被跳过
+        let mut done:Vec<Option<bool>>=vec![None;self.tasks.len()];
+        let mut started=vec![false;self.tasks.len()];
+        let (tx,rx)=mpsc::channel();
+        let executor=&executor;
+        thread::scope(|s|{
+            let mut running=0;
+            loop {
+                let mut progress=true;
+                while progress{
+                    progress=false;
+                    for i in 0..self.tasks.len(){
+                        if !selected[i]||started[i]{
+                            continue
+                        }
+                        if deps[i].iter().any(|d|done[*d]==Some(false)){
+                            started[i]=true;
+                            done[i]=Some(false);
+                            progress=true;
+                            let name=&self.tasks[i].name;
+                            println!("\x1b[33m[Skip]:Task {name} skipped due to a failed task.\x1b[0m");
+                            states.write().unwrap().set(name,TaskState::Skipped);
+                        }else if deps[i].iter().all(|d|done[*d]==Some(true)){
+                            started[i]=true;
+                            running+=1;
+                            let tx=tx.clone();
+                            let task=&self.tasks[i];
+                            s.spawn(move||{
+                                let r=executor(task);
+                                tx.send((i,r)).unwrap();
+                            });
+                        }
+                    }
+                }
+                if running==0{
+                    break
+                }
+                let (i,r)=rx.recv().unwrap();
+                running-=1;
+                let task=&self.tasks[i];
+                let state=finish_task(Some(states.clone()),&task.name,&task.options,r)?;
+                done[i]=Some(!state.is_failed());
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use crate::context::EmptyContext;
+    use super::*;
+
+    fn task(name:&str,kind:TaskKind,needs:Option<Vec<&str>>)->Task{
+        let options=TaskOptions{ needs:needs.map(|n|n.into_iter().map(String::from).collect()),..Default::default() };
+        Task{ name:name.into(), kind, options, closure:Box::new(FnPtr::new(name)), ctx:Arc::new(RwLock::new(EmptyContext::new())) }
+    }
+    /// 以target运行任务图，返回任务的执行顺序和最终状态，名称在fail中的任务执行失败
+    fn run(tasks:Vec<Task>,target:&str,fail:&[&str])->(Vec<String>,TaskStates){
+        let order=Mutex::new(vec![]);
+        let states=Arc::new(RwLock::new(TaskStates::new()));
+        TaskGraph::new(tasks).unwrap().run(target,states.clone(),|t|{
+            order.lock().unwrap().push(t.name.clone());
+            if fail.contains(&t.name.as_str()){
+                return Err(PipelineError::InvalidArgument(t.name.clone()))
+            }
+            Ok(().into())
+        }).unwrap();
+        let states=states.read().unwrap().clone();
+        (order.into_inner().unwrap(),states)
+    }
+
+    #[test]
+    fn steps_run_in_declaration_order(){
+        let tasks=vec![task("a",TaskKind::Step,None),task("b",TaskKind::Step,None),task("c",TaskKind::Step,None)];
+        let (order,states)=run(tasks,"all",&[]);
+        assert_eq!(order,vec!["a","b","c"]);
+        assert_eq!(states.get("c"),Some(TaskState::Success));
+    }
+    #[test]
+    fn needs_override_declaration_order(){
+        let tasks=vec![task("test",TaskKind::Step,Some(vec!["build"])),task("build",TaskKind::Step,Some(vec![]))];
+        let (order,_)=run(tasks,"all",&[]);
+        assert_eq!(order,vec!["build","test"]);
+    }
+    #[test]
+    fn target_runs_only_its_needs(){
+        let tasks=vec![
+            task("build",TaskKind::Step,Some(vec![])),
+            task("lint",TaskKind::Step,Some(vec![])),
+            task("test",TaskKind::Step,Some(vec!["build"]))
+        ];
+        let (order,states)=run(tasks,"test",&[]);
+        assert_eq!(order,vec!["build","test"]);
+        assert_eq!(states.get("lint"),None);
+    }
+    #[test]
+    fn target_runs_the_previous_step_it_implicitly_depends_on(){
+        let tasks=vec![
+            task("a",TaskKind::Step,None),
+            task("p",TaskKind::Parallel,None),
+            task("b",TaskKind::Step,None),
+            task("c",TaskKind::Step,Some(vec![]))
+        ];
+        let (order,states)=run(tasks,"b",&[]);
+        assert_eq!(order,vec!["a","b"]);
+        assert_eq!(states.get("p"),None);
+        assert_eq!(states.get("c"),None);
+    }
+    #[test]
+    fn detects_cycle_through_implicit_dependency(){
+        let tasks=vec![task("a",TaskKind::Step,Some(vec!["b"])),task("b",TaskKind::Step,None)];
+        assert!(matches!(TaskGraph::new(tasks),Err(PipelineError::DependencyCycle(_))));
+    }
+    #[test]
+    fn failed_task_skips_dependents(){
+        let tasks=vec![task("a",TaskKind::Step,None),task("b",TaskKind::Step,None),task("c",TaskKind::Step,Some(vec![]))];
+        let (order,states)=run(tasks,"all",&["a"]);
+        assert!(!order.contains(&"b".to_string()));
+        assert!(states.get("a").unwrap().is_failed());
+        assert_eq!(states.get("b"),Some(TaskState::Skipped));
+        assert_eq!(states.get("c"),Some(TaskState::Success));
+        assert_eq!(states.failed(),vec!["a"]);
+    }
+    #[test]
+    fn detects_dependency_cycle(){
+        let tasks=vec![
+            task("a",TaskKind::Step,Some(vec!["c"])),
+            task("b",TaskKind::Step,Some(vec!["a"])),
+            task("c",TaskKind::Step,Some(vec!["b"]))
+        ];
+        match TaskGraph::new(tasks) {
+            Err(PipelineError::DependencyCycle(cycle))=>assert_eq!(cycle,vec!["a","c","b","a"]),
+            r=>panic!("expected a dependency cycle, got {:?}",r.err())
+        }
+    }
+    #[test]
+    fn rejects_unknown_needs(){
+        let tasks=vec![task("a",TaskKind::Step,Some(vec!["missing"]))];
+        assert!(matches!(TaskGraph::new(tasks),Err(PipelineError::UnknownTask(n)) if n=="missing"));
+    }
+}
@@ -2,17 +2,22 @@ use std::any::Any;
 use std::collections::HashMap;
 use crate::v1::parser::FnDef;
 use crate::v1::position::Position;
+use crate::v1::stmt::{IfStmt, Stmt};
 use crate::v1::types::{Dynamic};
 
 #[derive(Debug,Clone)]
 pub enum Expr{
     StringConstant(String, Position),
+    /// 插值字符串，求值时将各部分的值依次拼接
+    StringTemplate(Vec<Expr>,Position),
     IntConstant(i64,Position),
     FloatConstant(f64,Position),
     FnClosure(FnClosureExpr,Position),
     FnCall(FnCallExpr,Position),
     Variable(String,Position),
     BinaryExpr(Op,Box<Expr>,Box<Expr>,Position),
+    /// 一元运算，Op为Not或Negative
+    UnaryExpr(Op,Box<Expr>,Position),
     Array(Vec<Expr>,Position),
     Map(Vec<(Expr,Expr)>,Position),
     Index(Box<Expr>,Box<Expr>,Position),
@@ -20,6 +25,12 @@ pub enum Expr{
     /// a=Person::new()
     /// a.name  -> MemberAccess
     MemberAccess(Box<Expr>,String,Position),
+    /// a?.b与a?.f()，内部为MemberAccess或以接收者为第一个参数的FnCall，接收者为null时值为null
+    SafeAccess(Box<Expr>,Position),
+    /// 作为表达式的if，值为执行的分支中最后一个表达式的值
+    If(Box<IfStmt>,Position),
+    /// when(subject){ 条件 -> 分支 else -> 分支 }
+    When(Box<WhenExpr>,Position),
     None(Position)
 }
 #[derive(Debug,Clone)]
@@ -40,6 +51,54 @@ impl StructExpr {
     }
 }
 #[derive(Debug,Clone)]
+pub struct WhenExpr{
+    subject:Option<Expr>,
+    branches:Vec<WhenBranch>,
+    else_body:Option<Vec<Stmt>>
+}
+
+impl WhenExpr {
+    pub fn new(subject:Option<Expr>,branches:Vec<WhenBranch>,else_body:Option<Vec<Stmt>>)->Self{
+        Self{subject,branches,else_body}
+    }
+    pub fn get_subject(&self)->Option<&Expr>{
+        self.subject.as_ref()
+    }
+    pub fn get_branches(&self)->&Vec<WhenBranch>{
+        &self.branches
+    }
+    pub fn get_else_body(&self)->Option<&Vec<Stmt>>{
+        self.else_body.as_ref()
+    }
+}
+#[derive(Debug,Clone)]
+pub struct WhenBranch{
+    /// 逗号分隔的多个条件，满足任意一个即执行该分支
+    conditions:Vec<WhenCondition>,
+    body:Vec<Stmt>
+}
+
+impl WhenBranch {
+    pub fn new(conditions:Vec<WhenCondition>,body:Vec<Stmt>)->Self{
+        Self{conditions,body}
+    }
+    pub fn get_conditions(&self)->&Vec<WhenCondition>{
+        &self.conditions
+    }
+    pub fn get_body(&self)->&Vec<Stmt>{
+        &self.body
+    }
+}
+#[derive(Debug,Clone)]
+pub enum WhenCondition{
+    /// 与subject相等，没有subject时为Bool条件
+    Value(Expr),
+    /// in/!in，subject是否包含在Array、Map、String或者Range中，bool为true时表示!in
+    In(Expr,bool),
+    /// is/!is，subject是否为该类型，bool为true时表示!is
+    Is(String,bool)
+}
+#[derive(Debug,Clone)]
 pub enum Op{
     Plus,
     Minus,
@@ -49,13 +108,34 @@ pub enum Op{
     Greater,
     Less,
     Equal,
-    NotEqual
-
+    NotEqual,
+    GreaterEqual,
+    LessEqual,
+    /// &&，短路求值
+    And,
+    /// ||，短路求值
+    Or,
+    /// !
+    Not,
+    /// 一元负号
+    Negative,
+    /// a..b，包含b的区间
+    RangeTo,
+    /// a until b，不包含b的区间
+    Until,
+    /// a downTo b，递减区间
+    DownTo,
+    /// range step n，修改区间的步长
+    Step,
+    /// a ?: b，a为null时取b
+    Elvis
 }
 #[derive(Debug,Clone)]
 pub struct FnCallExpr{
     pub name:String,
-    pub args:Vec<Expr>
+    pub args:Vec<Expr>,
+    /// 是否为a.f()形式的方法调用，方法调用优先查找接收者类型的方法，普通调用优先查找函数
+    pub method:bool
 }
 #[derive(Debug,Clone)]
 pub struct FnClosureExpr{
@@ -83,18 +163,23 @@ impl Expr {
     pub fn position(&self)->Position{
         match self  {
             Expr::StringConstant(_, pos) => {pos.clone()}
+            Expr::StringTemplate(_, pos) => {pos.clone()}
             Expr::IntConstant(_, pos) => {pos.clone()}
             Expr::FloatConstant(_, pos) => {pos.clone()}
             Expr::Variable(_, pos) => {pos.clone()}
             Expr::FnClosure(_,pos)=>{pos.clone()}
             Expr::FnCall(_,pos)=>{pos.clone()}
             Expr::BinaryExpr(_,_,_,pos)=>{pos.clone()}
+            Expr::UnaryExpr(_,_,pos)=>{pos.clone()}
             Expr::Array(_,pos)=>{pos.clone()}
             Expr::Index(_,_,pos)=>{pos.clone()}
             Expr::Map(_,pos)=>{pos.clone()}
             Expr::None(pos)=>{pos.clone()}
             Expr::Struct(_,pos)=>{pos.clone()}
-            Expr::MemberAccess(_,_,pos)=>pos.clone()
+            Expr::MemberAccess(_,_,pos)=>pos.clone(),
+            Expr::SafeAccess(_,pos)=>pos.clone(),
+            Expr::If(_,pos)=>pos.clone(),
+            Expr::When(_,pos)=>pos.clone()
         }
     }
 
@@ -1,13 +1,16 @@
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock, RwLockWriteGuard, Weak};
-use crate::context::{Context, EmptyContext};
+use indexmap::IndexMap;
+use crate::builtin::check_deadline;
+use crate::context::{Context, EmptyContext, Scope};
 use crate::context::PipelineContextValue;
+use crate::check::{check_type, is_builtin_type, type_matches};
 use crate::engine::{PipelineEngine};
 use crate::error::{PipelineError, PipelineResult};
-use crate::module::{Function, Module};
-use crate::v1::expr::{Expr, FnCallExpr, Op};
-use crate::v1::stmt::Stmt;
-use crate::v1::types::{Dynamic, SignalType, Struct, Value};
+use crate::module::{display_value, Class, Function, Module};
+use crate::v1::expr::{Expr, FnCallExpr, Op, WhenCondition, WhenExpr};
+use crate::v1::stmt::{IfStmt, Stmt};
+use crate::v1::types::{Dynamic, Range, SignalType, Struct, Value};
 
 #[derive(Clone,Debug)]
 pub struct Interpreter{
@@ -48,7 +51,14 @@ impl Interpreter{
         return self.eval_stmt_with_context(ctx,stmt)
     }
 
+    /// 执行语句，出错时为错误附加该语句的位置。
+    /// 任务超过timeout时不再执行后续语句，使没有正在执行命令的任务(例如循环)也能在超时后结束
     pub fn eval_stmt_with_context(&mut self, ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>, stmt:Stmt) ->PipelineResult<Value>{
+        let pos=stmt.position();
+        check_deadline(&ctx).map_err(|e|e.with_position(&pos))?;
+        self.eval_stmt_without_position(ctx,stmt).map_err(|e|e.with_position(&pos))
+    }
+    fn eval_stmt_without_position(&mut self, ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>, stmt:Stmt) ->PipelineResult<Value>{
         match stmt {
             Stmt::FnCall(fc, pos) => {
                 let ctx=PipelineEngine::with_value(ctx,"$pos",pos.into());
@@ -67,12 +77,10 @@ impl Interpreter{
                 return Ok(Value::Signal(SignalType::Continue))
             }
             Stmt::Assign(e,_)=>{
+                let name=target_name(&e.0);
                 let target=self.eval_expr(ctx.clone(),e.0)?;
                 let value=self.eval_expr(ctx,e.1)?;
-                if !target.can_mutable(){
-                    panic!("it must be mutable")
-                }
-                let target=target.get_mut_arc();
+                let target=target.get_mut_arc(&name)?;
                 let mut target=target.write().unwrap();
                 *target=value.as_dynamic();
             }
@@ -80,53 +88,34 @@ impl Interpreter{
                 return self.eval_expr(ctx, *e)
             }
             Stmt::If(b,_)=>{
-                for if_branch in b.get_branches(){
-                    let d=self.eval_expr(ctx.clone(),if_branch.get_condition().clone())?;
-                    let d=d.as_dynamic().as_bool();
-                    match d {
-                        None => {
-                            return Err(PipelineError::ExpectedType("bool".into()))
-                        }
-                        Some(d) => {
-                            let mut l=None;
-                            if d {
-                                for i in if_branch.get_body() {
-                                    let t=self.eval_stmt_with_context(ctx.clone(), i.clone())?;
-                                    l=Some(t)
-                                }
-                                if let None=l{
-                                    return Ok(().into())
-                                }
-                                return Ok(l.unwrap())
-                            }
-                        }
-                    }
-                }
-               if let Some(else_body)=b.get_else_body(){
-                   let mut l=None;
-                   for i in else_body {
-                       l=Some(self.eval_stmt_with_context(ctx.clone(), i.clone())?);
-                   }
-                   return Ok(l.unwrap())
-               }
-
+                return self.eval_if(ctx,&b,false)
             }
             Stmt::IndexAssign(target,i,v,_)=>{
+                let name=format!("{}[]",target_name(&target));
                 let i=self.eval_expr(ctx.clone(),*i)?;
                 let v=self.eval_expr(ctx.clone(),*v)?;
                 let target=self.eval_expr(ctx,*target)?;
-                let target=target.get_mut_arc();
-                let mut target=target.write().unwrap();
-                if target.is_array(){
-                    let a=target.as_mut_array().unwrap();
-                    let index=i.as_dynamic().as_integer().unwrap();
-                    a[index as usize]=v;
-                }else if target.is_map(){
-                    let m=target.as_mut_map().unwrap();
-                    let key=i.as_dynamic();
-                    m.insert(key,v);
-                }else{
-                    panic!("{} cannot support index assign",target.type_name())
+                assign_index(&target,&i,v,&name)?;
+            }
+            Stmt::CompoundAssign(e,_)=>{
+                let (target,op,rhs)=*e;
+                let name=target_name(&target);
+                match target {
+                    Expr::Index(target,i,_)=>{
+                        let target=self.eval_expr(ctx.clone(),*target)?;
+                        let i=self.eval_expr(ctx.clone(),*i)?;
+                        let current=index_value(&target.as_dynamic(),&i)?.as_dynamic();
+                        let rhs=self.eval_expr(ctx,rhs)?;
+                        let v=arithmetic(&op,current,rhs.as_dynamic())?;
+                        assign_index(&target,&i,v.into(),&name)?;
+                    }
+                    target=>{
+                        let target=self.eval_expr(ctx.clone(),target)?;
+                        let rhs=self.eval_expr(ctx,rhs)?;
+                        let v=arithmetic(&op,target.as_dynamic(),rhs.as_dynamic())?;
+                        let target=target.get_mut_arc(&name)?;
+                        *target.write().unwrap()=v;
+                    }
                 }
             }
             Stmt::While(b,blocks,_)=>{
@@ -134,14 +123,15 @@ impl Interpreter{
                 let d=d.as_dynamic().as_bool();
                 return match d {
                     None => {
-                        Err(PipelineError::ExpectedType("bool".into()))
+                        Err(PipelineError::ExpectedType("Bool".into()))
                     }
                     Some(d) => {
 
                         let mut condition=d;
                         'outer:while condition {
+                            let block_ctx=PipelineEngine::with_block_scope(ctx.clone());
                             'inner:for i in &*blocks {
-                                let r=self.eval_stmt_with_context(ctx.clone(), i.clone())?;
+                                let r=self.eval_stmt_with_context(block_ctx.clone(), i.clone())?;
                                 if let Value::Signal(s)=r{
                                     match s {
                                         SignalType::Break => {
@@ -151,10 +141,13 @@ impl Interpreter{
                                             break 'inner
                                         }
                                     }
+                                }else if !is_unit(&r){
+                                    //循环体中的return
+                                    return Ok(r)
                                 }
                             }
                              let d0=self.eval_expr(ctx.clone(),*b.clone())?;
-                            condition=d0.as_dynamic().as_bool().unwrap();
+                            condition=d0.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
                         }
                         Ok(().into())
                     }
@@ -162,24 +155,34 @@ impl Interpreter{
             }
             Stmt::ForIn(one,other ,target, blocks, ..)=> {
                 let target = self.eval_expr(ctx.clone(), *target.clone())?;
-                let target = target.as_dynamic().as_array().unwrap();
-                let mut count=0;
-                'outer:for i in target{
-                    let scope=PipelineEngine::context_with_scope(&ctx);
-                    let mut scope=scope.write().unwrap();
+                let target = target.as_dynamic();
+                //每个元素为(下标,元素)，Map为(键,值)，单个循环变量时取元素，Map取键。Range迭代时才生成元素
+                let is_map=target.is_map();
+                let items:Box<dyn Iterator<Item=(Value,Value)>>=match target {
+                    Dynamic::Array(a)=>Box::new(a.into_iter().enumerate().map(|(i,v)|((i as i64).into(),v))),
+                    Dynamic::Map(m)=>Box::new(m.into_iter().map(|(k,v)|(Value::Immutable(k),v))),
+                    Dynamic::Range(r)=>Box::new(r.iter().enumerate().map(|(i,v)|((i as i64).into(),v.into()))),
+                    Dynamic::String(s)=>{
+                        let chars:Vec<char>=s.chars().collect();
+                        Box::new(chars.into_iter().enumerate().map(|(i,c)|((i as i64).into(),c.to_string().into())))
+                    }
+                    t=>return Err(PipelineError::UnexpectedType(t.type_name()))
+                };
+                'outer:for (first,second) in items{
+                    //每次迭代使用新的作用域，循环变量不会泄漏到循环之外，闭包捕获的是当次迭代的值
+                    let mut scope=Scope::with_parent(PipelineEngine::context_with_scope(&ctx));
                     match other.clone() {
                         None => {
-                            scope.set(one.as_str(),i);
+                            scope.set(one.as_str(),if is_map{first}else{second});
                         }
                         Some(s) => {
-                            scope.set(one.as_str(),count.into());
-                            count+=1;
-                            scope.set(s.as_str(),i);
+                            scope.set(one.as_str(),first);
+                            scope.set(s.as_str(),second);
                         }
                     }
-                    drop(scope);
+                    let block_ctx=PipelineEngine::with_scope(ctx.clone(),scope);
                     'inner: for i in &*blocks {
-                        let r = self.eval_stmt_with_context(ctx.clone(), i.clone())?;
+                        let r = self.eval_stmt_with_context(block_ctx.clone(), i.clone())?;
                         if let Value::Signal(s) = r {
                             match s {
                                 SignalType::Break => {
@@ -189,14 +192,117 @@ impl Interpreter{
                                     break 'inner
                                 }
                             }
+                        }else if !is_unit(&r){
+                            return Ok(r)
                         }
                     }
                 }
             }
+            Stmt::Expr(e,_)=>{
+                //作为语句的when中的return、break、continue需要传递到外层
+                if let Expr::When(w,_)=*e{
+                    return self.eval_when(ctx,&w,false)
+                }
+                self.eval_expr(ctx,*e)?;
+            }
+            Stmt::Throw(e,_)=>{
+                let v=self.eval_expr(ctx,*e)?;
+                return Err(PipelineError::Thrown(v))
+            }
+            Stmt::Try(t,_)=>{
+                let mut r=self.eval_block(PipelineEngine::with_block_scope(ctx.clone()),t.get_body());
+                if let (Err(e),Some((name,catch)))=(&r,t.get_catch()){
+                    let mut scope=Scope::with_parent(PipelineEngine::context_with_scope(&ctx));
+                    scope.set(name.as_str(),error_value(&ctx,e));
+                    r=self.eval_block(PipelineEngine::with_scope(ctx.clone(),scope),catch);
+                }
+                if let Some(finally)=t.get_finally(){
+                    //finally中出错或者return时覆盖try和catch的结果
+                    let f=self.eval_block(PipelineEngine::with_block_scope(ctx),finally)?;
+                    if !is_unit(&f){
+                        return Ok(f)
+                    }
+                }
+                return r
+            }
             Stmt::Noop => {}
         }
         Ok(().into())
     }
+    /// 执行if，as_value为true时作为表达式求值，没有执行任何分支时值为Unit
+    fn eval_if(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,if_stmt:&IfStmt,as_value:bool)->PipelineResult<Value>{
+        for branch in if_stmt.get_branches(){
+            let d=self.eval_expr(ctx.clone(),branch.get_condition().clone())?;
+            let d=d.as_dynamic().as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
+            if d{
+                return self.eval_branch(ctx,branch.get_body(),as_value)
+            }
+        }
+        match if_stmt.get_else_body() {
+            Some(body)=>self.eval_branch(ctx,&body,as_value),
+            None=>Ok(().into())
+        }
+    }
+    /// 执行when，依次检查各分支的条件，执行第一个满足条件的分支
+    fn eval_when(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,when:&WhenExpr,as_value:bool)->PipelineResult<Value>{
+        let subject=match when.get_subject() {
+            None=>None,
+            Some(e)=>Some(self.eval_expr(ctx.clone(),e.clone())?.as_dynamic())
+        };
+        for branch in when.get_branches(){
+            for condition in branch.get_conditions(){
+                if self.when_matches(ctx.clone(),subject.as_ref(),condition)?{
+                    return self.eval_branch(ctx,branch.get_body(),as_value)
+                }
+            }
+        }
+        match when.get_else_body() {
+            Some(body)=>self.eval_branch(ctx,body,as_value),
+            None=>Ok(().into())
+        }
+    }
+    fn when_matches(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,subject:Option<&Dynamic>,condition:&WhenCondition)->PipelineResult<bool>{
+        match (condition,subject) {
+            (WhenCondition::Value(e),None)=>{
+                let d=self.eval_expr(ctx,e.clone())?;
+                d.as_dynamic().as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))
+            }
+            (WhenCondition::Value(e),Some(subject))=>Ok(self.eval_expr(ctx,e.clone())?.as_dynamic()==*subject),
+            (WhenCondition::In(e,negated),Some(subject))=>{
+                let container=self.eval_expr(ctx,e.clone())?.as_dynamic();
+                Ok(contains(&container,subject)?!=*negated)
+            }
+            (WhenCondition::Is(t,negated),Some(subject))=>Ok(type_matches(subject,t)!=*negated),
+            (_,None)=>Err(PipelineError::InvalidArgument("in and is branches of when require a subject".into()))
+        }
+    }
+    /// 在新的块作用域中执行if或when的分支。作为语句时返回其中return、break、continue的结果，
+    /// 作为表达式时值为分支中最后一个表达式的值
+    fn eval_branch(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,body:&[Stmt],as_value:bool)->PipelineResult<Value>{
+        let ctx=PipelineEngine::with_block_scope(ctx);
+        if !as_value{
+            return self.eval_block(ctx,body)
+        }
+        let Some((last,rest))=body.split_last() else {return Ok(().into())};
+        let r=self.eval_block(ctx.clone(),rest)?;
+        if !is_unit(&r){
+            return Ok(r)
+        }
+        match last.as_value_expr() {
+            Some(e)=>self.eval_expr(ctx,e),
+            None=>self.eval_stmt_with_context(ctx,last.clone())
+        }
+    }
+    /// 依次执行语句块，遇到return、break、continue时停止并返回其结果
+    fn eval_block(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,stmts:&[Stmt])->PipelineResult<Value>{
+        for stmt in stmts{
+            let r=self.eval_stmt_with_context(ctx.clone(),stmt.clone())?;
+            if !is_unit(&r){
+                return Ok(r)
+            }
+        }
+        Ok(().into())
+    }
     pub  fn eval_let_stmt(&mut self, ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>, l:Box<(String,Expr)>)->PipelineResult<Value>{
         // let d=self.eval_expr(ctx.clone(),l.0)?;
 
@@ -210,13 +316,20 @@ impl Interpreter{
         Ok(().into())
     }
 
+    /// 计算表达式，出错时为错误附加该表达式的位置
     pub  fn eval_expr(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,expr:Expr)->PipelineResult<Value>{
+        let pos=expr.position();
+        self.eval_expr_without_position(ctx,expr).map_err(|e|e.with_position(&pos))
+    }
+    fn eval_expr_without_position(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,expr:Expr)->PipelineResult<Value>{
         match expr.clone() {
-            Expr::FnCall(_, _)=>{
+            Expr::FnCall(f, _)=>{
+                return self.eval_fn_call_expr_with_context(ctx,f);
+            }
+            Expr::FnClosure(_,_)=>{
                 let mut ptr=expr.dynamic().as_fn_ptr().unwrap();
-                let mut e=PipelineEngine::default();
-                e.set_interpreter(self);
-                return ptr.call(&mut e,ctx);
+                ptr.capture(PipelineEngine::context_with_scope(&ctx));
+                Ok(Dynamic::FnPtr(ptr).into())
             }
             Expr::Variable(i,_)=>{
                 let d=PipelineEngine::context_with_dynamic(&ctx,i.clone());
@@ -240,7 +353,7 @@ impl Interpreter{
                 Ok(Value::Mutable(Arc::new(RwLock::new(Dynamic::Array(dv)))))
             }
             Expr::Map(v,_)=>{
-                let mut dv=HashMap::new();
+                let mut dv=IndexMap::new();
                 for e in v{
                     let key=self.eval_expr(ctx.clone(), e.0)?;
                     let value=self.eval_expr(ctx.clone(), e.1)?;
@@ -251,28 +364,11 @@ impl Interpreter{
             Expr::Index(s,e,_)=>{
                 let d=self.eval_expr(ctx.clone(),*s)?;
                 let d=d.as_dynamic();
-                match d {
-                    Dynamic::Array(a) => {
-                        let index=self.eval_expr(ctx,*e)?;
-                        let index=index.as_dynamic().as_integer().unwrap();
-                        Ok(a[index as usize].clone())
-                    }
-                    Dynamic::Map(m) => {
-                        let index=self.eval_expr(ctx,*e)?;
-                        let index=index.as_dynamic();
-                        Ok(m[&index].clone())
-                    }
-                    Dynamic::String(s)=>{
-                        let index=self.eval_expr(ctx,*e)?;
-                        let index=index.as_dynamic().as_integer().unwrap();
-                        let r=String::from(s.chars().nth(index as usize).unwrap());
-                        Ok(r.into())
-                    }
-                    t=>{
-                        return Err(PipelineError::UndefinedOperation(format!("index [] to {}",t.type_name())))
-                    }
+                if let Dynamic::Array(_)|Dynamic::Map(_)|Dynamic::String(_)=d{
+                    let index=self.eval_expr(ctx,*e)?;
+                    return index_value(&d,&index)
                 }
-
+                Err(PipelineError::UndefinedOperation(format!("index [] to {}",d.type_name())))
             }
 
 
@@ -283,35 +379,35 @@ impl Interpreter{
                         let l_r=l_r.as_dynamic();
                         let r_r=self.eval_expr(ctx.clone(),*r)?;
                         let r_r=r_r.as_dynamic();
-                        return Ok((l_r+r_r).into())
+                        return Ok((l_r+r_r)?.into())
                     }
                     Op::Minus => {
                         let l_r=self.eval_expr(ctx.clone(),*l)?;
                         let l_r=l_r.as_dynamic();
                         let r_r=self.eval_expr(ctx.clone(),*r)?;
                         let r_r=r_r.as_dynamic();
-                        return Ok((l_r-r_r).into())
+                        return Ok((l_r-r_r)?.into())
                     }
                     Op::Mul=>{
                         let l_r=self.eval_expr(ctx.clone(),*l)?;
                         let l_r=l_r.as_dynamic();
                         let r_r=self.eval_expr(ctx.clone(),*r)?;
                         let r_r=r_r.as_dynamic();
-                        return Ok((l_r*r_r).into())
+                        return Ok((l_r*r_r)?.into())
                     }
                     Op::Greater=>{
                         let l_r=self.eval_expr(ctx.clone(),*l)?;
                         let l_r=l_r.as_dynamic();
                         let r_r=self.eval_expr(ctx.clone(),*r)?;
                         let r_r=r_r.as_dynamic();
-                        return Ok((l_r>r_r).into())
+                        return Ok(l_r.compare(&r_r)?.is_gt().into())
                     }
                     Op::Less=>{
                         let l_r=self.eval_expr(ctx.clone(),*l)?;
                         let l_r=l_r.as_dynamic();
                         let r_r=self.eval_expr(ctx.clone(),*r)?;
                         let r_r=r_r.as_dynamic();
-                        return Ok((l_r<r_r).into())
+                        return Ok(l_r.compare(&r_r)?.is_lt().into())
                     }
                     Op::Equal=>{
                         let l_r=self.eval_expr(ctx.clone(),*l)?;
@@ -332,61 +428,164 @@ impl Interpreter{
                         let l_r=l_r.as_dynamic();
                         let r_r=self.eval_expr(ctx.clone(),*r)?;
                         let r_r=r_r.as_dynamic();
-                        return Ok((l_r/r_r).into())
+                        return Ok((l_r/r_r)?.into())
                     }
                     Op::Mod=>{
                         let l_r=self.eval_expr(ctx.clone(),*l)?;
                         let l_r=l_r.as_dynamic();
                         let r_r=self.eval_expr(ctx.clone(),*r)?;
                         let r_r=r_r.as_dynamic();
-                        return Ok((l_r%r_r).into())
+                        return Ok((l_r%r_r)?.into())
+                    }
+                    Op::GreaterEqual=>{
+                        let l_r=self.eval_expr(ctx.clone(),*l)?;
+                        let l_r=l_r.as_dynamic();
+                        let r_r=self.eval_expr(ctx.clone(),*r)?;
+                        let r_r=r_r.as_dynamic();
+                        return Ok(l_r.compare(&r_r)?.is_ge().into())
+                    }
+                    Op::LessEqual=>{
+                        let l_r=self.eval_expr(ctx.clone(),*l)?;
+                        let l_r=l_r.as_dynamic();
+                        let r_r=self.eval_expr(ctx.clone(),*r)?;
+                        let r_r=r_r.as_dynamic();
+                        return Ok(l_r.compare(&r_r)?.is_le().into())
+                    }
+                    Op::RangeTo|Op::Until|Op::DownTo|Op::Step=>{
+                        let l_r=self.eval_expr(ctx.clone(),*l)?.as_dynamic();
+                        let r_r=self.eval_expr(ctx.clone(),*r)?.as_dynamic();
+                        return Ok(Dynamic::Range(range(op,l_r,r_r)?).into())
+                    }
+                    Op::Elvis=>{
+                        let l_r=self.eval_expr(ctx.clone(),*l)?;
+                        if !matches!(l_r.as_dynamic(),Dynamic::Unit){
+                            return Ok(l_r)
+                        }
+                        return self.eval_expr(ctx,*r)
+                    }
+                    Op::And|Op::Or=>{
+                        let l_r=self.eval_expr(ctx.clone(),*l)?;
+                        let l_r=l_r.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
+                        //短路求值：&&左侧为false或||左侧为true时不再计算右侧
+                        if l_r==matches!(op,Op::Or){
+                            return Ok(l_r.into())
+                        }
+                        let r_r=self.eval_expr(ctx.clone(),*r)?;
+                        let r_r=r_r.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))?;
+                        return Ok(r_r.into())
+                    }
+                    op=>{
+                        return Err(PipelineError::UndefinedOperation(format!("binary operator {op:?}")))
                     }
                 }
             }
+            Expr::StringTemplate(parts,_)=>{
+                let mut s=String::new();
+                let ctx=self.with_modules(ctx);
+                for part in parts{
+                    let v=self.eval_expr(ctx.clone(),part)?;
+                    s.push_str(&display_value(&ctx,&v)?);
+                }
+                Ok(s.into())
+            }
+            Expr::UnaryExpr(op,e,_)=>{
+                let v=self.eval_expr(ctx.clone(),*e)?;
+                match (op,v.as_dynamic()) {
+                    (Op::Not,Dynamic::Boolean(b))=>Ok((!b).into()),
+                    (Op::Negative,Dynamic::Integer(i))=>{
+                        i.checked_neg().map(Into::into).ok_or(PipelineError::IntegerOverflow(format!("-({i})")))
+                    }
+                    (Op::Negative,Dynamic::Float(f))=>Ok((-f).into()),
+                    (op,t)=>Err(PipelineError::UndefinedOperation(format!("unary operator {op:?} to {}",t.type_name())))
+                }
+            }
             Expr::Struct(e,_)=>{
-                let mut props=HashMap::new();
+                let mut values=HashMap::new();
                 for (k,i) in e.get_props(){
-                    let mut v=self.eval_expr(ctx.clone(),i.clone())?;
-                    if v.is_immutable()||v.is_mutable(){
-                        let scope=PipelineEngine::context_with_scope(&ctx);
-                        let mut scope=scope.write().unwrap();
-                        let  v0=Value::Mutable(v.as_arc());
-                        v=Value::Refer(v0.as_weak());
-                        scope.set(format!("{}.{}",e.get_name(),k).as_str(),v0)
-                    }
-                    props.insert(k.clone(),v);
+                    values.insert(k.clone(),self.eval_expr(ctx.clone(),i.clone())?);
                 }
-                Ok(
-                    Value::Mutable(
-                        Arc::new(
-                            RwLock::new(
-                                Dynamic::Struct(
-                                    Box::new(
-                                        Struct::new(e.get_name().into(),props)
-                                    )
-                                )
-                            )
-                        )
-                    )
-                )
+                let class=self.main_module.read().unwrap().get_classes().get(e.get_name()).cloned();
+                let class=class.ok_or(PipelineError::UnexpectedType(e.get_name().into()))?;
+                self.new_object(ctx,&class,values)
             }
+            Expr::If(if_stmt,_)=>self.eval_if(ctx,&if_stmt,true),
+            Expr::When(when,_)=>self.eval_when(ctx,&when,true),
             Expr::MemberAccess(father,prop,_)=>{
-                let obj=self.eval_expr(ctx,*father)?;
+                let obj=self.eval_expr(ctx,*father)?.as_dynamic();
                 // println!("{:?}",obj);
-                let obj=obj.as_dynamic().as_struct().unwrap();
-                let r=obj.get_prop(&prop).unwrap();
-                return Ok(r)
+                member(obj,prop)
+            }
+            Expr::SafeAccess(access,_)=>{
+                //先求值接收者，为null时不再访问属性或调用方法，也不再求值其余参数
+                match *access {
+                    Expr::MemberAccess(father,prop,_)=>{
+                        let obj=self.eval_expr(ctx,*father)?.as_dynamic();
+                        if let Dynamic::Unit=obj{
+                            return Ok(().into())
+                        }
+                        member(obj,prop)
+                    }
+                    Expr::FnCall(f,_)=>{
+                        let (receiver,args)=f.args.split_first().ok_or(PipelineError::ArityMismatch(f.name.clone(),1,0))?;
+                        let mut v=self.eval_fn_call_args(ctx.clone(),std::slice::from_ref(receiver))?;
+                        if let Dynamic::Unit=v[0].as_dynamic(){
+                            return Ok(().into())
+                        }
+                        v.extend(self.eval_fn_call_args(ctx.clone(),args)?);
+                        self.call_function(ctx,f.name,v,true)
+                    }
+                    e=>self.eval_expr(ctx,e)
+                }
             }
             _=>Ok(expr.dynamic().into())
         }
     }
+    /// 向上下文中加入脚本中的函数和已导入的模块，供原生函数调用脚本函数
+    fn with_modules(&self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>)->Arc<RwLock<dyn Context<PipelineContextValue>>>{
+        let ctx=PipelineEngine::with_value(ctx,"$shared_module",PipelineContextValue::SharedModule(self.main_module.clone()));
+        PipelineEngine::with_value(ctx,"$modules",PipelineContextValue::Modules(Arc::new(RwLock::new(self.modules.clone()))))
+    }
+    /// 创建class的对象，values中没有的属性使用默认值，没有默认值的可空属性为null
+    fn new_object(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,class:&Class,mut values:HashMap<String,Value>)->PipelineResult<Value>{
+        let mut props=HashMap::new();
+        for a in class.get_attributions(){
+            let v=match values.remove(&a.name) {
+                Some(v)=>v,
+                None=>match class.get_default(&a.name) {
+                    Some(default)=>self.eval_expr(ctx.clone(),default.clone())?,
+                    None if a.declaration_type.ends_with('?')=>().into(),
+                    None=>return Err(PipelineError::PropertyRequired(class.get_name(),a.name.clone()))
+                }
+            };
+            check_type(&v,&a.declaration_type,||format!("property \"{}\" of class \"{}\"",a.name,class.get_name()))?;
+            //属性由对象自身持有，修改属性不会影响创建对象时传入的变量
+            props.insert(a.name.clone(),Value::with_mutable(v.as_dynamic()));
+        }
+        if let Some(name)=values.into_keys().next(){
+            return Err(PipelineError::PropertyUndefined(class.get_name(),name))
+        }
+        Ok(Value::with_mutable(Dynamic::Struct(Box::new(Struct::new(class.get_name(),props)))))
+    }
     pub fn eval_fn_call_expr(&mut self,f:FnCallExpr)->PipelineResult<Value>{
         let c=Arc::new(RwLock::new(EmptyContext::new()));
        self.eval_fn_call_expr_with_context(c,f)
     }
     pub  fn eval_fn_call_expr_with_context(&mut self, ctx: Arc<RwLock<dyn Context<PipelineContextValue>>>, f:FnCallExpr) ->PipelineResult<Value>{
+        //json.parse(s)与json::parse(s)等价，同名的变量优先
+        if let (true,Some(Expr::Variable(m,_)))=(f.method,f.args.first()){
+            if self.modules.contains_key(m)&&PipelineEngine::context_with_dynamic(&ctx,m.as_str()).is_none(){
+                let name=format!("{m}::{}",f.name);
+                let v=self.eval_fn_call_args(ctx.clone(),&f.args[1..])?;
+                return self.call_function(ctx,name,v,false)
+            }
+        }
+        let v=self.eval_fn_call_args(ctx.clone(),&f.args)?;
+        self.call_function(ctx,f.name,v,f.method)
+    }
+    /// 求值函数调用的参数，非延迟执行的闭包参数会先被调用，以其返回值作为参数
+    fn eval_fn_call_args(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,args:&[Expr])->PipelineResult<Vec<Value>>{
         let mut v=vec![];
-        for e in &f.args{
+        for e in args{
             let d=self.eval_expr(ctx.clone(),e.clone())?;
             if d.as_dynamic().is_fn_ptr(){
                 let mut ptr=d.as_dynamic().as_fn_ptr().unwrap();
@@ -396,23 +595,26 @@ impl Interpreter{
                 }
                 let mut e=PipelineEngine::new_raw();
                 e.set_interpreter(self);
-                let d=ptr.call(&mut e,ctx.clone()).unwrap();
+                let d=ptr.call(&mut e,ctx.clone())?;
                 v.push(d);
                 continue
             }else if d.as_dynamic().is_variable(){
                 let d=d.as_dynamic().as_variable().unwrap();
-                let r=PipelineEngine::context_with_dynamic(&ctx,d.as_str()).unwrap();
+                let r=PipelineEngine::context_with_dynamic(&ctx,d.as_str()).ok_or(PipelineError::VariableUndefined(d))?;
                 v.push(r);
                 continue
             }
             v.push(d);
         }
-        let fist_param_type=v[0].as_dynamic().type_name();
-        let ctx=PipelineEngine::with_value(ctx,"$shared_module",PipelineContextValue::SharedModule(self.main_module.clone()));
-        let ctx=PipelineEngine::with_value(ctx,"$modules",PipelineContextValue::Modules(Arc::new(RwLock::new(self.modules.clone()))));
+        Ok(v)
+    }
+    /// 按名称调用函数，依次查找模块函数、第一个参数类型的方法与函数、保存在变量中的lambda以及构造函数
+    fn call_function(&mut self,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,name:String,v:Vec<Value>,method:bool)->PipelineResult<Value>{
+        let fist_param_type=v.first().map(|d|d.as_dynamic().type_name()).unwrap_or_default();
+        let ctx=self.with_modules(ctx);
         let mut r=None;
-        if f.name.contains("::"){
-            let mut l=f.name.split("::");
+        if name.contains("::"){
+            let mut l=name.split("::");
             let module_name=l.next().unwrap();
             let m=self.modules.get(module_name);
             match m {
@@ -425,22 +627,277 @@ impl Interpreter{
                 }
             }
         }else{
-            let class_function_result=self.main_module.read().unwrap().get_class_function(&fist_param_type,f.name.as_str());
-            match class_function_result {
-                None => {
-                    r=self.main_module.read().unwrap().get_function(f.name.clone());
-                }
-                Some(class_function) => {
-                    let scope=PipelineEngine::context_with_scope(&ctx);
-                    let mut scope=scope.write().unwrap();
-                    scope.set("this",v[0].clone());
-                    r=Some(class_function)
+            let main_module=self.main_module.read().unwrap();
+            let class_function=||main_module.get_class_function(&fist_param_type,name.as_str());
+            let function=||main_module.get_function(name.clone());
+            //s.replace(a,b)调用String的方法，replace(path,a,b)调用同名的函数
+            r=if method{
+                class_function().or_else(function)
+            }else{
+                function().or_else(class_function)
+            };
+        }
+        if r.is_none(){
+            //保存在变量中的lambda
+            let lambda=PipelineEngine::context_with_dynamic(&ctx,name.as_str())
+                .and_then(|l|l.as_dynamic().as_fn_ptr())
+                .filter(|l|l.fn_def.is_some());
+            if let Some(lambda)=lambda{
+                let mut e=PipelineEngine::new_raw();
+                e.set_interpreter(self);
+                return lambda.call_with_args(&mut e,ctx,v)
+            }
+        }
+        if r.is_none(){
+            //构造函数，按照属性声明的顺序传入参数
+            let class=self.main_module.read().unwrap().get_classes().get(&name).cloned();
+            if let Some(class)=class.filter(|c|!is_builtin_type(&c.get_name())){
+                let attributions=class.get_attributions();
+                if v.len()>attributions.len(){
+                    return Err(PipelineError::ArityMismatch(name,attributions.len(),v.len()))
                 }
+                let values=attributions.iter().map(|a|a.name.clone()).zip(v).collect();
+                return self.new_object(ctx,&class,values)
             }
         }
         return match r {
-            None => { Err(PipelineError::FunctionUndefined(f.name)) }
+            None => { Err(PipelineError::FunctionUndefined(name)) }
             Some(f) => { f.call(ctx, v) }
         }
     }
 }
+/// 赋值目标的名称，用于错误提示
+fn target_name(expr:&Expr)->String{
+    match expr {
+        Expr::Variable(name,_)=>name.clone(),
+        Expr::MemberAccess(obj,prop,_)=>format!("{}.{prop}",target_name(obj)),
+        Expr::Index(obj,_,_)=>format!("{}[]",target_name(obj)),
+        _=>"expression".into()
+    }
+}
+/// 读取对象的属性
+fn member(obj:Dynamic,prop:String)->PipelineResult<Value>{
+    let r=match obj.as_struct() {
+        None => None,
+        Some(obj) => obj.get_prop(&prop)
+    };
+    r.ok_or(PipelineError::PropertyUndefined(obj.type_name(),prop))
+}
+/// 读取Array、Map或String中下标处的值
+fn index_value(target:&Dynamic,index:&Value)->PipelineResult<Value>{
+    match target {
+        Dynamic::Array(a) => {
+            let index=index.as_integer().ok_or(PipelineError::ExpectedType("Int".into()))?;
+            let index=check_index(index,a.len())?;
+            Ok(a[index].clone())
+        }
+        Dynamic::Map(m) => {
+            let index=index.as_dynamic();
+            m.get(&index).cloned().ok_or(PipelineError::KeyNotFound(index.to_string()))
+        }
+        Dynamic::String(s)=>{
+            let index=index.as_integer().ok_or(PipelineError::ExpectedType("Int".into()))?;
+            let index=check_index(index,s.chars().count())?;
+            Ok(String::from(s.chars().nth(index).unwrap()).into())
+        }
+        t=>Err(PipelineError::UndefinedOperation(format!("index [] to {}",t.type_name())))
+    }
+}
+/// 对Array或Map中下标处赋值，name为赋值目标的名称
+fn assign_index(target:&Value,index:&Value,value:Value,name:&str)->PipelineResult<()>{
+    let target=target.get_mut_arc(name)?;
+    let mut target=target.write().unwrap();
+    if target.is_array(){
+        let a=target.as_mut_array().unwrap();
+        let index=index.as_integer().ok_or(PipelineError::ExpectedType("Int".into()))?;
+        let index=check_index(index,a.len())?;
+        a[index]=value;
+    }else if target.is_map(){
+        let m=target.as_mut_map().unwrap();
+        m.insert(index.as_dynamic(),value);
+    }else{
+        return Err(PipelineError::UndefinedOperation(format!("index assign to {}",target.type_name())))
+    }
+    Ok(())
+}
+/// 复合赋值中的算术运算
+fn arithmetic(op:&Op,l:Dynamic,r:Dynamic)->PipelineResult<Dynamic>{
+    match op {
+        Op::Plus=>l+r,
+        Op::Minus=>l-r,
+        Op::Mul=>l*r,
+        Op::Div=>l/r,
+        op=>Err(PipelineError::UndefinedOperation(format!("binary operator {op:?}")))
+    }
+}
+/// 检查下标是否越界，返回可直接用于索引的下标
+fn check_index(index:i64,len:usize)->PipelineResult<usize>{
+    if index<0||index as usize>=len{
+        return Err(PipelineError::IndexOutOfBounds(index,len))
+    }
+    Ok(index as usize)
+}
+/// when中的in条件，Array是否包含该元素，Map是否包含该键，String是否包含该子串，Range是否包含该整数
+fn contains(container:&Dynamic,item:&Dynamic)->PipelineResult<bool>{
+    match container {
+        Dynamic::Array(a)=>Ok(a.iter().any(|i|i.as_dynamic()==*item)),
+        Dynamic::Map(m)=>Ok(m.contains_key(item)),
+        Dynamic::Range(r)=>Ok(item.as_integer().is_some_and(|i|r.contains(i))),
+        Dynamic::String(s)=>{
+            let item=item.as_string().ok_or(PipelineError::ExpectedType("String".into()))?;
+            Ok(s.contains(item.as_str()))
+        }
+        t=>Err(PipelineError::UnexpectedType(t.type_name()))
+    }
+}
+/// 区间运算，..、until、downTo的两侧必须为Int，step的左侧必须为Range
+fn range(op:Op,l:Dynamic,r:Dynamic)->PipelineResult<Range>{
+    let name=match op {Op::RangeTo=>"..",Op::Until=>"until",Op::DownTo=>"downTo",_=>"step"};
+    let undefined=||PipelineError::UndefinedOperation(format!("{} {name} {}",l.type_name(),r.type_name()));
+    match (op,&l,&r) {
+        (Op::RangeTo,Dynamic::Integer(a),Dynamic::Integer(b))=>Ok(Range::closed(*a,*b)),
+        (Op::Until,Dynamic::Integer(a),Dynamic::Integer(b))=>Ok(Range::until(*a,*b)),
+        (Op::DownTo,Dynamic::Integer(a),Dynamic::Integer(b))=>Ok(Range::down_to(*a,*b)),
+        (Op::Step,Dynamic::Range(range),Dynamic::Integer(step))=>range.with_step(*step),
+        _=>Err(undefined())
+    }
+}
+fn is_unit(v:&Value)->bool{
+    matches!(v,Value::Immutable(Dynamic::Unit))
+}
+/// 将错误转换为catch中可以访问的Error{message,kind,line,column}，line与column从1开始，未知时为0。
+/// 通过throw重新抛出的Error保持原样
+fn error_value(ctx:&Arc<RwLock<dyn Context<PipelineContextValue>>>,e:&PipelineError)->Value{
+    if let PipelineError::Thrown(v)=e.kind(){
+        if v.as_dynamic().type_name()=="Error"{
+            return v.clone()
+        }
+    }
+    let (mut line,mut column)=(0,0);
+    //位置相对于出错的脚本：已关联脚本的错误(例如来自导入模块的函数)使用其脚本，否则为当前执行的脚本
+    let source=e.source().cloned().or_else(||PipelineEngine::context_with_source(ctx));
+    if let (Some(pos),Some(source))=(e.position(),source){
+        let source:Vec<char>=source.text.chars().collect();
+        if pos.pos<source.len(){
+            let (row,col)=pos.get_row_col(&source);
+            line=row as i64+1;
+            column=col as i64+1;
+        }
+    }
+    let message=match e.kind() {
+        PipelineError::Thrown(v)=>v.as_dynamic().to_string(),
+        e=>e.to_string()
+    };
+    let mut props=HashMap::new();
+    props.insert("message".into(),message.into());
+    props.insert("kind".into(),String::from(e.kind_name()).into());
+    props.insert("line".into(),line.into());
+    props.insert("column".into(),column.into());
+    Value::with_mutable(Dynamic::Struct(Box::new(Struct::new("Error".into(),props))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(script:&str)->PipelineResult<Value>{
+        PipelineEngine::default().run_script(script)
+    }
+
+    #[test]
+    fn negates_numbers(){
+        assert_eq!(run("let x=5\nreturn -x").unwrap().as_integer(),Some(-5));
+        assert_eq!(run("return -(1.5)").unwrap().as_dynamic(),Dynamic::Float(-1.5));
+        let e=run("let x=-9223372036854775807-1\nreturn -x").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::IntegerOverflow(_)),"{e}");
+    }
+    #[test]
+    fn compound_assignment_evaluates_target_once(){
+        let script="let a=[10,20,30]\nlet i=0\nfn next(){ i+=1\n return i }\na[next()]+=5\na[next()]++\nreturn \"${a} ${i}\"";
+        assert_eq!(run(script).unwrap().as_string(),Some("[10,25,31] 2".into()));
+        let script="let m={\"n\":1}\nm[\"n\"]*=6\nm[\"n\"]--\nlet x=7\nx/=2\nreturn \"${m[\"n\"]} ${x}\"";
+        assert_eq!(run(script).unwrap().as_string(),Some("5 3".into()));
+        assert_eq!(run("class C(n:Int)\nlet c=C(1)\nc.n+=2\nc.n++\nreturn c.n").unwrap().as_integer(),Some(4));
+    }
+    fn error(script:&str)->PipelineError{
+        run(script).unwrap_err().kind().clone()
+    }
+    #[test]
+    fn runtime_mistakes_return_typed_errors(){
+        assert!(matches!(error("let a=[1,2]\nreturn a[2]"),PipelineError::IndexOutOfBounds(2,2)));
+        assert!(matches!(error("let a=[1,2]\na[-1]=0"),PipelineError::IndexOutOfBounds(-1,2)));
+        assert!(matches!(error("let m={\"a\":1}\nreturn m[\"b\"]"),PipelineError::KeyNotFound(k) if k=="b"));
+        assert!(matches!(error("fn f(a:Int){ return a }\nreturn f(1,2)"),PipelineError::ArityMismatch(f,1,2) if f=="f"));
+        assert!(matches!(error("return 1/0"),PipelineError::DivisionByZero));
+        assert!(matches!(error("return missing+1"),PipelineError::VariableUndefined(v) if v=="missing"));
+        assert!(matches!(error("return \"a\"-1"),PipelineError::UndefinedOperation(_)));
+        assert!(matches!(error("nothing(1)"),PipelineError::FunctionUndefined(f) if f=="nothing"));
+        assert!(matches!(error("true=false"),PipelineError::NotMutable(t) if t=="true"));
+    }
+    #[test]
+    fn runtime_errors_carry_their_position(){
+        let e=run("let a=[1]\nreturn a[5]").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::IndexOutOfBounds(5,1)));
+        assert!(e.render().contains("--> test.kts:2:9"),"{}",e.render());
+    }
+    #[test]
+    fn catch_receives_error_value(){
+        let script="let r=\"\"\ntry {\n  let a=[1]\n  a[3]=1\n} catch (e) {\n  r=\"${e.kind} ${e.line}:${e.column}\"\n}\nreturn r";
+        assert_eq!(run(script).unwrap().as_string(),Some("IndexOutOfBounds 4:4".into()));
+        let script="let r=\"\"\ntry { throw(\"boom\") } catch (e) { r=\"${e.kind}:${e.message}\" }\nreturn r";
+        assert_eq!(run(script).unwrap().as_string(),Some("Thrown:boom".into()));
+    }
+    #[test]
+    fn finally_runs_after_success_and_failure(){
+        let script="let log=[]\ntry { log.append(1) } finally { log.append(2) }\ntry { try { 1/0 } finally { log.append(3) } } catch (e) { log.append(e.kind) }\nreturn log";
+        assert_eq!(run(script).unwrap().as_dynamic().to_string(),"[1,2,3,DivisionByZero]");
+    }
+    #[test]
+    fn rethrown_error_keeps_kind_and_position(){
+        let e=run("try {\n  return missing\n} catch (e) {\n  throw(e)\n}").unwrap_err();
+        assert_eq!(e.to_string(),"eval failed,variable \"missing\" undefined.");
+        let script="let r=\"\"\ntry {\n  try { return missing } catch (e) { throw(e) }\n} catch (e) { r=\"${e.kind} ${e.line}\" }\nreturn r";
+        assert_eq!(run(script).unwrap().as_string(),Some("VariableUndefined 3".into()));
+        let e=run("throw(42)").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::Thrown(v) if v.as_integer()==Some(42)));
+    }
+    #[test]
+    fn function_locals_do_not_leak(){
+        assert!(matches!(error("fn f(a:Int){ let b=a\n return b }\nf(1)\nreturn b"),PipelineError::VariableUndefined(v) if v=="b"));
+        assert!(matches!(error("fn f(a:Int){ return a }\nf(1)\nreturn a"),PipelineError::VariableUndefined(v) if v=="a"));
+        let script="let x=1\nfn f(n:Int){ let x=n+10\n return x }\nreturn \"${f(5)} ${x}\"";
+        assert_eq!(run(script).unwrap().as_string(),Some("15 1".into()));
+    }
+    #[test]
+    fn recursion_keeps_each_call_separate(){
+        let script="fn fib(n:Int){ if(n<2){ return n }\n let a=fib(n-1)\n let b=fib(n-2)\n return a+b }\nreturn fib(10)";
+        assert_eq!(run(script).unwrap().as_integer(),Some(55));
+    }
+    #[test]
+    fn block_variables_end_with_the_block(){
+        assert!(matches!(error("for i in [1,2] { let j=i }\nreturn i"),PipelineError::VariableUndefined(v) if v=="i"));
+        assert!(matches!(error("if(true){ let j=1 }\nreturn j"),PipelineError::VariableUndefined(v) if v=="j"));
+        let script="let sum=0\nfor i in [1,2,3] { sum=sum+i }\nreturn sum";
+        assert_eq!(run(script).unwrap().as_integer(),Some(6));
+    }
+    #[test]
+    fn closures_capture_their_environment(){
+        let script="fn counter(){ let n=0\n return { -> n=n+1\n n } }\nlet c=counter()\nc()\nc()\nreturn c()";
+        assert_eq!(run(script).unwrap().as_integer(),Some(3));
+    }
+    #[test]
+    fn constructors_fill_defaults(){
+        let class="class Target(name:String,kind:String=\"bin\",opt:Int?)\n";
+        let script=format!("{class}let t=Target(\"cli\")\nreturn \"${{t.name}} ${{t.kind}} ${{t.opt==null}}\"");
+        assert_eq!(run(&script).unwrap().as_string(),Some("cli bin true".into()));
+        let script=format!("{class}let t=Target{{name:\"core\",kind:\"lib\"}}\nreturn t.kind");
+        assert_eq!(run(&script).unwrap().as_string(),Some("lib".into()));
+        let e=run(&format!("{class}let t=Target{{kind:\"lib\"}}")).unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::PropertyRequired(c,p) if c=="Target"&&p=="name"),"{e}");
+    }
+    #[test]
+    fn methods_assign_through_this_and_to_string_is_used(){
+        let script="class C(n:Int=0,items:Array=[])\nfun C.add(x:Int){\n  this.n=this.n+x\n  this.items.append(x)\n}\nfun C.toString(){ return \"C(${this.n})\" }\nlet a=C()\nlet b=C()\na.add(2)\na.add(3)\nreturn \"$a ${a.items} ${b.items}\"";
+        assert_eq!(run(script).unwrap().as_string(),Some("C(5) [2,3] []".into()));
+    }
+}
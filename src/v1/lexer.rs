@@ -1,19 +1,29 @@
 use std::{fs, io};
+use std::collections::VecDeque;
 use std::ops::{Add, Deref};
 use crate::v1::position::{NONE, Position};
-use crate::v1::token::Token;
+use crate::v1::token::{TemplatePart, Token};
 
 #[derive(Debug,Clone)]
 pub struct Lexer{
     chars:Vec<char>,
     index:usize,
+    /// 插值表达式单独词法分析时，其在原脚本中的起始位置
+    offset:usize,
     col:usize,
     row:usize,
     keywords:Vec<&'static str>
 }
 pub struct TokenStream{
     tokenizer:Lexer,
-    peek:Option<(Token,Position)>
+    /// 预读的token，支持向前查看多个token
+    peek:VecDeque<(Token,Position)>,
+    /// 最近一次读取或预读到的token的位置，用于定位解析错误
+    last:Position,
+    /// 预读的各个token所在的行
+    rows:VecDeque<usize>,
+    /// 最近一次读取的token所在的行
+    row:usize
 }
 
 impl Iterator for TokenStream {
@@ -31,25 +41,55 @@ impl TokenStream{
     pub fn new()->Self{
         Self{
             tokenizer:Lexer::new(),
-            peek:None
+            peek:VecDeque::new(),
+            last:NONE.clone(),
+            rows:VecDeque::new(),
+            row:0
         }
     }
     pub fn set_lexer(&mut self,lexer: Lexer){
         self.tokenizer=lexer;
     }
     pub fn next(&mut self)->(Token,Position){
-        if self.peek.is_some(){
-            return self.peek.take().unwrap_or((Token::EOF,NONE.clone()))
+        if let Some(t)=self.peek.pop_front(){
+            self.row=self.rows.pop_front().unwrap_or(self.row);
+            return t
         }
-        return self.tokenizer.next().unwrap_or((Token::EOF,NONE.clone()))
+        let t=self.tokenizer.next();
+        self.record(&t);
+        self.row=self.tokenizer.row;
+        return t.unwrap_or((Token::EOF,NONE.clone()))
     }
     pub fn peek(&mut self)->(Token,Position){
-        if self.peek.is_some(){
-            return self.peek.clone().unwrap()
+        self.peek_nth(0)
+    }
+    /// 向前查看第n个token(从0开始)，不会消耗token
+    pub fn peek_nth(&mut self,n:usize)->(Token,Position){
+        while self.peek.len()<=n{
+            let o=self.tokenizer.next();
+            self.record(&o);
+            match o {
+                None => return (Token::EOF,NONE.clone()),
+                Some(t) => {
+                    self.peek.push_back(t);
+                    self.rows.push_back(self.tokenizer.row);
+                }
+            }
+        }
+        return self.peek[n].clone()
+    }
+    /// 下一个token是否与上一个读取的token在同一行
+    pub fn peek_on_same_line(&mut self)->bool{
+        self.peek();
+        self.rows.front().is_some_and(|r|*r==self.row)
+    }
+    fn record(&mut self,t:&Option<(Token,Position)>){
+        if let Some((_,pos))=t{
+            self.last=pos.clone();
         }
-        let o=self.tokenizer.next();
-        self.peek=o.clone();
-        return o.unwrap_or((Token::EOF,NONE.clone()))
+    }
+    pub fn last_position(&self)->Position{
+        self.last.clone()
     }
 }
 
@@ -60,7 +100,10 @@ impl IntoIterator for Lexer {
     fn into_iter(self) -> Self::IntoIter {
         TokenStream{
             tokenizer:self,
-            peek:None
+            peek:VecDeque::new(),
+            last:NONE.clone(),
+            rows:VecDeque::new(),
+            row:0
         }
     }
 }
@@ -69,6 +112,7 @@ impl Lexer{
         Self{
             chars: vec![],
             index: 0,
+            offset: 0,
             col: 0,
             row: 0,
             keywords: vec!["fn","let","return"],
@@ -79,12 +123,25 @@ impl Lexer{
         self.chars=chars;
     }
     pub fn next(&mut self)->Option<(Token,Position)>{
+        let offset=self.offset;
+        self.next_token().map(|(t,mut pos)|{
+            pos.pos+=offset;
+            (t,pos)
+        })
+    }
+    fn next_token(&mut self)->Option<(Token,Position)>{
         loop{
             match self.current_char() {
                 None => { return None}
                 Some(c) => {
                     let peek=self.peek_char().unwrap_or('\0');
                     match (c,peek) {
+                        ('.','.')=>{
+                            let r= Some((Token::DotDot,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
                         ('.',p) if !p.is_numeric()=>{
                             let r= Some((Token::Dot,Position::new(self.index,1)));
                             self.next_char();
@@ -147,6 +204,23 @@ impl Lexer{
                             self.next_char();
                             return r
                         }
+                        ('?','.')=>{
+                            let r= Some((Token::SafeDot,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
+                        ('?',':')=>{
+                            let r= Some((Token::Elvis,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
+                        ('?',_)=>{
+                            let r= Some((Token::Question,Position::new(self.index,1)));
+                            self.next_char();
+                            return r
+                        }
                         (',',_)=>{
                             let r= Some((Token::Comma,Position::new(self.index,1)));
                             self.next_char();
@@ -158,6 +232,23 @@ impl Lexer{
                             self.next_char();
                             return r
                         }
+                        ('!',_)=>{
+                            let r= Some((Token::Not,Position::new(self.index,1)));
+                            self.next_char();
+                            return r
+                        }
+                        ('&','&')=>{
+                            let r= Some((Token::And,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
+                        ('|','|')=>{
+                            let r= Some((Token::Or,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
                         ('=','=')=>{
                             let r= Some((Token::Equal,Position::new(self.index,2)));
                             self.next_char();
@@ -169,6 +260,18 @@ impl Lexer{
                             self.next_char();
                             return r
                         }
+                        ('>','=')=>{
+                            let r= Some((Token::GreaterEqual,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
+                        ('<','=')=>{
+                            let r= Some((Token::LessEqual,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
                         ('>',_)=>{
                             let r= Some((Token::Greater,Position::new(self.index,1)));
                             self.next_char();
@@ -179,16 +282,52 @@ impl Lexer{
                             self.next_char();
                             return r
                         }
+                        ('+','=')=>{
+                            let r= Some((Token::PlusAssign,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
+                        ('+','+')=>{
+                            let r= Some((Token::Increment,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
                         ('+',_)=>{
                             let r= Some((Token::Plus,Position::new(self.index,1)));
                             self.next_char();
                             return r
                         }
+                        ('-','=')=>{
+                            let r= Some((Token::MinusAssign,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
+                        ('-','-')=>{
+                            let r= Some((Token::Decrement,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
+                        ('-','>')=>{
+                            let r= Some((Token::Arrow,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
                         ('-',_)=>{
                             let r= Some((Token::Minus,Position::new(self.index,1)));
                             self.next_char();
                             return r
                         }
+                        ('*','=')=>{
+                            let r= Some((Token::MulAssign,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
                         ('*',_)=>{
                             let r= Some((Token::Mul,Position::new(self.index,1)));
                             self.next_char();
@@ -200,6 +339,9 @@ impl Lexer{
                             return r
                         }
 
+                        ('"','"') if self.chars.get(self.index+2)==Some(&'"')=>{
+                            return self.scan_raw_string()
+                        }
                         ('"',_)=>{
                             let r= self.scan_string('"');
                             self.increase_index();
@@ -225,6 +367,12 @@ impl Lexer{
                             self.next_char();
                             self.next_char();
                         }
+                        ('/','=')=>{
+                            let r= Some((Token::DivAssign,Position::new(self.index,2)));
+                            self.next_char();
+                            self.next_char();
+                            return r
+                        }
                         ('/',_)=>{
                             let r= Some((Token::Div,Position::new(self.index,1)));
                             self.next_char();
@@ -252,7 +400,7 @@ impl Lexer{
         self.chars.get(self.index).map(|c|c.clone())
     }
     fn increase_index(&mut self){
-        if self.chars.get(self.index).unwrap()==&'\n'{
+        if self.chars.get(self.index)==Some(&'\n'){
             self.row+=1;
             self.col=0;
         }
@@ -264,6 +412,10 @@ impl Lexer{
         let mut pos=Position::with_pos(self.index);
         let mut is_decimal=false;
         while let  Some(c ) =self.current_char(){
+            //0..10中的..是区间运算符而不是小数点
+            if c=='.'&&self.peek_char()==Some('.'){
+                break
+            }
             if c=='.'&&!is_decimal{
                 v.push(c.clone());
                 self.increase_index();
@@ -297,20 +449,143 @@ impl Lexer{
         pos.set_span(v.len());
         return Some((Token::Identifier(v),pos))
     }
+    /// 从start到当前位置的无法识别的字面量
+    fn invalid(&self,start:usize,message:&str)->(Token,Position){
+        let end=self.index.min(self.chars.len().saturating_sub(1));
+        (Token::Invalid(message.into()),Position::new(start,end.saturating_sub(start)+1))
+    }
+    /// 扫描字符串字面量，处理转义字符(\n \t \r \\ \" \' \$ \u{..})以及$var、${expr}插值，
+    /// 结束时index指向结尾的引号。字符串未结束或转义错误时返回Token::Invalid
     fn scan_string(&mut self,prefix:char)->Option<(Token,Position)>{
         let mut v=String::new();
+        let mut parts=vec![];
         let mut pos=Position::with_pos(self.index);
         self.increase_index();
-        while let  Some(c ) =self.current_char(){
+        loop {
+            let Some(c)=self.current_char() else {
+                return Some(self.invalid(pos.pos,"unterminated string"))
+            };
             if c==prefix{
                 break
             }
-            v.push(c.clone());
+            match c {
+                '\\'=>{
+                    self.increase_index();
+                    let Some(e)=self.current_char() else {
+                        return Some(self.invalid(pos.pos,"unterminated string"))
+                    };
+                    match e {
+                        'n'=>v.push('\n'),
+                        't'=>v.push('\t'),
+                        'r'=>v.push('\r'),
+                        '0'=>v.push('\0'),
+                        '\\'|'"'|'\''|'$'=>v.push(e),
+                        'u'=>{
+                            let start=self.index-1;
+                            self.increase_index();
+                            if self.current_char()!=Some('{'){
+                                return Some(self.invalid(start,"invalid unicode escape, expected \\u{..}"))
+                            }
+                            let mut hex=String::new();
+                            self.increase_index();
+                            while let Some(h)=self.current_char(){
+                                if h=='}'||h==prefix{
+                                    break
+                                }
+                                hex.push(h);
+                                self.increase_index();
+                            }
+                            let ch=u32::from_str_radix(&hex,16).ok().and_then(char::from_u32);
+                            match (self.current_char(),ch) {
+                                (Some('}'),Some(ch))=>v.push(ch),
+                                _=>return Some(self.invalid(start,&format!("invalid unicode escape \\u{{{hex}}}")))
+                            }
+                        }
+                        //其他转义序列连同反斜杠原样保留，例如正则表达式中的\d
+                        e=>{
+                            v.push('\\');
+                            v.push(e);
+                        }
+                    }
+                    self.increase_index();
+                }
+                '$' if self.peek_char()==Some('{')=>{
+                    let interpolation=self.index;
+                    self.increase_index();
+                    self.increase_index();
+                    let start=self.index;
+                    let mut depth=0;
+                    let mut expr=String::new();
+                    while let Some(e)=self.current_char(){
+                        match e {
+                            '{'=>depth+=1,
+                            '}' if depth==0=>break,
+                            '}'=>depth-=1,
+                            _=>{}
+                        }
+                        expr.push(e);
+                        self.increase_index();
+                    }
+                    if self.current_char().is_none(){
+                        return Some(self.invalid(interpolation,"unterminated ${ in string"))
+                    }
+                    self.increase_index();
+                    if !v.is_empty(){
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut v)));
+                    }
+                    parts.push(TemplatePart::Expr(expr,self.offset+start));
+                }
+                '$' if self.peek_char().map_or(false,|p|p.is_alphabetic())=>{
+                    self.increase_index();
+                    let start=self.index;
+                    let mut ident=String::new();
+                    while let Some(i)=self.current_char(){
+                        if !i.is_alphabetic(){
+                            break
+                        }
+                        ident.push(i);
+                        self.increase_index();
+                    }
+                    if !v.is_empty(){
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut v)));
+                    }
+                    parts.push(TemplatePart::Expr(ident,self.offset+start));
+                }
+                c=>{
+                    v.push(c);
+                    self.increase_index();
+                }
+            }
+        }
+        pos.set_span(self.index-pos.pos+1);
+        if parts.is_empty(){
+            return Some((Token::String(v),pos))
+        }
+        if !v.is_empty(){
+            parts.push(TemplatePart::Literal(v));
+        }
+        Some((Token::TemplateString(parts),pos))
+    }
+    /// 扫描三引号包裹的原始字符串，内容原样保留，可以跨越多行，不处理转义和插值
+    fn scan_raw_string(&mut self)->Option<(Token,Position)>{
+        let mut v=String::new();
+        let mut pos=Position::with_pos(self.index);
+        for _ in 0..3{
             self.increase_index();
         }
-
-        pos.set_span(v.len()+2);
-        return Some((Token::String(v),pos))
+        loop {
+            let c=self.current_char()?;
+            if c=='"'&&self.peek_char()==Some('"')&&self.chars.get(self.index+2)==Some(&'"'){
+                break
+            }
+            v.push(c);
+            self.increase_index();
+        }
+        for _ in 0..3{
+            self.increase_index();
+        }
+        pos.set_span(self.index-pos.pos);
+        Some((Token::String(v),pos))
     }
     #[allow(unused)]
     pub fn get_source(&self) -> Vec<char> {
@@ -319,29 +594,84 @@ impl Lexer{
     pub fn from_path(path:impl AsRef<str>) ->Self{
         let script=fs::read_to_string(path.as_ref()).unwrap();
         return Self{  chars: script.chars().collect(),
-            index: 0, col: 0, row: 0,
+            index: 0, offset: 0, col: 0, row: 0,
             keywords: vec![
                 "let","fn","fun",
                 "return","if","while",
                 "import","else","val","var",
                 "break","continue",
-                "for","in","class"
+                "for","in","class",
+                "try","catch","finally","throw",
+                "when","is"
             ]
         }
     }
+    /// 对脚本中的一段源码(例如字符串插值中的表达式)做词法分析，offset为其在原脚本中的起始位置
+    pub fn from_script_at(script:impl AsRef<str>,offset:usize)->Self{
+        let mut lexer=Self::from_script(script);
+        lexer.offset=offset;
+        lexer
+    }
     pub fn from_script(script:impl AsRef<str>)->Self{
         return Self{
             chars: script.as_ref().chars().collect(),
-            index: 0, col: 0, row: 0,
+            index: 0, offset: 0, col: 0, row: 0,
             keywords:
             vec![
                 "let","fn","fun",
                 "return","if","while",
                 "import","else","val","var",
                 "break","continue",
-                "for","in","class"
+                "for","in","class",
+                "try","catch","finally","throw",
+                "when","is"
             ]
         }
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_token(script:&str)->Token{
+        Lexer::from_script(script).next().unwrap().0
+    }
+    fn invalid_message(script:&str)->String{
+        match first_token(script) {
+            Token::Invalid(message)=>message,
+            t=>panic!("expected an invalid token, got {t:?}")
+        }
+    }
+
+    #[test]
+    fn scans_escape_sequences(){
+        assert_eq!(first_token(r#""a\nb\t\\\"\'\$x""#),Token::String("a\nb\t\\\"'$x".into()));
+        assert_eq!(first_token(r#"'it\'s'"#),Token::String("it's".into()));
+        assert_eq!(first_token(r#""\u{4F60}\u{597D}""#),Token::String("你好".into()));
+    }
+    #[test]
+    fn keeps_unknown_escapes_verbatim(){
+        assert_eq!(first_token(r#""\d+\.\w""#),Token::String(r"\d+\.\w".into()));
+    }
+    #[test]
+    fn scans_interpolation(){
+        let token=first_token(r#""v$name-${a.map{ it }}!""#);
+        assert_eq!(token,Token::TemplateString(vec![
+            TemplatePart::Literal("v".into()),
+            TemplatePart::Expr("name".into(),3),
+            TemplatePart::Literal("-".into()),
+            TemplatePart::Expr("a.map{ it }".into(),10),
+            TemplatePart::Literal("!".into())
+        ]));
+    }
+    #[test]
+    fn reports_malformed_strings(){
+        assert_eq!(invalid_message(r#""abc"#),"unterminated string");
+        assert_eq!(invalid_message(r#""abc\"#),"unterminated string");
+        assert_eq!(invalid_message(r#""\u{zz}""#),r"invalid unicode escape \u{zz}");
+        assert_eq!(invalid_message(r#""\u{D800}""#),r"invalid unicode escape \u{D800}");
+        assert_eq!(invalid_message(r#""\u41""#),r"invalid unicode escape, expected \u{..}");
+        assert_eq!(invalid_message(r#""${a"#),"unterminated ${ in string");
+    }
+}
@@ -1,18 +1,19 @@
 use std::{env, fs};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::process::id;
 use scanner_rust::generic_array::typenum::Exp;
-use crate::error::{PipelineError, PipelineResult};
+use crate::error::{PipelineError, PipelineResult, SourceFile};
 use crate::error::PipelineError::UnknownModule;
 use crate::module::{Class, Function, Module};
 use crate::v1::lexer::{Lexer, TokenStream};
-use crate::v1::stmt::{IfBranchStmt, IfStmt, Stmt};
+use crate::v1::stmt::{IfBranchStmt, IfStmt, Stmt, TryStmt};
 
-use crate::v1::token::Token;
+use crate::v1::token::{TemplatePart, Token};
 use crate::v1::ast::AST;
-use crate::v1::expr::{Expr, FnCallExpr, FnClosureExpr, Op, StructExpr};
+use crate::v1::expr::{Expr, FnCallExpr, FnClosureExpr, Op, StructExpr, WhenBranch, WhenCondition, WhenExpr};
 use crate::v1::expr::Expr::{BinaryExpr, FnCall};
 use crate::v1::position::{NONE, Position};
 
@@ -20,11 +21,13 @@ pub struct PipelineParser{
     token_stream: TokenStream,
     fn_lib:Vec<FnDef>,
     modules:Vec<Module>,
-    classes:HashMap<String,Class>
+    classes:HashMap<String,Class>,
+    /// 正在解析if、while、when的条件，此时条件后的{是语句块而不是尾随闭包
+    in_condition:bool
 }
 impl PipelineParser{
     pub fn new()->Self{
-        Self{token_stream:TokenStream::new(),fn_lib:vec![],modules:vec![],classes:HashMap::new()}
+        Self{token_stream:TokenStream::new(),fn_lib:vec![],modules:vec![],classes:HashMap::new(),in_condition:false}
     }
     pub fn register_predefined_class(&mut self,class:Class){
         self.classes.insert(class.get_name(),class);
@@ -38,6 +41,10 @@ impl PipelineParser{
     pub fn get_fn_lib(&self)->Vec<FnDef>{
         self.fn_lib.clone()
     }
+    /// 最近一次读取的token的位置，解析出错时用于定位错误
+    pub fn last_position(&self)->Position{
+        self.token_stream.last_position()
+    }
     pub fn get_modules(&self)->&Vec<Module>{
         &self.modules
     }
@@ -100,9 +107,19 @@ impl PipelineParser{
                             self.parse_for_loop()
                         }
                         "class"=>{
-                            self.parse_class();
+                            self.parse_class()?;
                             continue
                         }
+                        "try"=>{
+                            self.parse_try_stmt()
+                        }
+                        "throw"=>{
+                            self.parse_throw_stmt()
+                        }
+                        "when"=>{
+                            let expr=self.parse_when()?;
+                            Ok(Stmt::Expr(Box::new(expr),pos))
+                        }
                         t=>Err(PipelineError::UnusedKeyword(t.into()))
                     }
                 },
@@ -115,16 +132,24 @@ impl PipelineParser{
         }
 
     }
+    /// 解析class声明，属性可以写在{}或()中，属性可以通过=指定默认值：
+    /// class Target(name:String,kind:String="bin")
     pub fn parse_class(&mut self)->PipelineResult<()>{
         self.parse_keyword("class")?;
         let (class_name,class_name_pos)=self.parse_identifier()?;
         let mut pos=class_name_pos.clone();
-        self.parse_special_token(Token::ParenthesisLeft)?;
+        let close=if self.try_parse_special_token(Token::BraceLeft){
+            Token::BraceRight
+        }else{
+            self.parse_special_token(Token::ParenthesisLeft)?;
+            Token::ParenthesisRight
+        };
         let mut attributions=vec![];
+        let mut defaults=vec![];
         loop{
             let (peek_token,peek_pos)=self.token_stream.peek();
             match peek_token {
-                Token::ParenthesisRight=>{
+                t if t==close=>{
                     break
                 }
                 Token::Comma=>{
@@ -134,14 +159,22 @@ impl PipelineParser{
                 _=>{
                     let (attribution_name,attribution_name_pos)=self.parse_identifier()?;
                     self.parse_special_token(Token::Colon)?;
-                    let (attribution_type,attribution_type_pos)=self.parse_identifier()?;
+                    let (attribution_type,attribution_type_pos)=self.parse_type()?;
                     pos.add_span(attribution_name_pos.span+attribution_type_pos.span+1);
+                    if self.try_parse_special_token(Token::Assign){
+                        let default=self.parse_expr()?;
+                        pos.add_span(1+default.position().span);
+                        defaults.push((attribution_name.clone(),default));
+                    }
                     attributions.push(VariableDeclaration::new(attribution_name,attribution_type));
                 }
             }
         }
-        self.parse_special_token(Token::ParenthesisRight)?;
-        let class_declaration=Class::new(class_name.clone(),attributions);
+        self.parse_special_token(close)?;
+        let mut class_declaration=Class::new(class_name.clone(),attributions);
+        for (name,default) in defaults{
+            class_declaration.set_default(name,default);
+        }
         self.classes.insert(class_name,class_declaration);
         Ok(())
     }
@@ -165,6 +198,8 @@ impl PipelineParser{
             if s!="for"{
                 return Err(PipelineError::UnusedKeyword(s));
             }
+            //for (k,v) in map与for k,v in map等价
+            let parenthesized=self.try_parse_special_token(Token::BraceLeft);
             let (one,pos1)=self.token_stream.next();
             let (peek,pos11)=self.token_stream.peek();
             let mut other=None;
@@ -176,12 +211,15 @@ impl PipelineParser{
                 }
                 _=>{}
             }
+            if parenthesized{
+                self.parse_special_token(Token::BraceRight)?;
+            }
             let (in_token,mut pos2)=self.token_stream.next();
             if let Token::Keyword(s0)=in_token{
                 if s0!="in"{
                     return Err(PipelineError::UnusedKeyword(s));
                 }
-                let expr=self.parse_expr_call_chain_exclude_map()?;
+                let expr=self.parse_condition()?;
                 self.parse_special_token(Token::ParenthesisLeft)?;
                 let blocks=self.parse_stmt_blocks()?;
                 self.parse_special_token(Token::ParenthesisRight)?;
@@ -203,11 +241,13 @@ impl PipelineParser{
         };
         let mut script=String::new();
         current_dir.push(format!("{}.kts",module_name.as_ref()));
+        let mut module_path=current_dir.clone();
         if current_dir.exists(){
             script=fs::read_to_string(current_dir).unwrap();
         }else{
             let home_dir = dirs::home_dir().expect("无法获取用户根目录");
             let file_path=home_dir.join(".pipeline/package").join(format!("{}.kts",module_name.as_ref()));
+            module_path=file_path.clone();
             let read_result=fs::read_to_string(file_path);
             match read_result {
                 Ok(r) => {
@@ -220,13 +260,17 @@ impl PipelineParser{
         }
         // 打印当前工作目录
 
+        let source=SourceFile::new(module_path.to_string_lossy(),script.as_str());
         let mut parser=PipelineParser::new();
         let lexer=Lexer::from_script(script);
         parser.set_lexer(lexer);
-        parser.parse_stmt_blocks()?;
+        parser.parse_stmt_blocks().map_err(|e|{
+            e.with_position(&parser.last_position()).with_source(&source)
+        })?;
         let lib=parser.get_fn_lib();
         let mut m=Module::new(module_name.as_ref());
-        for l in lib{
+        for mut l in lib{
+            l.source=Some(source.clone());
             m.register_script_function(l.name.clone(),l)
         }
         return Ok(Some(m));
@@ -258,11 +302,9 @@ impl PipelineParser{
             if s!="if"{
                 return Err(PipelineError::UnusedKeyword(s));
             }
-            let expr=self.parse_expr()?;
+            let expr=self.parse_condition()?;
             pos.add_span(expr.position().span);
-            self.parse_special_token(Token::ParenthesisLeft)?;
-            let blocks=self.parse_stmt_blocks()?;
-            self.parse_special_token(Token::ParenthesisRight)?;
+            let blocks=self.parse_branch_body()?;
             for i in &blocks{
                 pos.add_span(i.position().span)
             }
@@ -272,6 +314,10 @@ impl PipelineParser{
     }
 
     pub fn parse_if_stmt(&mut self)->PipelineResult<Stmt>{
+        let (if_stmt,pos)=self.parse_if()?;
+        Ok(Stmt::If(Box::new(if_stmt),pos))
+    }
+    fn parse_if(&mut self)->PipelineResult<(IfStmt,Position)>{
         let mut branches=vec![];
         let mut else_body=None;
         let ( b,pos)=self.parse_if_branch()?;
@@ -283,15 +329,15 @@ impl PipelineParser{
                 Token::Keyword(k) if k=="else" =>{
                     self.token_stream.next();
                     let (peek0,pos01)=self.token_stream.peek();
-                    if let Token::ParenthesisLeft=peek0{
-                        self.parse_special_token(Token::ParenthesisLeft)?;
-                        let blocks=self.parse_stmt_blocks()?;
-                        self.parse_special_token(Token::ParenthesisRight)?;
-                        else_body=Some(blocks);
-                        break
+                    if let Token::Keyword(k)=peek0{
+                        if k=="if"{
+                            let (b0,pos00)=self.parse_if_branch()?;
+                            branches.push(b0);
+                            continue
+                        }
                     }
-                    let (b0,pos00)=self.parse_if_branch()?;
-                    branches.push(b0);
+                    else_body=Some(self.parse_branch_body()?);
+                    break
                 }
                 _=>{
                     break
@@ -299,7 +345,7 @@ impl PipelineParser{
             }
         }
 
-        return Ok(Stmt::If(Box::new(IfStmt::new(branches,else_body)),pos))
+        return Ok((IfStmt::new(branches,else_body),pos))
 
     }
     pub fn parse_while_stmt(&mut self)->PipelineResult<Stmt>{
@@ -308,7 +354,7 @@ impl PipelineParser{
             if s!="while"{
                 return Err(PipelineError::UnusedKeyword(s));
             }
-            let expr=self.parse_expr()?;
+            let expr=self.parse_condition()?;
             pos.add_span(expr.position().span);
             self.parse_special_token(Token::ParenthesisLeft)?;
             let blocks=self.parse_stmt_blocks()?;
@@ -320,6 +366,133 @@ impl PipelineParser{
         }
         return Err(PipelineError::UnexpectedToken(ret));
     }
+    /// 解析if、while、when的条件
+    fn parse_condition(&mut self)->PipelineResult<Expr>{
+        let outer=std::mem::replace(&mut self.in_condition,true);
+        let expr=self.parse_expr();
+        self.in_condition=outer;
+        expr
+    }
+    /// 解析if、when的分支，分支可以是{...}包裹的语句块，也可以是单个语句或表达式：if (a) 1 else 2
+    fn parse_branch_body(&mut self)->PipelineResult<Vec<Stmt>>{
+        if let (Token::ParenthesisLeft,_)=self.token_stream.peek(){
+            return Ok(self.parse_block()?.0)
+        }
+        Ok(vec![self.parse_stmt()?])
+    }
+    /// when(subject){ "a","b" -> ... in list -> ... is Int -> ... else -> ... }，
+    /// 省略subject时每个分支的条件为Bool表达式
+    pub fn parse_when(&mut self)->PipelineResult<Expr>{
+        let (_,mut pos)=self.parse_keyword("when")?;
+        let mut subject=None;
+        if self.token_stream.peek().0!=Token::ParenthesisLeft{
+            subject=Some(self.parse_condition()?);
+        }
+        self.parse_special_token(Token::ParenthesisLeft)?;
+        let mut branches=vec![];
+        let mut else_body=None;
+        loop {
+            let (peek,peek_pos)=self.token_stream.peek();
+            match peek {
+                Token::ParenthesisRight=>{
+                    self.token_stream.next();
+                    pos.set_span(peek_pos.pos+peek_pos.span-pos.pos);
+                    break
+                }
+                Token::Keyword(k) if k=="else"=>{
+                    self.token_stream.next();
+                    self.parse_special_token(Token::Arrow)?;
+                    else_body=Some(self.parse_branch_body()?);
+                }
+                _=>{
+                    let mut conditions=vec![self.parse_when_condition()?];
+                    while self.try_parse_special_token(Token::Comma){
+                        conditions.push(self.parse_when_condition()?);
+                    }
+                    self.parse_special_token(Token::Arrow)?;
+                    let body=self.parse_branch_body()?;
+                    branches.push(WhenBranch::new(conditions,body));
+                }
+            }
+        }
+        Ok(Expr::When(Box::new(WhenExpr::new(subject,branches,else_body)),pos))
+    }
+    fn parse_when_condition(&mut self)->PipelineResult<WhenCondition>{
+        let mut negated=false;
+        if let (Token::Not,Token::Keyword(k))=(self.token_stream.peek().0,self.token_stream.peek_nth(1).0){
+            if k=="in"||k=="is"{
+                self.token_stream.next();
+                negated=true;
+            }
+        }
+        if let (Token::Keyword(k),_)=self.token_stream.peek(){
+            match k.as_str() {
+                "in"=>{
+                    self.token_stream.next();
+                    return Ok(WhenCondition::In(self.parse_expr()?,negated))
+                }
+                "is"=>{
+                    self.token_stream.next();
+                    return Ok(WhenCondition::Is(self.parse_type()?.0,negated))
+                }
+                _=>{}
+            }
+        }
+        Ok(WhenCondition::Value(self.parse_expr()?))
+    }
+    /// 解析{...}包裹的语句块，返回语句块及其位置
+    fn parse_block(&mut self)->PipelineResult<(Vec<Stmt>,Position)>{
+        let (_,mut pos)=self.parse_special_token(Token::ParenthesisLeft)?;
+        let blocks=self.parse_stmt_blocks()?;
+        let (_,end)=self.parse_special_token(Token::ParenthesisRight)?;
+        pos.set_span(end.pos+end.span-pos.pos);
+        Ok((blocks,pos))
+    }
+    /// try{...} catch(e){...} finally{...}，catch与finally至少有一个，
+    /// catch的参数可以省略括号，也可以像kotlin一样声明类型(e:Exception)，类型会被忽略
+    pub fn parse_try_stmt(&mut self)->PipelineResult<Stmt>{
+        let (_,mut pos)=self.parse_keyword("try")?;
+        let (body,body_pos)=self.parse_block()?;
+        let mut end=body_pos;
+        let mut catch=None;
+        let mut finally=None;
+        if let (Token::Keyword(k),_)=self.token_stream.peek(){
+            if k=="catch"{
+                self.token_stream.next();
+                let brace=self.try_parse_special_token(Token::BraceLeft);
+                let (name,_)=self.parse_identifier()?;
+                if self.try_parse_special_token(Token::Colon){
+                    self.parse_identifier()?;
+                }
+                if brace{
+                    self.parse_special_token(Token::BraceRight)?;
+                }
+                let (blocks,blocks_pos)=self.parse_block()?;
+                end=blocks_pos;
+                catch=Some((name,blocks));
+            }
+        }
+        if let (Token::Keyword(k),_)=self.token_stream.peek(){
+            if k=="finally"{
+                self.token_stream.next();
+                let (blocks,blocks_pos)=self.parse_block()?;
+                end=blocks_pos;
+                finally=Some(blocks);
+            }
+        }
+        if catch.is_none()&&finally.is_none(){
+            let (next,_)=self.token_stream.next();
+            return Err(PipelineError::UnexpectedToken(next))
+        }
+        pos.set_span(end.pos+end.span-pos.pos);
+        Ok(Stmt::Try(Box::new(TryStmt::new(body,catch,finally)),pos))
+    }
+    pub fn parse_throw_stmt(&mut self)->PipelineResult<Stmt>{
+        let (_,mut pos)=self.parse_keyword("throw")?;
+        let expr=self.parse_expr()?;
+        pos.add_span(expr.position().span);
+        Ok(Stmt::Throw(Box::new(expr),pos))
+    }
     pub fn parse_return_stmt(&mut self)->PipelineResult<Stmt>{
         let (ret,mut pos)=self.token_stream.next();
         if let Token::Keyword(s)=ret.clone(){
@@ -358,6 +531,7 @@ impl PipelineParser{
                     pos.add_span(pos1.span);
                     let (dec_args,pos2)=self.parse_fn_def_args()?;
                     pos.add_span(pos2.span);
+                    let return_type=self.parse_return_type()?;
                     self.parse_special_token(Token::ParenthesisLeft)?;
                     pos.add_span(1);
                     let stmts=self.parse_stmt_blocks()?;
@@ -367,7 +541,7 @@ impl PipelineParser{
                     }
                     self.parse_special_token(Token::ParenthesisRight)?;
                     pos.add_span(1);
-                    return Ok((FnDef::new(ident,dec_args,stmts,"Any".into()),pos))
+                    return Ok((FnDef::new(ident,dec_args,stmts,return_type),pos))
                 }
                 return Err(PipelineError::UnexpectedToken(next1))
             },
@@ -383,8 +557,7 @@ impl PipelineParser{
         if b{
             let (two_name,two_name_pos)=self.parse_identifier()?;
             let (function_params,function_params_pos)=self.parse_fn_def_args()?;
-            self.parse_special_token(Token::Colon)?;
-            let (return_type,return_type_pos)=self.parse_identifier()?;
+            let return_type=self.parse_return_type()?;
             self.parse_special_token(Token::ParenthesisLeft)?;
             let stmts=self.parse_stmt_blocks()?;
             self.parse_special_token(Token::ParenthesisRight)?;
@@ -394,8 +567,7 @@ impl PipelineParser{
             return Ok(())
         }
         let (function_params,function_params_pos)=self.parse_fn_def_args()?;
-        self.parse_special_token(Token::Colon)?;
-        let (return_type,return_type_pos)=self.parse_identifier()?;
+        let return_type=self.parse_return_type()?;
         self.parse_special_token(Token::ParenthesisLeft)?;
         let stmts=self.parse_stmt_blocks()?;
         self.parse_special_token(Token::ParenthesisRight)?;
@@ -435,22 +607,35 @@ impl PipelineParser{
         p.add_span(1);
         return Ok((v,p))
     }
+    /// 解析函数声明中可选的返回值类型，省略时为Any
+    fn parse_return_type(&mut self)->PipelineResult<String>{
+        if self.try_parse_special_token(Token::Colon){
+            return Ok(self.parse_type()?.0)
+        }
+        Ok("Any".into())
+    }
+    /// 解析类型名，类型名后的?表示可以为null，如Int?
+    pub fn parse_type(&mut self)->PipelineResult<(String,Position)>{
+        let (mut name,mut pos)=self.parse_identifier()?;
+        if self.try_parse_special_token(Token::Question){
+            name.push('?');
+            pos.add_span(1);
+        }
+        Ok((name,pos))
+    }
     pub fn parse_variable_declaration(&mut self)->PipelineResult<(VariableDeclaration,Position)>{
         let (next,mut pos)=self.token_stream.next();
         if let Token::Identifier(s)=next.clone(){
             self.parse_special_token(Token::Colon)?;
-            let (next1,pos1)=self.token_stream.next();
-            if let Token::Identifier(s1)=next1{
-                pos.add_span(1+pos1.span);
-                return Ok((VariableDeclaration::new(s,s1),pos))
-            }
-            return Err(PipelineError::UnexpectedToken(next1))
+            let (declaration_type,pos1)=self.parse_type()?;
+            pos.add_span(1+pos1.span);
+            return Ok((VariableDeclaration::new(s,declaration_type),pos))
         }
         return Err(PipelineError::UnexpectedToken(next))
     }
 
     pub fn parse_expr_stmt(&mut self)->PipelineResult<Stmt>{
-        let lhs=self.parse_expr_call_chain()?;
+        let lhs=self.parse_expr()?;
         let (token,mut pos0)=self.token_stream.peek();
         Ok(match token {
             Token::Assign=>{
@@ -463,11 +648,38 @@ impl PipelineParser{
                 }
                 return Ok(Stmt::Assign(Box::new((lhs.clone(), expr)), pos0))
             }
+            Token::PlusAssign|Token::MinusAssign|Token::MulAssign|Token::DivAssign|Token::Increment|Token::Decrement=>{
+                //a+=b、a++按a=a+b、a=a+1计算，目标可以是变量、属性或下标
+                self.token_stream.next();
+                let (op,rhs)=match token {
+                    Token::Increment=>(Op::Plus,Expr::IntConstant(1,pos0.clone())),
+                    Token::Decrement=>(Op::Minus,Expr::IntConstant(1,pos0.clone())),
+                    t=>{
+                        let op=match t {
+                            Token::PlusAssign=>Op::Plus,
+                            Token::MinusAssign=>Op::Minus,
+                            Token::MulAssign=>Op::Mul,
+                            _=>Op::Div
+                        };
+                        (op,self.parse_expr()?)
+                    }
+                };
+                pos0.add_span(rhs.position().span);
+                if let Expr::Index(_,_,pos1)=&lhs{
+                    let mut pos1=pos1.clone();
+                    pos1.add_span(pos0.span);
+                    return Ok(Stmt::CompoundAssign(Box::new((lhs,op,rhs)),pos1))
+                }
+                return Ok(Stmt::CompoundAssign(Box::new((lhs,op,rhs)),pos0))
+            }
             Token::BraceLeft => {
-                let(mut args,pos)=self.parse_fn_call_args()?;
+                let(mut args,args_pos)=self.parse_fn_call_args()?;
+                let mut pos=lhs.position();
+                pos.add_span(args_pos.span);
                 let mut fn_call_expr=FnCallExpr{
                     name:"".into(),
                     args:vec![],
+                    method:false
                 };
                 match lhs {
                     Expr::Variable(s,_)=>{
@@ -475,6 +687,7 @@ impl PipelineParser{
                     }
                     Expr::MemberAccess(b,n,_)=>{
                         fn_call_expr.name=n;
+                        fn_call_expr.method=true;
                         args.insert(0,*b)
                     }
                     _=>panic!("only variable and member_access expected")
@@ -487,7 +700,8 @@ impl PipelineParser{
                if let Expr::FnCall(fc,pos)=lhs{
                    return Ok(Stmt::FnCall(Box::new(fc),pos))
                }
-               return Ok(Stmt::Noop)
+               let pos=lhs.position();
+               return Ok(Stmt::Expr(Box::new(lhs),pos))
            }
 
         })
@@ -562,9 +776,12 @@ impl PipelineParser{
     //         args
     //     },pos))
     // }
+    /// 解析函数调用参数，具名参数(name=value)会被收集为一个Map，作为最后一个参数(尾随闭包之前)传入
     pub fn parse_fn_call_args(&mut self)->PipelineResult<(Vec<Expr>,Position)>{
         self.parse_special_token(Token::BraceLeft)?;
+        let in_condition=std::mem::replace(&mut self.in_condition,false);
         let mut v =vec![];
+        let mut named=vec![];
         let mut p=NONE.clone();
         p.add_span(1);
         loop {
@@ -573,8 +790,19 @@ impl PipelineParser{
                 self.token_stream.next();
                 break
             }
-            let expr=self.parse_expr()?;
-            v.push(expr.clone());
+            let mut expr=self.parse_expr()?;
+            let (peek,_)=self.token_stream.peek();
+            match (peek,&expr) {
+                (Token::Assign,Expr::Variable(name,name_pos))=>{
+                    self.token_stream.next();
+                    let value=self.parse_expr()?;
+                    let mut pos=name_pos.clone();
+                    pos.add_span(1+value.position().span);
+                    named.push((Expr::StringConstant(name.clone(),name_pos.clone()),value));
+                    expr=Expr::None(pos);
+                }
+                _=>v.push(expr.clone())
+            }
             let expr_pos=expr.position();
             if p.is_none(){
                 p.set_pos(expr_pos.pos);
@@ -595,28 +823,111 @@ impl PipelineParser{
                 _=>return Err(PipelineError::UnexpectedToken(token))
             }
         }
-        if let (peek,mut pos1)=self.token_stream.peek(){
-            if Token::ParenthesisLeft==peek{
-                self.parse_special_token(Token::ParenthesisLeft).unwrap();
-                let blocks=self.parse_stmt_blocks()?;
-                for stmt in &blocks{
-                    pos1.add_span(stmt.position().span)
+        self.in_condition=in_condition;
+        if !named.is_empty(){
+            let pos=named[0].0.position();
+            v.push(Expr::Map(named,pos));
+        }
+        if let (Token::ParenthesisLeft,false)=(self.token_stream.peek().0,self.in_condition){
+            let closure=self.parse_lambda()?;
+            p.add_span(closure.position().span);
+            v.push(closure);
+        }
+        return Ok((v,p))
+    }
+    /// 从start开始的token是否为lambda的参数列表(a, b:Int ->)，是则返回参数列表包含的token数
+    fn lambda_header_len(&mut self,start:usize)->Option<usize>{
+        let mut i=start;
+        loop {
+            match self.token_stream.peek_nth(i).0 {
+                Token::Arrow=>return Some(i-start+1),
+                Token::Identifier(_)=>i+=1,
+                _=>return None
+            }
+            if self.token_stream.peek_nth(i).0==Token::Colon{
+                if let Token::Identifier(_)=self.token_stream.peek_nth(i+1).0{
+                    i+=2;
+                    if self.token_stream.peek_nth(i).0==Token::Question{
+                        i+=1;
+                    }
+                }else{
+                    return None
                 }
-                let fn_def=FnDef::new("".to_string(),vec![],blocks,"Any".into());
-                pos1.add_span(1);
-                p.add_span(pos1.span);
-                v.push(Expr::FnClosure(FnClosureExpr { def: fn_def },pos1));
-                self.parse_special_token(Token::ParenthesisRight).unwrap();
+            }
+            match self.token_stream.peek_nth(i).0 {
+                Token::Comma=>i+=1,
+                Token::Arrow=>{},
+                _=>return None
             }
         }
-        return Ok((v,p))
+    }
+    /// 解析lambda：{ a, b -> a + b }，没有参数列表时通过it访问参数
+    fn parse_lambda(&mut self)->PipelineResult<Expr>{
+        let (_,mut pos)=self.parse_special_token(Token::ParenthesisLeft)?;
+        let mut args=vec![];
+        if self.lambda_header_len(0).is_some(){
+            loop {
+                if self.try_parse_special_token(Token::Arrow){
+                    break
+                }
+                let (name,_)=self.parse_identifier()?;
+                let mut declaration_type=String::from("Any");
+                if self.try_parse_special_token(Token::Colon){
+                    declaration_type=self.parse_type()?.0;
+                }
+                args.push(VariableDeclaration::new(name,declaration_type));
+                self.try_parse_special_token(Token::Comma);
+            }
+        }
+        let blocks=self.parse_stmt_blocks()?;
+        let (_,end)=self.parse_special_token(Token::ParenthesisRight)?;
+        pos.set_span(end.pos+end.span-pos.pos);
+        let fn_def=FnDef::new("".to_string(),args,blocks,"Any".into());
+        Ok(Expr::FnClosure(FnClosureExpr { def: fn_def },pos))
+    }
+    /// 解析插值字符串，每个插值表达式使用单独的词法分析器解析，位置仍对应原脚本
+    fn parse_template_string(&mut self,parts:Vec<TemplatePart>,pos:Position)->PipelineResult<Expr>{
+        let mut v=vec![];
+        for part in parts{
+            match part {
+                TemplatePart::Literal(s)=>v.push(Expr::StringConstant(s,pos.clone())),
+                TemplatePart::Expr(script,offset)=>{
+                    let lexer=Lexer::from_script_at(script,offset);
+                    let outer=std::mem::replace(&mut self.token_stream,lexer.into_iter());
+                    let expr=self.parse_expr().map_err(|e|e.with_position(&self.last_position()));
+                    let (rest,rest_pos)=self.token_stream.peek();
+                    self.token_stream=outer;
+                    let expr=expr?;
+                    if rest!=Token::EOF{
+                        return Err(PipelineError::UnexpectedToken(rest).with_position(&rest_pos))
+                    }
+                    v.push(expr);
+                }
+            }
+        }
+        Ok(Expr::StringTemplate(v,pos))
     }
     fn parse_primary(&mut self)->PipelineResult<Expr>{
+        //数组字面量可以作为方法调用的接收者：["a","b"].map{ it.uppercase() }
+        if self.token_stream.peek().0==Token::SquareBracketLeft{
+            return self.parse_array()
+        }
         let (token,mut pos)=self.token_stream.next();
         match token {
+            Token::BraceLeft => {
+                let in_condition=std::mem::replace(&mut self.in_condition,false);
+                let expr=self.parse_expr();
+                self.in_condition=in_condition;
+                let expr=expr?;
+                self.parse_special_token(Token::BraceRight)?;
+                Ok(expr)
+            }
             Token::String(s) => {
                 Ok(Expr::StringConstant(s,pos))
             }
+            Token::TemplateString(parts) => {
+                self.parse_template_string(parts,pos)
+            }
             Token::Int(i) => {
                 Ok(Expr::IntConstant(i,pos))
             }
@@ -634,7 +945,7 @@ impl PipelineParser{
                         let name=ident+"::"+fc_name;
                         let mut p=pos.clone();
                         p.add_span(pos2.span+2);
-                        let fn_expr=FnCallExpr{name,args};
+                        let fn_expr=FnCallExpr{name,args,method:false};
                         pos.add_span(pos2.span+pos3.span+2);
                         return Ok(Expr::FnCall(fn_expr,pos));
                     }
@@ -645,7 +956,8 @@ impl PipelineParser{
                         pos1.add_span(1+e.position().span+1);
                         return Ok(Expr::Index(Box::new(Expr::Variable(ident,pos)),Box::new(e),pos1))
                     }
-                    Token::ParenthesisLeft=>{
+                    //只有已声明的类名后跟{才是结构体字面量，避免把if a {...}中的a当作结构体
+                    Token::ParenthesisLeft if self.classes.contains_key(&ident)=>{
                         let mut props=HashMap::new();
                         self.token_stream.next();
                         loop{
@@ -680,53 +992,13 @@ impl PipelineParser{
             _=>Err(PipelineError::UnexpectedToken(token))
         }
     }
-    fn parse_primary_exclude_map(&mut self)->PipelineResult<Expr>{
-        let (token,mut pos)=self.token_stream.next();
-        match token {
-            Token::String(s) => {
-                Ok(Expr::StringConstant(s,pos))
-            }
-            Token::Int(i) => {
-                Ok(Expr::IntConstant(i,pos))
-            }
-            Token::Float(f) => {
-                Ok(Expr::FloatConstant(f,pos))
-            }
-            Token::Identifier(ident) => {
-                let (peek,mut pos1)=self.token_stream.peek();
-                match peek {
-                    Token::ScopeSymbol=>{
-                        self.token_stream.next();
-                        let (next,pos2)=self.token_stream.next();
-                        let fc_name=next.get_identifier_value();
-                        let (args,pos3)=self.parse_fn_call_args().unwrap();
-                        let name=ident+"::"+fc_name;
-                        let mut p=pos.clone();
-                        p.add_span(pos2.span+2);
-                        let fn_expr=FnCallExpr{name,args};
-                        pos.add_span(pos2.span+pos3.span+2);
-                        return Ok(Expr::FnCall(fn_expr,pos));
-                    }
-                    Token::SquareBracketLeft=>{
-                        self.token_stream.next();
-                        let e=self.parse_math_expr()?;
-                        self.parse_special_token(Token::SquareBracketRight)?;
-                        pos1.add_span(1+e.position().span+1);
-                        return Ok(Expr::Index(Box::new(Expr::Variable(ident,pos)),Box::new(e),pos1))
-                    }
-                    _=> Ok(Expr::Variable(ident,pos))
-                }
-            }
-            _=>Err(PipelineError::UnexpectedToken(token))
-        }
-    }
-
     fn parse_expr_call_chain(&mut self)->PipelineResult<Expr>{
         let mut lhs=self.parse_primary()?;
         loop{
             let (peek, pos)=self.token_stream.peek();
             match peek{
-                Token::Dot=>{
+                Token::Dot|Token::SafeDot=>{
+                    let safe=peek==Token::SafeDot;
                     self.token_stream.next();
                     let (next, pos0)=self.token_stream.next();
                     let name=next.get_identifier_value();
@@ -739,24 +1011,38 @@ impl PipelineParser{
                         let mut fn_call=FnCallExpr{
                             name: name.into(),
                             args,
+                            method:true
                         };
                         let mut p=lhs.position();
                         p.add_span(1+pos0.span+pos1.span);
                         fn_call.args.insert(0,lhs);
                         let expr=FnCall(fn_call,p);
                         lhs=expr;
+                    }else if let (Token::ParenthesisLeft,false)=(peek1,self.in_condition){
+                        //只有尾随闭包的方法调用：arr.map{ it*2 }
+                        let closure=self.parse_lambda()?;
+                        let mut p=lhs.position();
+                        p.add_span(1+pos0.span+closure.position().span);
+                        lhs=FnCall(FnCallExpr{name:name.into(),args:vec![lhs,closure],method:true},p);
                     }else{
                         let mut pos00=lhs.position();
                         pos00.add_span(1+pos0.span);
                         let member_access=Expr::MemberAccess(Box::new(lhs.clone()),name.into(),pos00);
                         lhs=member_access;
                     }
+                    if safe{
+                        let p=lhs.position();
+                        lhs=Expr::SafeAccess(Box::new(lhs),p);
+                    }
                 }
                 Token::BraceLeft=>{
-                    let(mut args,pos)=self.parse_fn_call_args()?;
+                    let(mut args,args_pos)=self.parse_fn_call_args()?;
+                    let mut pos=lhs.position();
+                    pos.add_span(args_pos.span);
                     let mut fn_call_expr=FnCallExpr{
                         name:"".into(),
                         args:vec![],
+                        method:false
                     };
                     match lhs.clone() {
                         Expr::Variable(s,_)=>{
@@ -764,6 +1050,7 @@ impl PipelineParser{
                         }
                         Expr::MemberAccess(b,n,_)=>{
                             fn_call_expr.name=n;
+                            fn_call_expr.method=true;
                             args.insert(0,*b)
                         }
                         _=>panic!("only variable and member_access expected")
@@ -785,207 +1072,127 @@ impl PipelineParser{
             }
         }
     }
-    fn parse_expr_call_chain_exclude_map(&mut self)->PipelineResult<Expr>{
-        let mut lhs=self.parse_primary_exclude_map()?;
-        loop{
-            let (peek, pos)=self.token_stream.peek();
-            match peek{
-                Token::Dot=>{
-                    self.token_stream.next();
-                    let (next, pos0)=self.token_stream.next();
-                    let name=next.get_identifier_value();
-                    let (peek1,pos1)=self.token_stream.peek();
-                    if let Token::BraceLeft=peek1{
-                        let (args,pos1)=self.parse_fn_call_args()?;
-                        // let (mut fn_call,mut pos)=self.parse_fn_call_expr(name,pos0)?;
-                        // pos.add_span(lhs.position().span+1);
-                        // fn_call.args.insert(0,lhs.clone());
-                        let mut fn_call=FnCallExpr{
-                            name: name.into(),
-                            args,
-                        };
-                        let mut p=lhs.position();
-                        p.add_span(1+pos0.span+pos1.span);
-                        fn_call.args.insert(0,lhs);
-                        let expr=FnCall(fn_call,p);
-                        lhs=expr;
-                    }else{
-                        let mut pos00=lhs.position();
-                        pos00.add_span(1+pos0.span);
-                        let member_access=Expr::MemberAccess(Box::new(lhs.clone()),name.into(),pos00);
-                        lhs=member_access;
-                    }
-                }
-                Token::BraceLeft=>{
-                    let(mut args,pos)=self.parse_fn_call_args()?;
-                    let mut fn_call_expr=FnCallExpr{
-                        name:"".into(),
-                        args:vec![],
-                    };
-                    match lhs.clone() {
-                        Expr::Variable(s,_)=>{
-                            fn_call_expr.name=s;
-                        }
-                        Expr::MemberAccess(b,n,_)=>{
-                            fn_call_expr.name=n;
-                            args.insert(0,*b)
-                        }
-                        _=>panic!("only variable and member_access expected")
-                    }
-                    fn_call_expr.args=args;
-                    lhs=Expr::FnCall(fn_call_expr,pos)
-                }
-                Token::SquareBracketLeft=>{
-                    let mut pos1=lhs.position();
-                    self.token_stream.next();
-                    let e=self.parse_math_expr()?;
-                    self.parse_special_token(Token::SquareBracketRight)?;
-                    pos1.add_span(1+e.position().span+1);
-                    return Ok(Expr::Index(Box::new(lhs),Box::new(e),pos1))
-                }
-                _=>{
-                    return Ok(lhs)
-                }
+    /// 一元运算符：!取反，-取负，负号后直接跟数字时折叠为负数常量
+    fn parse_unary(&mut self)->PipelineResult<Expr>{
+        let (token,mut pos)=self.token_stream.peek();
+        let op=match token {
+            Token::Not=>Op::Not,
+            Token::Minus=>Op::Negative,
+            Token::Keyword(k) if k=="if"=>{
+                let (if_stmt,pos)=self.parse_if()?;
+                return Ok(Expr::If(Box::new(if_stmt),pos))
             }
+            Token::Keyword(k) if k=="when"=>return self.parse_when(),
+            _=>return self.parse_expr_call_chain()
+        };
+        self.token_stream.next();
+        let expr=self.parse_unary()?;
+        pos.add_span(expr.position().span);
+        match (op,expr) {
+            (Op::Negative,Expr::IntConstant(i,_))=>Ok(Expr::IntConstant(-i,pos)),
+            (Op::Negative,Expr::FloatConstant(f,_))=>Ok(Expr::FloatConstant(-f,pos)),
+            (op,expr)=>Ok(Expr::UnaryExpr(op,Box::new(expr),pos))
         }
     }
+    fn binary_expr(op:Op,lhs:Expr,rhs:Expr)->Expr{
+        let mut pos=lhs.position();
+        pos.add_span(1+rhs.position().span);
+        BinaryExpr(op,Box::new(lhs),Box::new(rhs),pos)
+    }
     fn parse_term(&mut self)->PipelineResult<Expr>{
-        let lhs=self.parse_expr_call_chain()?;
-        let (token,mut pos)=self.token_stream.peek();
-        match token {
-            Token::Mul=>{
-                self.token_stream.next();
-                let right=self.parse_term()?;
-                pos.add_span(1+right.position().span+lhs.position().span);
-                Ok(BinaryExpr(Op::Mul,Box::new(lhs),Box::new(right),pos))
-            }
-            Token::Div=>{
-                self.token_stream.next();
-                let right=self.parse_term()?;
-                pos.add_span(1+right.position().span+lhs.position().span);
-                Ok(BinaryExpr(Op::Div,Box::new(lhs),Box::new(right),pos))
-            }
-            Token::Mod=>{
-                self.token_stream.next();
-                let right=self.parse_term()?;
-                pos.add_span(1+right.position().span+lhs.position().span);
-                Ok(BinaryExpr(Op::Mod,Box::new(lhs),Box::new(right),pos))
-            }
-            // Token::Dot=>{
-            //     self.token_stream.next();
-            //     let (next, pos0)=self.token_stream.next();
-            //     let name=next.get_identifier_value();
-            //     let (mut fn_call,mut pos)=self.parse_fn_call_expr(name,pos0)?;
-            //     pos.add_span(lhs.position().span+1);
-            //     fn_call.args.insert(0,lhs);
-            //     Ok(FnCall(fn_call,pos))
-            // }
-            // Token::String(s) => {
-            //     Ok(Expr::StringConstant(s,pos))
-            // }
-            // Token::Int(i) => {
-            //     let (peek,_)=self.token_stream.peek();
-            //     match peek {
-            //         Token::Mul=>{
-            //             self.token_stream.next();
-            //             let right=self.parse_term()?;
-            //             let left=Box::new(Expr::IntConstant(i,pos.clone()));
-            //             pos.add_span(1+right.position().span);
-            //             Ok(BinaryExpr(Op::Mul,left,Box::new(right),pos))
-            //         }
-            //         Token::Div=>{
-            //             self.token_stream.next();
-            //             let right=self.parse_term()?;
-            //             let left=Box::new(Expr::IntConstant(i,pos.clone()));
-            //             pos.add_span(1+right.position().span);
-            //             Ok(BinaryExpr(Op::Div,left,Box::new(right),pos))
-            //         }
-            //         Token::Mod=>{
-            //             self.token_stream.next();
-            //             let right=self.parse_term()?;
-            //             let left=Box::new(Expr::IntConstant(i,pos.clone()));
-            //             pos.add_span(1+right.position().span);
-            //             Ok(BinaryExpr(Op::Mod,left,Box::new(right),pos))
-            //         }
-            //         _=>Ok(Expr::IntConstant(i,pos))
-            //     }
-            //
-            // }
-            // Token::Float(f) => {
-            //     Ok(Expr::FloatConstant(f,pos))
-            // }
-            // Token::Identifier(ident) => {
-            //     let (peek,mut pos1)=self.token_stream.peek();
-            //     match peek {
-            //         Token::Mul=>{
-            //             self.token_stream.next();
-            //             let right=self.parse_term()?;
-            //             let left=Box::new(Expr::Variable(ident,pos.clone()));
-            //             pos.add_span(1+right.position().span);
-            //             Ok(BinaryExpr(Op::Mul,left,Box::new(right),pos))
-            //         }
-            //         Token::Div=>{
-            //             self.token_stream.next();
-            //             let right=self.parse_term()?;
-            //             let left=Box::new(Expr::Variable(ident,pos.clone()));
-            //             pos.add_span(1+right.position().span);
-            //             Ok(BinaryExpr(Op::Div,left,Box::new(right),pos))
-            //         }
-            //         Token::Mod=>{
-            //             self.token_stream.next();
-            //             let right=self.parse_term()?;
-            //             let left=Box::new(Expr::Variable(ident,pos.clone()));
-            //             pos.add_span(1+right.position().span);
-            //             Ok(BinaryExpr(Op::Mod,left,Box::new(right),pos))
-            //         }
-            //         Token::BraceLeft=>{
-            //             let (args,pos2)=self.parse_fn_call_args().unwrap();
-            //             let fn_expr=FnCallExpr{name:ident,args};
-            //             pos.add_span(pos2.span);
-            //             return Ok(Expr::FnCall(fn_expr,pos));
-            //         }
-            //         Token::ScopeSymbol=>{
-            //             self.token_stream.next();
-            //             let (next,pos1)=self.token_stream.next();
-            //             let fc_name=next.get_identifier_value();
-            //             let (args,pos2)=self.parse_fn_call_args().unwrap();
-            //             let fn_expr=FnCallExpr{name:ident+"::"+fc_name,args};
-            //             pos.add_span(pos1.span+pos2.span+2);
-            //             return Ok(Expr::FnCall(fn_expr,pos));
-            //         }
-            //         Token::SquareBracketLeft=>{
-            //             self.token_stream.next();
-            //             let e=self.parse_math_expr()?;
-            //             self.parse_special_token(Token::SquareBracketRight)?;
-            //             pos1.add_span(1+e.position().span+1);
-            //             return Ok(Expr::Index(ident,Box::new(e),pos1))
-            //         }
-            //         _=> Ok(Expr::Variable(ident,pos))
-            //     }
-            // }
-            _=>Ok(lhs)
+        let mut lhs=self.parse_unary()?;
+        loop {
+            let op=match self.token_stream.peek().0 {
+                Token::Mul=>Op::Mul,
+                Token::Div=>Op::Div,
+                Token::Mod=>Op::Mod,
+                _=>return Ok(lhs)
+            };
+            self.token_stream.next();
+            let rhs=self.parse_unary()?;
+            lhs=Self::binary_expr(op,lhs,rhs);
         }
     }
     fn parse_math_expr(&mut self)->PipelineResult<Expr>{
-        let lhs=self.parse_term()?;
-        let next=self.token_stream.peek();
-        return match next.0 {
-            Token::Plus=>{
-                self.token_stream.next();
-                let mut pos=lhs.position();
-                let rhs=self.parse_expr()?;
-                pos.add_span(1+rhs.position().span);
-                Ok(Expr::BinaryExpr(Op::Plus,Box::new(lhs),Box::new(rhs),pos))
-            }
-            Token::Minus=>{
-                self.token_stream.next();
-                let mut pos=lhs.position();
-                let rhs=self.parse_expr()?;
-                pos.add_span(1+rhs.position().span);
-                Ok(Expr::BinaryExpr(Op::Minus,Box::new(lhs),Box::new(rhs),pos))
-            }
-            _=>Ok(lhs)
+        let mut lhs=self.parse_term()?;
+        loop {
+            let op=match self.token_stream.peek().0 {
+                Token::Plus=>Op::Plus,
+                Token::Minus=>Op::Minus,
+                _=>return Ok(lhs)
+            };
+            self.token_stream.next();
+            let rhs=self.parse_term()?;
+            lhs=Self::binary_expr(op,lhs,rhs);
+        }
+    }
+    /// 区间运算：a..b、a until b、a downTo b以及range step n，until、downTo、step为中缀标识符而不是关键字
+    fn parse_range_expr(&mut self)->PipelineResult<Expr>{
+        let mut lhs=self.parse_math_expr()?;
+        loop {
+            let op=match self.token_stream.peek().0 {
+                Token::DotDot=>Op::RangeTo,
+                //换行后的until、downTo、step是下一条语句的开始
+                Token::Identifier(_) if !self.token_stream.peek_on_same_line()=>return Ok(lhs),
+                //后面不是操作数时(例如step("build"){...})按普通标识符处理
+                Token::Identifier(_) if !Self::is_operand_start(self.token_stream.peek_nth(1).0)=>return Ok(lhs),
+                Token::Identifier(s) if s=="until"=>Op::Until,
+                Token::Identifier(s) if s=="downTo"=>Op::DownTo,
+                Token::Identifier(s) if s=="step"=>Op::Step,
+                _=>return Ok(lhs)
+            };
+            self.token_stream.next();
+            let rhs=self.parse_math_expr()?;
+            lhs=Self::binary_expr(op,lhs,rhs);
+        }
+    }
+    /// token能否作为区间运算右侧操作数的开始
+    fn is_operand_start(token:Token)->bool{
+        matches!(token,Token::Int(_)|Token::Float(_)|Token::Identifier(_)|Token::Minus|Token::BraceLeft)
+    }
+    fn parse_elvis_expr(&mut self)->PipelineResult<Expr>{
+        let mut lhs=self.parse_range_expr()?;
+        while self.token_stream.peek().0==Token::Elvis{
+            self.token_stream.next();
+            let rhs=self.parse_range_expr()?;
+            lhs=Self::binary_expr(Op::Elvis,lhs,rhs);
+        }
+        Ok(lhs)
+    }
+    fn parse_comparison_expr(&mut self)->PipelineResult<Expr>{
+        let mut lhs=self.parse_elvis_expr()?;
+        loop {
+            let op=match self.token_stream.peek().0 {
+                Token::Greater=>Op::Greater,
+                Token::Less=>Op::Less,
+                Token::GreaterEqual=>Op::GreaterEqual,
+                Token::LessEqual=>Op::LessEqual,
+                Token::Equal=>Op::Equal,
+                Token::NotEqual=>Op::NotEqual,
+                _=>return Ok(lhs)
+            };
+            self.token_stream.next();
+            let rhs=self.parse_elvis_expr()?;
+            lhs=Self::binary_expr(op,lhs,rhs);
+        }
+    }
+    fn parse_and_expr(&mut self)->PipelineResult<Expr>{
+        let mut lhs=self.parse_comparison_expr()?;
+        while self.token_stream.peek().0==Token::And{
+            self.token_stream.next();
+            let rhs=self.parse_comparison_expr()?;
+            lhs=Self::binary_expr(Op::And,lhs,rhs);
         }
+        Ok(lhs)
+    }
+    fn parse_or_expr(&mut self)->PipelineResult<Expr>{
+        let mut lhs=self.parse_and_expr()?;
+        while self.token_stream.peek().0==Token::Or{
+            self.token_stream.next();
+            let rhs=self.parse_and_expr()?;
+            lhs=Self::binary_expr(Op::Or,lhs,rhs);
+        }
+        Ok(lhs)
     }
     fn parse_array(&mut self)->PipelineResult<Expr>{
         let (next,mut pos)=self.token_stream.next();
@@ -1043,47 +1250,19 @@ impl PipelineParser{
         }
         return Ok(Expr::Map(v,pos))
     }
+    /// 运算符优先级从低到高依次为：|| && 比较运算 ?: 区间运算 +- */% 一元运算，同级运算左结合
     pub fn parse_expr(&mut self)->PipelineResult<Expr>{
         let (peek,pos)=self.token_stream.peek();
-        if peek==Token::SquareBracketLeft{
-            return self.parse_array()
-        }
         if peek==Token::ParenthesisLeft{
-            return self.parse_map()
-        }
-        let lhs=self.parse_math_expr()?;
-        let next=self.token_stream.peek();
-        return match next.0 {
-            Token::Greater=>{
-                self.token_stream.next();
-                let mut pos=lhs.position();
-                let rhs=self.parse_math_expr()?;
-                pos.add_span(1+rhs.position().span);
-                Ok(Expr::BinaryExpr(Op::Greater,Box::new(lhs),Box::new(rhs),pos))
-            }
-            Token::Less=>{
-                self.token_stream.next();
-                let mut pos=lhs.position();
-                let rhs=self.parse_math_expr()?;
-                pos.add_span(1+rhs.position().span);
-                Ok(Expr::BinaryExpr(Op::Less,Box::new(lhs),Box::new(rhs),pos))
-            }
-            Token::Equal=>{
-                self.token_stream.next();
-                let mut pos=lhs.position();
-                let rhs=self.parse_math_expr()?;
-                pos.add_span(1+rhs.position().span);
-                Ok(Expr::BinaryExpr(Op::Equal,Box::new(lhs),Box::new(rhs),pos))
-            }
-            Token::NotEqual=>{
-                self.token_stream.next();
-                let mut pos=lhs.position();
-                let rhs=self.parse_math_expr()?;
-                pos.add_span(1+rhs.position().span);
-                Ok(Expr::BinaryExpr(Op::NotEqual,Box::new(lhs),Box::new(rhs),pos))
+            //{}和{key:value}为Map，其余为lambda
+            let is_map=self.token_stream.peek_nth(1).0==Token::ParenthesisRight
+                ||self.token_stream.peek_nth(2).0==Token::Colon;
+            if is_map&&self.lambda_header_len(1).is_none(){
+                return self.parse_map()
             }
-            _=>Ok(lhs)
+            return self.parse_lambda()
         }
+        self.parse_or_expr()
     }
     pub fn parse_special_token(&mut self,rhs: Token)->PipelineResult<(Token,Position)>{
         let (token,pos)=self.token_stream.next();
@@ -1097,7 +1276,7 @@ impl PipelineParser{
     }
     #[allow(unused)]
     pub fn from_token_stream(token_stream:TokenStream)->Self{
-        return Self{ token_stream,fn_lib:vec![],modules:vec![],classes:HashMap::new() }
+        return Self{ token_stream,fn_lib:vec![],modules:vec![],classes:HashMap::new(),in_condition:false }
     }
 
 }
@@ -1122,11 +1301,25 @@ pub struct FnDef{
     pub name:String,
     pub return_type:String,
     pub args:Vec<VariableDeclaration>,
-    pub body:Vec<Stmt>
+    pub body:Vec<Stmt>,
+    /// 定义在导入模块中的函数所在的模块文件，函数体中的位置相对于该文件
+    pub source:Option<Arc<SourceFile>>
 }
 
 impl FnDef {
     pub fn new(name:String,args:Vec<VariableDeclaration>,body:Vec<Stmt>,return_type:String)->Self{
-        Self{name,args,body,return_type}
+        Self{name,args,body,return_type,source:None}
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::engine::PipelineEngine;
+    use super::*;
+
+    #[test]
+    fn reports_class_declaration_errors_at_their_position(){
+        let e=PipelineEngine::default().run_script("class Foo(a Int)\nprintln(1)").unwrap_err();
+        assert!(matches!(e.kind(),PipelineError::UnexpectedToken(Token::Identifier(t)) if t=="Int"),"{e}");
+        assert_eq!(e.position().map(|p|p.pos),Some(12));
+    }
+}
@@ -50,7 +50,7 @@ impl Position {
         }
         // 由于循环在到达pos时退出，我们需要检查最后一个字符是否是换行符
         // 如果是，则实际的列数应该是换行符之前的列数
-        if let Some(&last_char) = source.get(self.pos - 1) {
+        if let Some(&last_char) = self.pos.checked_sub(1).and_then(|p|source.get(p)) {
             if last_char == '\n' {
                 col = 0;
             }
@@ -1,4 +1,4 @@
-use crate::v1::expr::{Expr, FnCallExpr};
+use crate::v1::expr::{Expr, FnCallExpr, Op};
 use crate::v1::position::{NONE, Position};
 
 #[derive(Debug,Clone)]
@@ -14,9 +14,18 @@ pub enum Stmt{
     /// 第二个Expr表示的是获取索引的表达式
     /// 第三个Expr表示的是对索引处的赋值
     IndexAssign(Box<Expr>,Box<Expr>,Box<Expr>,Position),
+    /// a+=b、a++等复合赋值，(赋值目标,运算符,右侧的值)。
+    /// 目标可以是变量、属性或下标，目标及其下标只求值一次
+    CompoundAssign(Box<(Expr,Op,Expr)>,Position),
     Break(Position),
     Continue(Position),
     Import(String,Position),
+    /// try{}catch(e){}finally{}
+    Try(Box<TryStmt>,Position),
+    /// throw(value)，抛出一个可以被catch捕获的错误
+    Throw(Box<Expr>,Position),
+    /// 单独作为语句的表达式，位于lambda末尾时其值作为lambda的返回值
+    Expr(Box<Expr>,Position),
     Noop
 }
 #[derive(Debug,Clone)]
@@ -55,7 +64,39 @@ impl IfBranchStmt {
         &self.body
     }
 }
+#[derive(Debug,Clone)]
+pub struct TryStmt{
+    body:Vec<Stmt>,
+    /// (错误变量名,catch块)
+    catch:Option<(String,Vec<Stmt>)>,
+    finally:Option<Vec<Stmt>>
+}
+
+impl TryStmt {
+    pub fn new(body:Vec<Stmt>,catch:Option<(String,Vec<Stmt>)>,finally:Option<Vec<Stmt>>)->Self{
+        Self{body,catch,finally}
+    }
+    pub fn get_body(&self)->&Vec<Stmt>{
+        &self.body
+    }
+    pub fn get_catch(&self)->Option<&(String,Vec<Stmt>)>{
+        self.catch.as_ref()
+    }
+    pub fn get_finally(&self)->Option<&Vec<Stmt>>{
+        self.finally.as_ref()
+    }
+}
 impl Stmt{
+    /// 位于lambda或if、when分支末尾的语句作为值使用时对应的表达式，
+    /// 函数调用、if和单独的表达式有值，其余语句没有
+    pub fn as_value_expr(&self)->Option<Expr>{
+        match self {
+            Stmt::Expr(e,_)=>Some((**e).clone()),
+            Stmt::If(if_stmt,pos)=>Some(Expr::If(if_stmt.clone(),pos.clone())),
+            Stmt::FnCall(call,pos)=>Some(Expr::FnCall((**call).clone(),pos.clone())),
+            _=>None
+        }
+    }
     pub fn is_noop(&self)->bool{
         return match self {
             Stmt::Noop => true,
@@ -85,9 +126,13 @@ impl Stmt{
             Stmt::IndexAssign(_,_,_,pos)=>{
                 pos.clone()
             }
+            Stmt::CompoundAssign(_,pos)=>pos.clone(),
             Stmt::Import(_,pos)=>{
                 pos.clone()
             }
+            Stmt::Try(_,pos)=>pos.clone(),
+            Stmt::Throw(_,pos)=>pos.clone(),
+            Stmt::Expr(_,pos)=>pos.clone(),
             Stmt::Noop => {
                 NONE.clone()
             }
@@ -2,6 +2,8 @@
 #[derive(Debug, Clone,PartialEq)]
 pub enum  Token{
     String(String),
+    /// 包含$var或${expr}插值的字符串
+    TemplateString(Vec<TemplatePart>),
     Int(i64),
     Float(f64),
     Identifier(String),
@@ -21,6 +23,8 @@ pub enum  Token{
     ParenthesisRight,
     /// .
     Dot,
+    /// ..
+    DotDot,
     /// :
     Colon,
     //::
@@ -47,9 +51,48 @@ pub enum  Token{
     Equal,
     /// !=
     NotEqual,
+    /// >=
+    GreaterEqual,
+    /// <=
+    LessEqual,
+    /// &&
+    And,
+    /// ||
+    Or,
+    /// !
+    Not,
+    /// ->
+    Arrow,
+    /// ?
+    Question,
+    /// ?.
+    SafeDot,
+    /// ?:
+    Elvis,
+    /// +=
+    PlusAssign,
+    /// -=
+    MinusAssign,
+    /// *=
+    MulAssign,
+    /// /=
+    DivAssign,
+    /// ++
+    Increment,
+    /// --
+    Decrement,
+    /// 无法识别的字面量(如未结束的字符串、错误的转义)，携带错误描述，解析时作为错误报告
+    Invalid(String),
     EOF
 }
 
+/// 插值字符串的组成部分
+#[derive(Debug, Clone,PartialEq)]
+pub enum TemplatePart{
+    Literal(String),
+    /// 插值表达式的源码及其在脚本中的起始位置
+    Expr(String,usize)
+}
 impl Token {
     pub fn token_id(&self)->i8{
         match self {
@@ -78,7 +121,25 @@ impl Token {
             Token::Div=>22,
             Token::Mod=>23,
             Token::ScopeSymbol=>24,
-            Token::NotEqual=>25
+            Token::NotEqual=>25,
+            Token::GreaterEqual=>26,
+            Token::LessEqual=>27,
+            Token::And=>28,
+            Token::Or=>29,
+            Token::Not=>30,
+            Token::TemplateString(_)=>31,
+            Token::Arrow=>32,
+            Token::Question=>33,
+            Token::DotDot=>34,
+            Token::SafeDot=>35,
+            Token::Elvis=>36,
+            Token::PlusAssign=>37,
+            Token::MinusAssign=>38,
+            Token::MulAssign=>39,
+            Token::DivAssign=>40,
+            Token::Increment=>41,
+            Token::Decrement=>42,
+            Token::Invalid(_)=>43
         }
     }
     pub fn get_identifier_value(&self)->&str{
@@ -5,11 +5,13 @@ use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::{Add, Div, Mul, Rem, Sub};
 use std::sync::{Arc, RwLock, RwLockWriteGuard, Weak};
-use crate::context::{Context, PipelineContextValue};
+use indexmap::IndexMap;
+use crate::context::{Context, PipelineContextValue, Scope};
 use crate::engine::{PipelineEngine};
-use crate::error::PipelineResult;
+use crate::error::{PipelineError, PipelineResult};
 use crate::v1::expr::{Expr, FnCallExpr, Op, StructExpr};
 use crate::v1::parser::FnDef;
+use crate::check::{argument_target, check_type};
 #[derive(Debug,Clone)]
 pub enum Dynamic{
     Unit,
@@ -20,10 +22,63 @@ pub enum Dynamic{
     Variable(String),
     FnPtr(Box<FnPtr>),
     Array(Vec<Value>),
-    Map(HashMap<Dynamic,Value>),
+    /// 保持键的插入顺序
+    Map(IndexMap<Dynamic,Value>),
     Struct(Box<Struct>),
+    Range(Range),
     Native(Arc<RwLock<dyn Any+Send+Sync>>)
 }
+/// 整数区间，只保存起止和步长，迭代时才逐个生成元素。end总是包含在区间内，step为负时递减
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Range{
+    start:i64,
+    end:i64,
+    step:i64
+}
+impl Range {
+    /// start..end，包含end
+    pub fn closed(start:i64,end:i64)->Self{
+        Self{start,end,step:1}
+    }
+    /// start until end，不包含end
+    pub fn until(start:i64,end:i64)->Self{
+        Self{start,end:end.saturating_sub(1),step:1}
+    }
+    /// start downTo end，从start递减到end
+    pub fn down_to(start:i64,end:i64)->Self{
+        Self{start,end,step:-1}
+    }
+    /// 修改步长，step必须为正数，方向保持不变
+    pub fn with_step(self,step:i64)->PipelineResult<Self>{
+        if step<=0{
+            return Err(PipelineError::InvalidArgument(format!("step must be positive, but got {step}")))
+        }
+        Ok(Self{step:step*self.step.signum(),..self})
+    }
+    pub fn iter(&self)->impl Iterator<Item=i64>{
+        let Range{start,end,step}=*self;
+        std::iter::successors(Some(start),move|i|i.checked_add(step))
+            .take_while(move|i|if step>0{*i<=end}else{*i>=end})
+    }
+    pub fn contains(&self,i:i64)->bool{
+        let in_bounds=if self.step>0{self.start<=i&&i<=self.end}else{self.end<=i&&i<=self.start};
+        //使用i128计算，避免区间跨度超过i64范围时溢出
+        in_bounds&&(i as i128-self.start as i128)%self.step as i128==0
+    }
+}
+impl Display for Range {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.step>0{
+            write!(f,"{}..{}",self.start,self.end)?;
+        }else{
+            write!(f,"{} downTo {}",self.start,self.end)?;
+        }
+        if self.step.abs()!=1{
+            write!(f," step {}",self.step.abs())?;
+        }
+        Ok(())
+    }
+}
 #[derive(Debug,Clone)]
 pub struct Struct{
     name:String,
@@ -81,6 +136,7 @@ impl Value {
         }
     }
 
+    /// 取出值的副本，Signal以及已经释放的引用视为Unit
     pub fn as_dynamic(&self)->Dynamic{
         match self {
             Value::Immutable(d) => {d.clone()}
@@ -89,43 +145,47 @@ impl Value {
                 return r.clone()
             }
             Value::Refer(r)=>{
-                r.upgrade().unwrap().read().unwrap().clone()
+                match r.upgrade() {
+                    None => Dynamic::Unit,
+                    Some(r) => r.read().unwrap().clone()
+                }
             }
-            _=>panic!("signal cannot as dynamic")
+            Value::Signal(_)=>Dynamic::Unit
         }
     }
-    pub fn get_mut_arc(&self)->Arc<RwLock<Dynamic>>{
+    /// 取出可修改的值，target为出错时提示的赋值目标
+    pub fn get_mut_arc(&self,target:&str)->PipelineResult<Arc<RwLock<Dynamic>>>{
         match self {
-            Value::Immutable(d) => {panic!("can not be mutable")}
             Value::Mutable( d) => {
-                d.clone()
+                Ok(d.clone())
             }
             Value::Refer(r)=>{
-               r.upgrade().unwrap()
+               r.upgrade().ok_or(PipelineError::NotMutable(target.into()))
             }
-            _=>panic!("signal cannot as mut arc")
+            _=>Err(PipelineError::NotMutable(target.into()))
         }
     }
     pub fn as_arc(&self)->Arc<RwLock<Dynamic>>{
         match self {
-            Value::Immutable(d) => {Arc::new(RwLock::new(d.clone()))}
             Value::Mutable( d) => {
                d.clone()
             }
             Value::Refer(r)=>{
-                r.upgrade().unwrap()
+                match r.upgrade() {
+                    None => Arc::new(RwLock::new(Dynamic::Unit)),
+                    Some(r) => r
+                }
             }
-            _=>panic!("signal cannot as arc")
+            _=>Arc::new(RwLock::new(self.as_dynamic()))
         }
     }
-    pub fn as_weak(&self)->Weak<RwLock<Dynamic>>{
+    pub fn as_weak(&self,target:&str)->PipelineResult<Weak<RwLock<Dynamic>>>{
         match self {
-            Value::Immutable(d) => {panic!("can not be weak pointer")}
             Value::Mutable( d) => {
-                Arc::downgrade(d)
+                Ok(Arc::downgrade(d))
             }
-            Value::Refer(r)=>r.clone(),
-            _=>panic!("signal cannot as weak")
+            Value::Refer(r)=>Ok(r.clone()),
+            _=>Err(PipelineError::NotMutable(target.into()))
         }
     }
 }
@@ -169,27 +229,35 @@ impl From<bool> for Value {
     }
 }
 
-impl From<Value> for String {
-    fn from(value: Value) -> Self {
-        value.as_string().unwrap()
+impl TryFrom<Value> for String {
+    type Error = PipelineError;
+
+    fn try_from(value: Value) -> PipelineResult<Self> {
+        value.as_string().ok_or(PipelineError::ExpectedType("String".into()))
     }
 }
 
-impl From<Value> for i64 {
-    fn from(value: Value) -> Self {
-        value.as_integer().unwrap()
+impl TryFrom<Value> for i64 {
+    type Error = PipelineError;
+
+    fn try_from(value: Value) -> PipelineResult<Self> {
+        value.as_integer().ok_or(PipelineError::ExpectedType("Int".into()))
     }
 }
 
-impl From<Value> for f64 {
-    fn from(value: Value) -> Self {
-        value.as_float().unwrap()
+impl TryFrom<Value> for f64 {
+    type Error = PipelineError;
+
+    fn try_from(value: Value) -> PipelineResult<Self> {
+        value.as_float().ok_or(PipelineError::ExpectedType("Float".into()))
     }
 }
 
-impl From<Value> for bool {
-    fn from(value: Value) -> Self {
-        value.as_bool().unwrap()
+impl TryFrom<Value> for bool {
+    type Error = PipelineError;
+
+    fn try_from(value: Value) -> PipelineResult<Self> {
+        value.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))
     }
 }
 impl Struct {
@@ -201,13 +269,18 @@ impl Struct {
     pub fn get_prop(&self,name:&str)->Option<Value>{
         self.props.get(name).map(|e|e.clone())
     }
+    pub fn get_props(&self)->&HashMap<String,Value>{
+        &self.props
+    }
 }
 #[derive(Debug,Clone)]
 pub struct FnPtr{
     pub name:String,
     pub params:Vec<Expr>,
     is_defer:bool,
-    pub fn_def:Option<FnDef>
+    pub fn_def:Option<FnDef>,
+    /// lambda定义时所在的作用域
+    pub captured:Option<Arc<RwLock<Scope>>>
 }
 
 impl FnPtr {
@@ -216,7 +289,8 @@ impl FnPtr {
             name:name.into(),
             params:vec![],
             fn_def:None,
-            is_defer:false
+            is_defer:false,
+            captured:None
         }
     }
     pub fn is_defer(&self)->bool{
@@ -231,21 +305,68 @@ impl FnPtr {
     pub fn set_fn_def(&mut self,fn_def:&FnDef){
         self.fn_def=Some(fn_def.clone())
     }
+    pub fn capture(&mut self,scope:Arc<RwLock<Scope>>){
+        self.captured=Some(scope)
+    }
     pub  fn call(&mut self, engine:&mut PipelineEngine, ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>)->PipelineResult<Value>{
         let fn_def= self.fn_def.clone();
         match fn_def {
             None => {
-                let expr=FnCallExpr{ name: self.name.clone(), args: self.params.clone() };
+                let expr=FnCallExpr{ name: self.name.clone(), args: self.params.clone(), method:false };
                 engine.eval_fn_call_expr_from_ast(ctx,expr)
             },
-            Some(f) => {
-                let blocks=f.body;
-                engine.eval_stmt_blocks_from_ast_with_context(ctx,blocks)
+            Some(_) => {
+                self.call_with_scope(engine,ctx,Scope::new())
             }
         }
 
     }
 }
+impl FnPtr {
+    /// 以参数调用lambda，没有声明参数时通过it访问参数(多个参数时it为参数组成的Array)
+    pub fn call_with_args(&self,engine:&mut PipelineEngine,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,args:Vec<Value>)->PipelineResult<Value>{
+        let fn_def=self.fn_def.as_ref().ok_or(PipelineError::ExpectedType("Closure".into()))?;
+        let mut scope=Scope::new();
+        if fn_def.args.is_empty(){
+            match args.len() {
+                0=>{}
+                1=>scope.set("it",args[0].clone()),
+                _=>scope.set("it",Value::with_mutable(Dynamic::Array(args)))
+            }
+        }else{
+            if fn_def.args.len()!=args.len(){
+                return Err(PipelineError::ArityMismatch("lambda".into(),fn_def.args.len(),args.len()))
+            }
+            for (a,v) in fn_def.args.iter().zip(args){
+                check_type(&v,&a.declaration_type,||argument_target(&a.name,"lambda"))?;
+                scope.set(a.name.as_str(),v);
+            }
+        }
+        self.call_with_scope(engine,ctx,scope)
+    }
+    /// 在scope中执行lambda，scope的上级作用域为lambda定义时所在的作用域，
+    /// 原生函数可以通过scope向lambda中注入变量。函数体的最后一个语句是表达式时，其值作为返回值
+    pub fn call_with_scope(&self,engine:&mut PipelineEngine,ctx:Arc<RwLock<dyn Context<PipelineContextValue>>>,mut scope:Scope)->PipelineResult<Value>{
+        let fn_def=self.fn_def.as_ref().ok_or(PipelineError::ExpectedType("Closure".into()))?;
+        let parent=match &self.captured {
+            None => PipelineEngine::context_with_scope(&ctx),
+            Some(s) => s.clone()
+        };
+        scope.set_parent(parent);
+        let ctx=PipelineEngine::with_scope(ctx,scope);
+        let mut body=fn_def.body.clone();
+        //最后的表达式、函数调用或者if语句的值作为返回值
+        let last=body.last().and_then(|s|s.as_value_expr());
+        if last.is_some(){
+            body.pop();
+        }
+        let r=engine.eval_stmt_blocks_from_ast_with_context(ctx.clone(),body)?;
+        if let (Value::Immutable(Dynamic::Unit),Some(last))=(&r,last){
+            return engine.eval_expr_from_ast_with_context(ctx,last)
+        }
+        Ok(r)
+    }
+}
 impl From<&str> for Dynamic{
     fn from(value: &str) -> Self {
         Dynamic::String(String::from(value))
@@ -257,24 +378,32 @@ impl From<String> for Dynamic{
     }
 }
 
-impl From<Dynamic> for String {
-    fn from(value: Dynamic) -> Self {
-        value.as_string().unwrap()
+impl TryFrom<Dynamic> for String {
+    type Error = PipelineError;
+
+    fn try_from(value: Dynamic) -> PipelineResult<Self> {
+        value.as_string().ok_or(PipelineError::ExpectedType("String".into()))
     }
 }
-impl From<Dynamic> for bool {
-    fn from(value: Dynamic) -> Self {
-        value.as_bool().unwrap()
+impl TryFrom<Dynamic> for bool {
+    type Error = PipelineError;
+
+    fn try_from(value: Dynamic) -> PipelineResult<Self> {
+        value.as_bool().ok_or(PipelineError::ExpectedType("Bool".into()))
     }
 }
-impl From<Dynamic> for i64 {
-    fn from(value: Dynamic) -> Self {
-        value.as_integer().unwrap()
+impl TryFrom<Dynamic> for i64 {
+    type Error = PipelineError;
+
+    fn try_from(value: Dynamic) -> PipelineResult<Self> {
+        value.as_integer().ok_or(PipelineError::ExpectedType("Int".into()))
     }
 }
-impl From<Dynamic> for f64 {
-    fn from(value: Dynamic) -> Self {
-        value.as_float().unwrap()
+impl TryFrom<Dynamic> for f64 {
+    type Error = PipelineError;
+
+    fn try_from(value: Dynamic) -> PipelineResult<Self> {
+        value.as_float().ok_or(PipelineError::ExpectedType("Float".into()))
     }
 }
 impl From<i64> for Dynamic{
@@ -331,6 +460,7 @@ impl Display for Dynamic {
                 }
                 write!(f,"}}")
             }
+            Dynamic::Range(r)=>write!(f,"{r}"),
             Dynamic::Native(v)=>{
                 write!(f,"Native Value")
             }
@@ -350,7 +480,10 @@ impl Hash for Dynamic{
                 i.hash(state)
             }
             Dynamic::Float(f) => {
-
+                //与之相等的整数保持相同的hash
+                if f.fract()==0.0{
+                    (*f as i64).hash(state)
+                }
             }
             Dynamic::String(s) => {
                 s.hash(state)
@@ -362,149 +495,109 @@ impl Hash for Dynamic{
         }
     }
 }
-impl Mul for Dynamic{
-    type Output = Dynamic;
-
-    fn mul(self, rhs: Self) -> Self::Output {
-        match self {
-            Dynamic::Integer(i) => {
-                match rhs {
-                    Dynamic::Integer(r) => {
-                        Dynamic::Integer(i*r)
-                    }
-                    Dynamic::Float(r) => {
-                        Dynamic::Float(i as f64*r)
-                    }
-                    t=>panic!("Integer can not mul {}",t.type_name())
-                }
-
+impl Dynamic{
+    /// 数值运算，Int与Int的运算结果为Int，超出Int的范围时报错；有Float参与时结果为Float
+    fn arithmetic(self,rhs:Self,op:&str,int:fn(i64,i64)->Option<i64>,float:fn(f64,f64)->f64)->PipelineResult<Dynamic>{
+        match (&self,&rhs) {
+            (Dynamic::Integer(l),Dynamic::Integer(r))=>{
+                int(*l,*r).map(Dynamic::Integer).ok_or(PipelineError::IntegerOverflow(format!("{l} {op} {r}")))
             }
-            Dynamic::Float(f) => {
-                let t=rhs.as_float().unwrap();
-                Dynamic::Float(f*t)
+            (Dynamic::Integer(_)|Dynamic::Float(_),Dynamic::Integer(_)|Dynamic::Float(_))=>{
+                Ok(Dynamic::Float(float(self.convert_float().unwrap(),rhs.convert_float().unwrap())))
             }
-            _=>panic!("不能进行相乘操作")
+            _=>Err(PipelineError::UndefinedOperation(format!("{} {op} {}",self.type_name(),rhs.type_name())))
         }
     }
+    /// 比较两个值，只有数值之间以及String之间可以比较
+    pub fn compare(&self,rhs:&Self)->PipelineResult<Ordering>{
+        self.partial_cmp(rhs)
+            .ok_or(PipelineError::UndefinedOperation(format!("compare {} with {}",self.type_name(),rhs.type_name())))
+    }
+}
+impl Mul for Dynamic{
+    type Output = PipelineResult<Dynamic>;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.arithmetic(rhs,"*",i64::checked_mul,|l,r|l*r)
+    }
 }
 
 impl Div for Dynamic {
-    type Output = Dynamic;
+    type Output = PipelineResult<Dynamic>;
 
     fn div(self, rhs: Self) -> Self::Output {
-        match self {
-            Dynamic::Integer(i) => {
-                match rhs {
-                    Dynamic::Integer(r) => {
-                        Dynamic::Integer(i/r)
-                    }
-                    Dynamic::Float(r) => {
-                        Dynamic::Float(i as f64/r)
-                    }
-                    t=>panic!("Integer can not div {}",t.type_name())
-                }
-            }
-            Dynamic::Float(f) => {
-                match rhs {
-                    Dynamic::Integer(r) => {
-                        Dynamic::Float(f/r as f64)
-                    }
-                    Dynamic::Float(r) => {
-                        Dynamic::Float(f/r)
-                    }
-                    t=>panic!("Float can not div {}",t.type_name())
-                }
-            }
-            _=>panic!("不能进行相乘操作")
+        if let (Dynamic::Integer(_),Dynamic::Integer(0))=(&self,&rhs){
+            return Err(PipelineError::DivisionByZero)
         }
+        self.arithmetic(rhs,"/",i64::checked_div,|l,r|l/r)
     }
 }
 
 impl Rem for Dynamic {
-    type Output = Dynamic;
+    type Output = PipelineResult<Dynamic>;
 
     fn rem(self, rhs: Self) -> Self::Output {
-        match self {
-            Dynamic::Integer(i) => {
-                let r=rhs.as_integer().unwrap();
-                Dynamic::Integer(i%r)
-            }
-            Dynamic::Float(f) => {
-                let t=rhs.as_float().unwrap();
-                Dynamic::Float(f%t)
-            }
-            _=>panic!("不能进行相乘操作")
+        if let (Dynamic::Integer(_),Dynamic::Integer(0))=(&self,&rhs){
+            return Err(PipelineError::DivisionByZero)
         }
+        self.arithmetic(rhs,"%",i64::checked_rem,|l,r|l%r)
     }
 }
 impl PartialEq<Self> for Dynamic {
+    /// 类型不同的值不相等，Int与Float按数值比较
     fn eq(&self, rhs: &Self) -> bool {
-        match self {
-            Dynamic::Integer(i) => {
-                let r=rhs.as_integer().unwrap();
-                i.eq(&r)
+        match (self,rhs) {
+            (Dynamic::Unit,Dynamic::Unit)=>true,
+            (Dynamic::Integer(l),Dynamic::Integer(r))=>l==r,
+            (Dynamic::Integer(_)|Dynamic::Float(_),Dynamic::Integer(_)|Dynamic::Float(_))=>{
+                self.convert_float()==rhs.convert_float()
             }
-            Dynamic::Float(f) => {
-                let t=rhs.as_float().unwrap();
-                f.eq(&t)
+            (Dynamic::String(l),Dynamic::String(r))=>l==r,
+            (Dynamic::Boolean(l),Dynamic::Boolean(r))=>l==r,
+            (Dynamic::Array(l),Dynamic::Array(r))=>{
+                l.len()==r.len()&&l.iter().zip(r).all(|(l,r)|l.as_dynamic()==r.as_dynamic())
+            }
+            (Dynamic::Map(l),Dynamic::Map(r))=>{
+                l.len()==r.len()&&l.iter().all(|(k,v)|r.get(k).is_some_and(|r|v.as_dynamic()==r.as_dynamic()))
             }
-            Dynamic::String(s)=>{
-                let o=rhs.as_string().unwrap();
-                s.eq(&o)
+            (Dynamic::Range(l),Dynamic::Range(r))=>l==r,
+            (Dynamic::Struct(l),Dynamic::Struct(r))=>{
+                l.name==r.name&&l.props.iter().all(|(k,v)|r.props.get(k).is_some_and(|r|v.as_dynamic()==r.as_dynamic()))
             }
-            _=>panic!("不能进行相等操作")
+            _=>false
         }
     }
 }
 
 impl PartialOrd for Dynamic {
     fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
-        match self {
-            Dynamic::Integer(i) => {
-                let r=rhs.as_integer().unwrap();
-                i.partial_cmp(&r)
-            }
-            Dynamic::Float(f) => {
-                let t=rhs.as_float().unwrap();
-                f.partial_cmp(&t)
+        match (self,rhs) {
+            (Dynamic::Integer(l),Dynamic::Integer(r))=>l.partial_cmp(r),
+            (Dynamic::Integer(_)|Dynamic::Float(_),Dynamic::Integer(_)|Dynamic::Float(_))=>{
+                self.convert_float()?.partial_cmp(&rhs.convert_float()?)
             }
-            _=>panic!("不能进行比较操作")
+            (Dynamic::String(l),Dynamic::String(r))=>l.partial_cmp(r),
+            _=>None
         }
     }
 }
 impl Add for Dynamic{
-    type Output = Dynamic;
+    type Output = PipelineResult<Dynamic>;
 
+    /// String与任意值相加时拼接为字符串
     fn add(self, rhs: Self) -> Self::Output {
-        match self {
-            Dynamic::Integer(i) => {
-                let r=rhs.as_integer().unwrap();
-                Dynamic::Integer(i+r)
-            }
-            Dynamic::Float(f) => {
-                let t=rhs.as_float().unwrap();
-                Dynamic::Float(f+t)
-            }
-            _=>panic!("不能进行相加操作")
+        if let Dynamic::String(l)=&self{
+            return Ok(Dynamic::String(format!("{l}{rhs}")))
         }
+        self.arithmetic(rhs,"+",i64::checked_add,|l,r|l+r)
     }
 }
 
 impl Sub for Dynamic {
-    type Output = Dynamic;
+    type Output = PipelineResult<Dynamic>;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        match self {
-            Dynamic::Integer(i) => {
-                let r=rhs.as_integer().unwrap();
-                Dynamic::Integer(i-r)
-            }
-            Dynamic::Float(f) => {
-                let t=rhs.as_float().unwrap();
-                Dynamic::Float(f-t)
-            }
-            _=>panic!("不能进行相加操作")
-        }
+        self.arithmetic(rhs,"-",i64::checked_sub,|l,r|l-r)
     }
 }
 impl Dynamic{
@@ -537,6 +630,9 @@ impl Dynamic{
             Dynamic::Map(_) => {
                 "Map".into()
             }
+            Dynamic::Range(_) => {
+                "Range".into()
+            }
             Dynamic::Native(_) => {
                 "Native".into()
             }
@@ -637,7 +733,7 @@ impl Dynamic{
             _=>None
         }
     }
-    pub fn as_mut_map(&mut self)->Option<&mut HashMap<Dynamic,Value>>{
+    pub fn as_mut_map(&mut self)->Option<&mut IndexMap<Dynamic,Value>>{
         match self {
             Dynamic::Map( i)=>Some(i),
             _=>None
@@ -649,6 +745,12 @@ impl Dynamic{
             _=>None
         }
     }
+    pub fn as_range(&self)->Option<Range>{
+        match self {
+            Dynamic::Range(r)=>Some(*r),
+            _=>None
+        }
+    }
     pub fn as_native(&self)->Option<Arc<RwLock<dyn Any+Send+Sync>>>{
         match self {
             Dynamic::Native(a)=>Some(a.clone()),
@@ -662,4 +764,76 @@ impl Dynamic{
             _=>None
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 执行script后求值expr，以字符串形式返回结果
+    fn eval(script:&str,expr:&str)->String{
+        let mut engine=PipelineEngine::default();
+        let ctx=PipelineEngine::background();
+        for stmt in engine.compile_stmt_blocks(script).unwrap(){
+            engine.eval_stmt_from_ast_with_context(ctx.clone(),stmt).unwrap();
+        }
+        let expr=engine.compile_expr(expr).unwrap();
+        engine.eval_expr_from_ast_with_context(ctx,expr).unwrap().as_dynamic().to_string()
+    }
+
+    #[test]
+    fn lambda_returns_trailing_expression(){
+        assert_eq!(eval("let f={ x -> x+1 }","f(1)"),"2");
+        assert_eq!(eval("let f={ x -> let y=x*2\n y+1 }","f(3)"),"7");
+    }
+    #[test]
+    fn lambda_returns_trailing_call(){
+        assert_eq!(eval("let f={ x -> x.uppercase() }","f(\"hi\")"),"HI");
+        assert_eq!(eval("","[\"a\",\"b\"].map{ it.uppercase() }"),"[A,B]");
+    }
+    #[test]
+    fn lambda_returns_trailing_if(){
+        assert_eq!(eval("let f={ x -> if(x>1){ \"big\" }else{ \"small\" } }","f(2)"),"big");
+        assert_eq!(eval("let f={ x -> if(x>1){ \"big\" }else{ \"small\" } }","f(0)"),"small");
+    }
+    #[test]
+    fn integer_arithmetic_reports_overflow(){
+        let overflow=|r:PipelineResult<Dynamic>|matches!(r,Err(PipelineError::IntegerOverflow(_)));
+        assert!(overflow(Dynamic::Integer(i64::MAX)+Dynamic::Integer(1)));
+        assert!(overflow(Dynamic::Integer(i64::MIN)-Dynamic::Integer(1)));
+        assert!(overflow(Dynamic::Integer(i64::MAX)*Dynamic::Integer(2)));
+        assert!(overflow(Dynamic::Integer(i64::MIN)/Dynamic::Integer(-1)));
+        assert!(overflow(Dynamic::Integer(i64::MIN)%Dynamic::Integer(-1)));
+        assert_eq!((Dynamic::Integer(i64::MAX)+Dynamic::Float(1.0)).unwrap(),Dynamic::Float(i64::MAX as f64+1.0));
+        assert_eq!((Dynamic::Integer(7)%Dynamic::Integer(-3)).unwrap(),Dynamic::Integer(1));
+    }
+    #[test]
+    fn range_iterates_in_both_directions(){
+        assert_eq!(Range::closed(1,4).iter().collect::<Vec<_>>(),vec![1,2,3,4]);
+        assert_eq!(Range::until(0,10).with_step(3).unwrap().iter().collect::<Vec<_>>(),vec![0,3,6,9]);
+        assert_eq!(Range::down_to(5,0).with_step(2).unwrap().iter().collect::<Vec<_>>(),vec![5,3,1]);
+        assert_eq!(Range::until(0,0).iter().count(),0);
+        assert!(Range::closed(0,1).with_step(0).is_err());
+    }
+    #[test]
+    fn range_iteration_stops_at_integer_bounds(){
+        assert_eq!(Range::closed(i64::MAX-1,i64::MAX).iter().count(),2);
+        assert_eq!(Range::down_to(i64::MIN+1,i64::MIN).iter().count(),2);
+    }
+    #[test]
+    fn range_contains_respects_step(){
+        let r=Range::closed(1,10).with_step(3).unwrap();
+        assert!(r.contains(7));
+        assert!(!r.contains(8));
+        assert!(!r.contains(13));
+        let r=Range::down_to(10,0).with_step(4).unwrap();
+        assert!(r.contains(2));
+        assert!(!r.contains(3));
+        assert!(Range::closed(i64::MIN,i64::MAX).with_step(2).unwrap().contains(i64::MAX-1));
+    }
+    #[test]
+    fn range_expressions(){
+        assert_eq!(eval("","0 until 10 step 3"),"0..9 step 3");
+        assert_eq!(eval("","10 downTo 1"),"10 downTo 1");
+        assert_eq!(eval("fn step(n:Int){ return n*10 }","step(2)"),"20");
+    }
+}